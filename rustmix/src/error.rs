@@ -49,4 +49,14 @@ pub enum RmxError {
 
     #[error("Application exited with error {0}")]
     ExitCode(i32),
+
+    #[error(
+        "Record error at line {line}{}. {message}",
+        .field.as_ref().map(|it| format!(", field '{}'", it)).unwrap_or_default()
+    )]
+    Record {
+        line: u64,
+        field: Option<String>,
+        message: String,
+    },
 }
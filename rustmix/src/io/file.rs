@@ -1,16 +1,47 @@
-use csv::{ReaderBuilder, WriterBuilder};
+use csv::{ByteRecord, ReaderBuilder, StringRecord, WriterBuilder};
+use futures::{
+    future::Future,
+    stream::{self, BoxStream, Stream, StreamExt},
+};
 use serde::{de, Serialize};
 use serde_json;
 use std::{
-    fs::{self, OpenOptions},
-    io::{BufRead, BufReader, Write},
+    borrow::Cow,
+    fs::{self, OpenOptions, TryLockError},
+    io::{self as sio, BufRead, BufReader, BufWriter, Read, Seek, SeekFrom, Write},
     path::Path,
+    pin::pin,
+    process,
+    sync::atomic::{AtomicU64, Ordering},
+    thread,
+    time::{Duration, Instant},
+};
+use tokio::{
+    io::{self as aio, AsyncBufReadExt, AsyncReadExt, AsyncWriteExt},
+    sync::mpsc,
+    task,
 };
 
 use super::directory;
-use crate::Result;
+use crate::{error::RmxError, Result};
+
+pub use csv::{QuoteStyle, Trim};
 
 const LINES_BUFFER_DEFAULT: usize = 1000;
+const RECORDS_BUFFER_DEFAULT: usize = 100;
+const LOCK_POLL_MAX: Duration = Duration::from_millis(100);
+
+const DETECT_SAMPLE_MAX: usize = 4096;
+// Windows-1252 characters for the bytes 0x80 to 0x9F, the rest match Latin-1. The 5 bytes it
+// leaves undefined map to the C1 controls like browsers do.
+const WINDOWS_1252: [char; 32] = [
+    '\u{20AC}', '\u{81}', '\u{201A}', '\u{0192}', '\u{201E}', '\u{2026}', '\u{2020}', '\u{2021}',
+    '\u{02C6}', '\u{2030}', '\u{0160}', '\u{2039}', '\u{0152}', '\u{8D}', '\u{017D}', '\u{8F}',
+    '\u{90}', '\u{2018}', '\u{2019}', '\u{201C}', '\u{201D}', '\u{2022}', '\u{2013}', '\u{2014}',
+    '\u{02DC}', '\u{2122}', '\u{0161}', '\u{203A}', '\u{0153}', '\u{9D}', '\u{017E}', '\u{0178}',
+];
+
+static TEMP_COUNTER: AtomicU64 = AtomicU64::new(0);
 
 #[derive(Default, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
 pub enum FileOpenOptions {
@@ -21,6 +52,592 @@ pub enum FileOpenOptions {
     Append,
 }
 
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum LockMode {
+    #[default]
+    Shared,
+    Exclusive,
+}
+
+// Advisory lock on a file, released when dropped. It only keeps out the ones that lock the file
+// too, plain reads and writes are not blocked.
+#[derive(Debug)]
+pub struct FileLock<'a> {
+    file: &'a std::fs::File,
+    mode: LockMode,
+}
+
+impl Drop for FileLock<'_> {
+    fn drop(&mut self) {
+        self.file.unlock().ok();
+    }
+}
+
+impl FileLock<'_> {
+    pub fn mode(&self) -> LockMode {
+        self.mode
+    }
+
+    pub fn unlock(self) -> Result<()> {
+        let result = self.file.unlock();
+        std::mem::forget(self);
+        result.map_err(Into::into)
+    }
+}
+
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum TextEncoding {
+    #[default]
+    Utf8,
+    // Invalid sequences become U+FFFD instead of failing the record.
+    Utf8Lossy,
+    Latin1,
+    Windows1252,
+    Utf16Le,
+    Utf16Be,
+}
+
+impl TextEncoding {
+    pub fn name(&self) -> &'static str {
+        match self {
+            TextEncoding::Utf8 | TextEncoding::Utf8Lossy => "UTF-8",
+            TextEncoding::Latin1 => "ISO-8859-1",
+            TextEncoding::Windows1252 => "windows-1252",
+            TextEncoding::Utf16Le => "UTF-16LE",
+            TextEncoding::Utf16Be => "UTF-16BE",
+        }
+    }
+
+    pub fn is_utf16(&self) -> bool {
+        matches!(self, TextEncoding::Utf16Le | TextEncoding::Utf16Be)
+    }
+
+    pub fn bom(&self) -> &'static [u8] {
+        match self {
+            TextEncoding::Utf8 | TextEncoding::Utf8Lossy => &[0xEF, 0xBB, 0xBF],
+            TextEncoding::Utf16Le => &[0xFF, 0xFE],
+            TextEncoding::Utf16Be => &[0xFE, 0xFF],
+            _ => &[],
+        }
+    }
+
+    pub fn from_bom(bytes: &[u8]) -> Option<TextEncoding> {
+        [
+            TextEncoding::Utf8,
+            TextEncoding::Utf16Le,
+            TextEncoding::Utf16Be,
+        ]
+        .into_iter()
+        .find(|it| bytes.starts_with(it.bom()))
+    }
+
+    // Guesses from the start of the content. A byte order mark wins, then NULs in every other byte
+    // mean UTF-16, and anything that is not valid UTF-8 is taken as Windows-1252. Only the first
+    // DETECT_SAMPLE_MAX bytes are looked at, TextLines switches a detected UTF-8 to Windows-1252
+    // at the first line further down that is not valid UTF-8.
+    pub fn detect(bytes: &[u8]) -> TextEncoding {
+        if let Some(encoding) = Self::from_bom(bytes) {
+            return encoding;
+        }
+
+        let sample = &bytes[..bytes.len().min(DETECT_SAMPLE_MAX)];
+        let units = sample.len() / 2;
+        let even = sample.iter().step_by(2).filter(|&&it| it == 0).count();
+        let odd = sample
+            .iter()
+            .skip(1)
+            .step_by(2)
+            .filter(|&&it| it == 0)
+            .count();
+
+        if units > 0 && odd * 4 > units && even * 4 < odd {
+            return TextEncoding::Utf16Le;
+        }
+
+        if units > 0 && even * 4 > units && odd * 4 < even {
+            return TextEncoding::Utf16Be;
+        }
+
+        match std::str::from_utf8(sample) {
+            Ok(_) => TextEncoding::Utf8,
+            // The sample may end in the middle of a character.
+            Err(e) if e.error_len().is_none() => TextEncoding::Utf8,
+            Err(_) => TextEncoding::Windows1252,
+        }
+    }
+
+    fn decode(&self, bytes: &[u8]) -> std::result::Result<String, usize> {
+        match self {
+            TextEncoding::Utf8 => std::str::from_utf8(bytes)
+                .map(str::to_string)
+                .map_err(|e| e.valid_up_to()),
+            TextEncoding::Utf8Lossy => Ok(String::from_utf8_lossy(bytes).into_owned()),
+            TextEncoding::Latin1 => Ok(bytes.iter().map(|&it| it as char).collect()),
+            TextEncoding::Windows1252 => {
+                Ok(bytes.iter().map(|&it| windows_1252_char(it)).collect())
+            }
+            TextEncoding::Utf16Le | TextEncoding::Utf16Be => match self.decode_utf16(bytes) {
+                (text, None) => Ok(text),
+                (_, Some(valid_up_to)) => Err(valid_up_to),
+            },
+        }
+    }
+
+    // Like decode but invalid sequences become U+FFFD. Also tells whether any did.
+    fn decode_lossy(&self, bytes: &[u8]) -> (String, bool) {
+        match self {
+            TextEncoding::Utf8 | TextEncoding::Utf8Lossy => match String::from_utf8_lossy(bytes) {
+                Cow::Borrowed(text) => (text.to_string(), false),
+                Cow::Owned(text) => (text, true),
+            },
+            TextEncoding::Utf16Le | TextEncoding::Utf16Be => {
+                let (text, invalid) = self.decode_utf16(bytes);
+                (text, invalid.is_some())
+            }
+            // Every byte is a character in the single byte encodings.
+            _ => (self.decode(bytes).unwrap_or_default(), false),
+        }
+    }
+
+    // Returns the text with U+FFFD for the invalid units and where the first one starts.
+    fn decode_utf16(&self, bytes: &[u8]) -> (String, Option<usize>) {
+        let units = bytes.chunks_exact(2).map(|it| match self {
+            TextEncoding::Utf16Be => u16::from_be_bytes([it[0], it[1]]),
+            _ => u16::from_le_bytes([it[0], it[1]]),
+        });
+        let mut text = String::with_capacity(bytes.len() / 2);
+        let mut invalid = None;
+        let mut offset = 0;
+
+        for c in char::decode_utf16(units) {
+            match c {
+                Ok(c) => {
+                    text.push(c);
+                    offset += c.len_utf16() * 2;
+                }
+                Err(_) => {
+                    text.push(char::REPLACEMENT_CHARACTER);
+                    invalid.get_or_insert(offset);
+                    offset += 2;
+                }
+            }
+        }
+
+        if !bytes.len().is_multiple_of(2) {
+            text.push(char::REPLACEMENT_CHARACTER);
+            invalid.get_or_insert(offset);
+        }
+
+        (text, invalid)
+    }
+
+    fn encode(&self, text: Vec<u8>) -> Vec<u8> {
+        match self {
+            // Characters Latin-1 cannot hold are written as '?'.
+            TextEncoding::Latin1 => String::from_utf8_lossy(&text)
+                .chars()
+                .map(|it| if (it as u32) < 256 { it as u8 } else { b'?' })
+                .collect(),
+            TextEncoding::Windows1252 => String::from_utf8_lossy(&text)
+                .chars()
+                .map(windows_1252_byte)
+                .collect(),
+            TextEncoding::Utf16Le => String::from_utf8_lossy(&text)
+                .encode_utf16()
+                .flat_map(u16::to_le_bytes)
+                .collect(),
+            TextEncoding::Utf16Be => String::from_utf8_lossy(&text)
+                .encode_utf16()
+                .flat_map(u16::to_be_bytes)
+                .collect(),
+            _ => text,
+        }
+    }
+
+    fn line_ending(&self) -> (&'static [u8], &'static [u8]) {
+        match self {
+            TextEncoding::Utf16Le => (&[b'\n', 0], &[b'\r', 0]),
+            TextEncoding::Utf16Be => (&[0, b'\n'], &[0, b'\r']),
+            _ => (b"\n", b"\r"),
+        }
+    }
+}
+
+fn windows_1252_char(byte: u8) -> char {
+    match byte {
+        0x80..=0x9F => WINDOWS_1252[(byte - 0x80) as usize],
+        _ => byte as char,
+    }
+}
+
+// Characters Windows-1252 cannot hold are written as '?'.
+fn windows_1252_byte(c: char) -> u8 {
+    match c as u32 {
+        0..=0x7F | 0xA0..=0xFF => c as u8,
+        _ => WINDOWS_1252
+            .iter()
+            .position(|&it| it == c)
+            .map_or(b'?', |it| 0x80 + it as u8),
+    }
+}
+
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct TextOptions {
+    // None detects it from the byte order mark or the start of the content.
+    pub encoding: Option<TextEncoding>,
+    // Invalid sequences become U+FFFD and the line is flagged instead of failing.
+    pub lossy: bool,
+}
+
+impl TextOptions {
+    pub fn new() -> Self {
+        Default::default()
+    }
+
+    pub fn with_encoding(&self, encoding: TextEncoding) -> Self {
+        TextOptions {
+            encoding: Some(encoding),
+            ..*self
+        }
+    }
+
+    pub fn with_lossy(&self, lossy: bool) -> Self {
+        TextOptions { lossy, ..*self }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct TextLine {
+    pub number: u64,
+    pub text: String,
+    // Some of the bytes were invalid and got replaced with U+FFFD.
+    pub replaced: bool,
+}
+
+// Reads lines in any of the supported encodings as UTF-8, without the line endings. The byte order
+// mark is skipped.
+#[derive(Debug)]
+pub struct TextLines<R: BufRead> {
+    reader: R,
+    encoding: TextEncoding,
+    detected: bool,
+    lossy: bool,
+    line: u64,
+    replaced: Vec<u64>,
+    finished: bool,
+}
+
+impl<R: BufRead> TextLines<R> {
+    pub fn new(mut reader: R, options: &TextOptions) -> Result<Self> {
+        let buffer = reader.fill_buf()?;
+        let encoding = options
+            .encoding
+            .unwrap_or_else(|| TextEncoding::detect(buffer));
+        let bom = if buffer.starts_with(encoding.bom()) {
+            encoding.bom().len()
+        } else {
+            0
+        };
+        reader.consume(bom);
+        Ok(TextLines {
+            reader,
+            encoding,
+            detected: options.encoding.is_none(),
+            lossy: options.lossy || encoding == TextEncoding::Utf8Lossy,
+            line: 0,
+            replaced: Vec::new(),
+            finished: false,
+        })
+    }
+
+    // The encoding of the lines read so far. A detected UTF-8 can still turn into Windows-1252.
+    pub fn encoding(&self) -> TextEncoding {
+        self.encoding
+    }
+
+    // The numbers of the lines read so far that needed replacements.
+    pub fn replaced_lines(&self) -> &[u64] {
+        &self.replaced
+    }
+
+    fn read_line(&mut self, line: &mut Vec<u8>) -> sio::Result<()> {
+        let (newline, _) = self.encoding.line_ending();
+
+        loop {
+            if self.reader.read_until(b'\n', line)? == 0 {
+                return Ok(());
+            }
+
+            // In UTF-16 the byte only ends the line when the whole unit is a line feed.
+            match self.encoding {
+                TextEncoding::Utf16Le if !line.len().is_multiple_of(2) => {
+                    let Some(&next) = self.reader.fill_buf()?.first() else {
+                        return Ok(());
+                    };
+
+                    self.reader.consume(1);
+                    line.push(next);
+
+                    if next == 0 {
+                        return Ok(());
+                    }
+                }
+                TextEncoding::Utf16Be
+                    if line.len().is_multiple_of(2) && line.ends_with(newline) =>
+                {
+                    return Ok(())
+                }
+                TextEncoding::Utf16Le | TextEncoding::Utf16Be => {}
+                _ => return Ok(()),
+            }
+        }
+    }
+}
+
+impl<R: BufRead> Iterator for TextLines<R> {
+    type Item = Result<TextLine>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.finished {
+            return None;
+        }
+
+        let mut bytes = Vec::new();
+
+        if let Err(e) = self.read_line(&mut bytes) {
+            self.finished = true;
+            return Some(Err(e.into()));
+        }
+
+        if bytes.is_empty() {
+            self.finished = true;
+            return None;
+        }
+
+        self.line += 1;
+
+        let (newline, carriage_return) = self.encoding.line_ending();
+        let mut bytes = bytes.as_slice();
+
+        if let Some(it) = bytes.strip_suffix(newline) {
+            bytes = it.strip_suffix(carriage_return).unwrap_or(it);
+        }
+
+        // The detection only sampled the start, so the rest of the content is not UTF-8 after all.
+        if self.detected
+            && self.encoding == TextEncoding::Utf8
+            && std::str::from_utf8(bytes).is_err()
+        {
+            self.encoding = TextEncoding::Windows1252;
+        }
+
+        if self.lossy {
+            let (text, replaced) = self.encoding.decode_lossy(bytes);
+
+            if replaced {
+                self.replaced.push(self.line);
+            }
+
+            return Some(Ok(TextLine {
+                number: self.line,
+                text,
+                replaced,
+            }));
+        }
+
+        Some(
+            self.encoding
+                .decode(bytes)
+                .map(|text| TextLine {
+                    number: self.line,
+                    text,
+                    replaced: false,
+                })
+                .map_err(|valid_up_to| RmxError::Record {
+                    line: self.line,
+                    field: None,
+                    message: format!(
+                        "Invalid {} after {} bytes",
+                        self.encoding.name(),
+                        valid_up_to
+                    ),
+                }),
+        )
+    }
+}
+
+// Serves the lines as UTF-8 bytes so byte oriented parsers can read the other encodings.
+struct TranscodedReader<R: BufRead> {
+    lines: TextLines<R>,
+    buffer: Vec<u8>,
+    position: usize,
+}
+
+impl<R: BufRead> TranscodedReader<R> {
+    fn new(lines: TextLines<R>) -> Self {
+        TranscodedReader {
+            lines,
+            buffer: Vec::new(),
+            position: 0,
+        }
+    }
+}
+
+impl<R: BufRead> Read for TranscodedReader<R> {
+    fn read(&mut self, buf: &mut [u8]) -> sio::Result<usize> {
+        if self.position >= self.buffer.len() {
+            let line = match self.lines.next() {
+                None => return Ok(0),
+                Some(Ok(line)) => line,
+                Some(Err(RmxError::Io(e))) => return Err(e),
+                Some(Err(e)) => {
+                    return Err(sio::Error::new(sio::ErrorKind::InvalidData, e.to_string()))
+                }
+            };
+            self.buffer = line.text.into_bytes();
+            self.buffer.push(b'\n');
+            self.position = 0;
+        }
+
+        let len = buf.len().min(self.buffer.len() - self.position);
+        buf[..len].copy_from_slice(&self.buffer[self.position..self.position + len]);
+        self.position += len;
+        Ok(len)
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct DelimitedOptions {
+    pub delimiter: u8,
+    pub has_headers: bool,
+    pub quote: u8,
+    // Whether the reader treats quotes as special at all.
+    pub quoting: bool,
+    pub quote_style: QuoteStyle,
+    pub comment: Option<u8>,
+    pub trim: Trim,
+    // Allows rows with a different number of fields.
+    pub flexible: bool,
+    pub encoding: TextEncoding,
+}
+
+impl Default for DelimitedOptions {
+    fn default() -> Self {
+        DelimitedOptions {
+            delimiter: b',',
+            has_headers: true,
+            quote: b'"',
+            quoting: true,
+            quote_style: QuoteStyle::Necessary,
+            comment: None,
+            trim: Trim::None,
+            flexible: false,
+            encoding: TextEncoding::Utf8,
+        }
+    }
+}
+
+impl DelimitedOptions {
+    pub fn new() -> Self {
+        Default::default()
+    }
+
+    pub fn csv() -> Self {
+        Self::new()
+    }
+
+    pub fn tsv() -> Self {
+        Self::new().with_delimiter(b'\t')
+    }
+
+    pub fn with_delimiter(&self, delimiter: u8) -> Self {
+        DelimitedOptions {
+            delimiter,
+            ..self.clone()
+        }
+    }
+
+    pub fn with_headers(&self, has_headers: bool) -> Self {
+        DelimitedOptions {
+            has_headers,
+            ..self.clone()
+        }
+    }
+
+    pub fn with_quote(&self, quote: u8) -> Self {
+        DelimitedOptions {
+            quote,
+            ..self.clone()
+        }
+    }
+
+    pub fn with_quoting(&self, quoting: bool) -> Self {
+        DelimitedOptions {
+            quoting,
+            ..self.clone()
+        }
+    }
+
+    pub fn with_quote_style(&self, quote_style: QuoteStyle) -> Self {
+        DelimitedOptions {
+            quote_style,
+            ..self.clone()
+        }
+    }
+
+    pub fn with_comment(&self, comment: u8) -> Self {
+        DelimitedOptions {
+            comment: Some(comment),
+            ..self.clone()
+        }
+    }
+
+    pub fn with_trim(&self, trim: Trim) -> Self {
+        DelimitedOptions {
+            trim,
+            ..self.clone()
+        }
+    }
+
+    pub fn with_flexible(&self, flexible: bool) -> Self {
+        DelimitedOptions {
+            flexible,
+            ..self.clone()
+        }
+    }
+
+    pub fn with_encoding(&self, encoding: TextEncoding) -> Self {
+        DelimitedOptions {
+            encoding,
+            ..self.clone()
+        }
+    }
+
+    fn reader_builder(&self) -> ReaderBuilder {
+        let mut builder = ReaderBuilder::new();
+        builder
+            .delimiter(self.delimiter)
+            .has_headers(self.has_headers)
+            .quote(self.quote)
+            .quoting(self.quoting)
+            .comment(self.comment)
+            .trim(self.trim)
+            .flexible(self.flexible);
+        builder
+    }
+
+    fn writer_builder(&self) -> WriterBuilder {
+        let mut builder = WriterBuilder::new();
+        builder
+            .delimiter(self.delimiter)
+            .has_headers(self.has_headers)
+            .quote(self.quote)
+            .quote_style(self.quote_style)
+            .flexible(self.flexible);
+        builder
+    }
+}
+
 pub fn exists<T: AsRef<Path>>(path: T) -> bool {
     path.as_ref().is_file()
 }
@@ -66,6 +683,104 @@ pub fn delete<T: AsRef<Path>>(path: T) -> Result<()> {
     fs::remove_file(path).map_err(Into::into)
 }
 
+// Writes to a temporary file next to the target, syncs it and renames it over the target, so
+// readers see either the old content or the new one, never a partial write.
+pub fn write_atomic<T: AsRef<Path>, B: AsRef<[u8]>>(path: T, bytes: B) -> Result<()> {
+    write_atomic_with(path, |file| {
+        file.write_all(bytes.as_ref()).map_err(Into::into)
+    })
+}
+
+pub fn write_lines_atomic<T: AsRef<Path>, L: AsRef<str>>(
+    path: T,
+    data: impl Iterator<Item = L>,
+) -> Result<()> {
+    write_atomic_with(path, |file| {
+        let mut writer = BufWriter::new(file);
+
+        for line in data {
+            writeln!(writer, "{}", line.as_ref())?;
+        }
+
+        writer.flush()?;
+        Ok(())
+    })
+}
+
+pub fn write_json_atomic<T: AsRef<Path>, D: Serialize>(
+    path: T,
+    data: &D,
+    pretty: Option<bool>,
+) -> Result<()> {
+    write_atomic_with(path, |file| file.write_json(data, pretty))
+}
+
+pub fn write_atomic_with<T: AsRef<Path>, F: FnOnce(&mut std::fs::File) -> Result<()>>(
+    path: T,
+    f: F,
+) -> Result<()> {
+    let path = path.as_ref();
+    let Some(name) = path.file_name() else {
+        return Err(RmxError::Argument(format!(
+            "'{}' is not a file path",
+            path.display()
+        )));
+    };
+    let dir = match path.parent() {
+        Some(dir) if !dir.as_os_str().is_empty() => dir,
+        _ => Path::new("."),
+    };
+    directory::ensure(dir)?;
+
+    let temp = dir.join(format!(
+        ".{}.{}.{}.tmp",
+        name.to_string_lossy(),
+        process::id(),
+        TEMP_COUNTER.fetch_add(1, Ordering::Relaxed)
+    ));
+    let result =
+        write_temp(path, &temp, f).and_then(|_| fs::rename(&temp, path).map_err(Into::into));
+
+    if result.is_err() {
+        fs::remove_file(&temp).ok();
+        return result;
+    }
+
+    // The target is already replaced at this point, so a directory that cannot be synced only
+    // risks the rename after a crash and is not reported as a failed write.
+    sync_dir(dir).ok();
+    Ok(())
+}
+
+fn write_temp<F: FnOnce(&mut std::fs::File) -> Result<()>>(
+    path: &Path,
+    temp: &Path,
+    f: F,
+) -> Result<()> {
+    let mut file = create_with(temp, FileOpenOptions::New)?;
+
+    // Keep the permissions of the file being replaced.
+    if let Ok(metadata) = fs::metadata(path) {
+        file.set_permissions(metadata.permissions())?;
+    }
+
+    f(&mut file)?;
+    file.sync_all()?;
+    Ok(())
+}
+
+// The rename is only durable once the directory entry is synced too.
+#[cfg(unix)]
+fn sync_dir(dir: &Path) -> Result<()> {
+    std::fs::File::open(dir)?.sync_all()?;
+    Ok(())
+}
+
+#[cfg(not(unix))]
+fn sync_dir(_dir: &Path) -> Result<()> {
+    Ok(())
+}
+
 pub trait FileEx {
     fn read(&self) -> Result<impl Iterator<Item = String>>;
     fn read_filtered<F: Fn(&str) -> bool + 'static>(
@@ -90,6 +805,24 @@ pub trait FileEx {
     fn write_lines<T: AsRef<str>>(&mut self, data: impl Iterator<Item = T>) -> Result<()>;
     fn read_json<T: de::DeserializeOwned>(&self) -> Result<T>;
     fn write_json<T: Serialize>(&mut self, data: &T, pretty: Option<bool>) -> Result<()>;
+    fn read_text(&self, options: &TextOptions) -> Result<TextLines<BufReader<&std::fs::File>>>;
+    fn read_jsonl<T: de::DeserializeOwned>(&self) -> Result<impl Iterator<Item = Result<T>>>;
+    fn read_jsonl_batch<T: de::DeserializeOwned, R: Fn(u32, Vec<T>) -> bool + 'static>(
+        &self,
+        batch: usize,
+        callback: R,
+    ) -> Result<u32>;
+    fn write_jsonl<T: Serialize>(&mut self, data: impl Iterator<Item = T>) -> Result<()>;
+    fn append_jsonl<T: Serialize>(&mut self, data: &T) -> Result<()>;
+    fn read_json_array<T: de::DeserializeOwned>(&self) -> Result<impl Iterator<Item = Result<T>>>;
+    fn read_json_array_batch<T: de::DeserializeOwned, R: Fn(u32, Vec<T>) -> bool + 'static>(
+        &self,
+        batch: usize,
+        callback: R,
+    ) -> Result<u32>;
+    fn acquire_lock(&self, mode: LockMode) -> Result<FileLock<'_>>;
+    fn try_acquire_lock(&self, mode: LockMode) -> Result<Option<FileLock<'_>>>;
+    fn acquire_lock_timeout(&self, mode: LockMode, timeout: Duration) -> Result<FileLock<'_>>;
     fn create_delimited_reader(
         &mut self,
         delimiter: Option<u8>,
@@ -100,30 +833,32 @@ pub trait FileEx {
         delimiter: Option<u8>,
         has_headers: Option<bool>,
     ) -> csv::Writer<&mut std::fs::File>;
+    fn read_records<T: de::DeserializeOwned>(
+        &mut self,
+        options: &DelimitedOptions,
+    ) -> Result<impl Iterator<Item = Result<T>> + '_>;
+    fn write_records<T: Serialize>(
+        &mut self,
+        records: &[T],
+        options: &DelimitedOptions,
+    ) -> Result<usize>;
 }
 
 impl FileEx for std::fs::File {
+    // Invalid UTF-8 is replaced with U+FFFD instead of dropping the line, use read_text to pick
+    // the encoding or to fail on it. A read error ends the lines.
     fn read(&self) -> Result<impl Iterator<Item = String>> {
-        let reader = BufReader::new(self);
-        Ok(reader.lines().filter_map(|line| line.ok()))
+        let lines = self.read_text(&TextOptions::new().with_encoding(TextEncoding::Utf8Lossy))?;
+        Ok(lines.map_while(|line| line.ok()).map(|line| line.text))
     }
 
     fn read_filtered<F: Fn(&str) -> bool + 'static>(
         &self,
         filter: F,
     ) -> Result<impl Iterator<Item = String>> {
-        let reader = BufReader::new(self);
-        Ok(reader.lines().filter_map(move |e| {
-            if let Ok(line) = e {
-                if line.is_empty() || !filter(&line) {
-                    return None;
-                }
-
-                Some(line)
-            } else {
-                None
-            }
-        }))
+        Ok(self
+            .read()?
+            .filter(move |line| !line.is_empty() && filter(line)))
     }
 
     fn read_batch<R: Fn(u32, Vec<String>) -> bool + 'static>(
@@ -136,23 +871,18 @@ impl FileEx for std::fs::File {
         } else {
             batch
         };
-        let mut reader = BufReader::new(self);
+        let reader = self.read_text(&TextOptions::new().with_encoding(TextEncoding::Utf8Lossy))?;
         let mut batch_number = 0u32;
-        let mut line: String = String::new();
         let mut lines: Vec<String> = Vec::with_capacity(batch);
 
-        while let Ok(n) = reader.read_line(&mut line) {
-            if n == 0 {
-                break;
-            }
+        for line in reader {
+            let line = line?.text;
 
             if line.is_empty() {
-                line.clear();
                 continue;
             }
 
-            lines.push(line.clone());
-            line.clear();
+            lines.push(line);
 
             if lines.len() < batch {
                 continue;
@@ -190,23 +920,18 @@ impl FileEx for std::fs::File {
         } else {
             batch
         };
-        let mut reader = BufReader::new(self);
+        let reader = self.read_text(&TextOptions::new().with_encoding(TextEncoding::Utf8Lossy))?;
         let mut batch_number = 0u32;
-        let mut line: String = String::new();
         let mut lines = Vec::with_capacity(batch);
 
-        while let Ok(n) = reader.read_line(&mut line) {
-            if n == 0 {
-                break;
-            }
+        for line in reader {
+            let line = line?.text;
 
             if line.is_empty() || !filter(&line) {
-                line.clear();
                 continue;
             }
 
-            lines.push(line.clone());
-            line.clear();
+            lines.push(line);
 
             if lines.len() < batch {
                 continue;
@@ -258,6 +983,109 @@ impl FileEx for std::fs::File {
         Ok(())
     }
 
+    fn read_text(&self, options: &TextOptions) -> Result<TextLines<BufReader<&std::fs::File>>> {
+        TextLines::new(BufReader::new(self), options)
+    }
+
+    fn read_jsonl<T: de::DeserializeOwned>(&self) -> Result<impl Iterator<Item = Result<T>>> {
+        let reader = BufReader::new(self);
+        Ok(reader
+            .lines()
+            .enumerate()
+            .filter_map(|(index, line)| match line {
+                Ok(line) if line.trim().is_empty() => None,
+                Ok(line) => Some(serde_json::from_str(&line).map_err(|e| RmxError::Record {
+                    line: index as u64 + 1,
+                    field: None,
+                    message: e.to_string(),
+                })),
+                Err(e) => Some(Err(e.into())),
+            }))
+    }
+
+    fn read_jsonl_batch<T: de::DeserializeOwned, R: Fn(u32, Vec<T>) -> bool + 'static>(
+        &self,
+        batch: usize,
+        callback: R,
+    ) -> Result<u32> {
+        read_items_batch(self.read_jsonl()?, batch, callback)
+    }
+
+    fn write_jsonl<T: Serialize>(&mut self, data: impl Iterator<Item = T>) -> Result<()> {
+        let mut writer = BufWriter::new(self);
+
+        for item in data {
+            serde_json::to_writer(&mut writer, &item)?;
+            writer.write_all(b"\n")?;
+        }
+
+        writer.flush()?;
+        Ok(())
+    }
+
+    fn append_jsonl<T: Serialize>(&mut self, data: &T) -> Result<()> {
+        let mut line = serde_json::to_vec(data)?;
+        line.push(b'\n');
+        self.seek(SeekFrom::End(0))?;
+        self.write_all(&line)?;
+        Ok(())
+    }
+
+    fn read_json_array<T: de::DeserializeOwned>(&self) -> Result<impl Iterator<Item = Result<T>>> {
+        Ok(JsonArrayReader::new(BufReader::new(self)))
+    }
+
+    fn read_json_array_batch<T: de::DeserializeOwned, R: Fn(u32, Vec<T>) -> bool + 'static>(
+        &self,
+        batch: usize,
+        callback: R,
+    ) -> Result<u32> {
+        read_items_batch(self.read_json_array()?, batch, callback)
+    }
+
+    fn acquire_lock(&self, mode: LockMode) -> Result<FileLock<'_>> {
+        match mode {
+            LockMode::Shared => self.lock_shared()?,
+            LockMode::Exclusive => self.lock()?,
+        }
+
+        Ok(FileLock { file: self, mode })
+    }
+
+    fn try_acquire_lock(&self, mode: LockMode) -> Result<Option<FileLock<'_>>> {
+        let result = match mode {
+            LockMode::Shared => self.try_lock_shared(),
+            LockMode::Exclusive => self.try_lock(),
+        };
+
+        match result {
+            Ok(_) => Ok(Some(FileLock { file: self, mode })),
+            Err(TryLockError::WouldBlock) => Ok(None),
+            Err(TryLockError::Error(e)) => Err(e.into()),
+        }
+    }
+
+    // There is no portable blocking lock with a timeout, so this polls with a growing delay.
+    fn acquire_lock_timeout(&self, mode: LockMode, timeout: Duration) -> Result<FileLock<'_>> {
+        let deadline = Instant::now() + timeout;
+        let mut delay = Duration::from_millis(1);
+
+        loop {
+            if let Some(lock) = self.try_acquire_lock(mode)? {
+                return Ok(lock);
+            }
+
+            let now = Instant::now();
+
+            if now >= deadline {
+                return Err(RmxError::Timeout);
+            }
+
+            thread::sleep(delay.min(deadline - now));
+            delay = (delay * 2).min(LOCK_POLL_MAX);
+        }
+    }
+
     fn create_delimited_reader(
         &mut self,
         delimiter: Option<u8>,
@@ -283,4 +1111,600 @@ impl FileEx for std::fs::File {
             .has_headers(has_headers)
             .from_writer(self)
     }
+
+    fn read_records<T: de::DeserializeOwned>(
+        &mut self,
+        options: &DelimitedOptions,
+    ) -> Result<impl Iterator<Item = Result<T>> + '_> {
+        // The parser splits on single bytes, so UTF-16 is turned into UTF-8 before it.
+        let (source, encoding): (Box<dyn Read + '_>, _) = if options.encoding.is_utf16() {
+            let text_options = TextOptions::new().with_encoding(options.encoding);
+            let lines = TextLines::new(BufReader::new(&*self), &text_options)?;
+            (Box::new(TranscodedReader::new(lines)), TextEncoding::Utf8)
+        } else {
+            (Box::new(self), options.encoding)
+        };
+        let mut reader = options.reader_builder().from_reader(source);
+        let headers = if options.has_headers {
+            Some(decode_record(
+                reader.byte_headers().map_err(record_error)?,
+                encoding,
+                None,
+            )?)
+        } else {
+            None
+        };
+        Ok(reader.into_byte_records().map(move |record| {
+            let record = record.map_err(record_error)?;
+            let record = decode_record(&record, encoding, headers.as_ref())?;
+            deserialize_record(&record, headers.as_ref())
+        }))
+    }
+
+    fn write_records<T: Serialize>(
+        &mut self,
+        records: &[T],
+        options: &DelimitedOptions,
+    ) -> Result<usize> {
+        let mut writer = options.writer_builder().from_writer(Vec::new());
+
+        for (index, record) in records.iter().enumerate() {
+            writer.serialize(record).map_err(|e| RmxError::Record {
+                line: index as u64 + 1,
+                field: None,
+                message: e.to_string(),
+            })?;
+        }
+
+        let buffer = writer.into_inner().map_err(|e| e.into_error())?;
+        self.write_all(&options.encoding.encode(buffer))?;
+        Ok(records.len())
+    }
+}
+
+// Same batching as read_batch, stopping at the first item that fails.
+fn read_items_batch<T, R: Fn(u32, Vec<T>) -> bool>(
+    items: impl Iterator<Item = Result<T>>,
+    batch: usize,
+    callback: R,
+) -> Result<u32> {
+    let batch = if batch == 0 {
+        LINES_BUFFER_DEFAULT
+    } else {
+        batch
+    };
+    let mut batch_number = 0u32;
+    let mut buffer = Vec::with_capacity(batch);
+
+    for item in items {
+        buffer.push(item?);
+
+        if buffer.len() < batch {
+            continue;
+        }
+
+        batch_number += 1;
+
+        if !callback(
+            batch_number,
+            std::mem::replace(&mut buffer, Vec::with_capacity(batch)),
+        ) {
+            return Ok(batch_number);
+        }
+    }
+
+    if buffer.is_empty() {
+        return Ok(batch_number);
+    }
+
+    batch_number += 1;
+    callback(batch_number, buffer);
+    Ok(batch_number)
+}
+
+// Yields the elements of a top-level JSON array one by one. Each element is cut out of the input
+// by tracking nesting and strings, so only one element is in memory at a time.
+struct JsonArrayReader<R: BufRead, T> {
+    bytes: sio::Bytes<R>,
+    line: u64,
+    count: u64,
+    started: bool,
+    finished: bool,
+    _item: std::marker::PhantomData<T>,
+}
+
+impl<R: BufRead, T: de::DeserializeOwned> JsonArrayReader<R, T> {
+    fn new(reader: R) -> Self {
+        JsonArrayReader {
+            bytes: reader.bytes(),
+            line: 1,
+            count: 0,
+            started: false,
+            finished: false,
+            _item: std::marker::PhantomData,
+        }
+    }
+
+    fn error(&mut self, line: u64, message: impl Into<String>) -> Option<Result<T>> {
+        self.finished = true;
+        Some(Err(RmxError::Record {
+            line,
+            field: None,
+            message: message.into(),
+        }))
+    }
+
+    fn next_byte(&mut self) -> Option<sio::Result<u8>> {
+        let byte = self.bytes.next()?;
+
+        if let Ok(b'\n') = byte {
+            self.line += 1;
+        }
+
+        Some(byte)
+    }
+}
+
+impl<R: BufRead, T: de::DeserializeOwned> Iterator for JsonArrayReader<R, T> {
+    type Item = Result<T>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.finished {
+            return None;
+        }
+
+        if !self.started {
+            loop {
+                match self.next_byte() {
+                    Some(Ok(b'[')) => break,
+                    Some(Ok(b)) if b.is_ascii_whitespace() => continue,
+                    Some(Ok(_)) | None => {
+                        let line = self.line;
+                        return self.error(line, "The document is not a JSON array");
+                    }
+                    Some(Err(e)) => {
+                        self.finished = true;
+                        return Some(Err(e.into()));
+                    }
+                }
+            }
+
+            self.started = true;
+        }
+
+        let mut element = Vec::new();
+        let mut start = self.line;
+        let mut depth = 0usize;
+        let mut in_string = false;
+        let mut escaped = false;
+
+        loop {
+            let b = match self.next_byte() {
+                Some(Ok(b)) => b,
+                Some(Err(e)) => {
+                    self.finished = true;
+                    return Some(Err(e.into()));
+                }
+                None => {
+                    let line = self.line;
+                    return self.error(line, "Unexpected end of the JSON array");
+                }
+            };
+
+            if element.is_empty() {
+                if b.is_ascii_whitespace() {
+                    continue;
+                }
+
+                start = self.line;
+            }
+
+            if in_string {
+                if escaped {
+                    escaped = false;
+                } else if b == b'\\' {
+                    escaped = true;
+                } else if b == b'"' {
+                    in_string = false;
+                }
+
+                element.push(b);
+                continue;
+            }
+
+            match b {
+                b'"' => in_string = true,
+                b'{' | b'[' => depth += 1,
+                b'}' | b']' if depth > 0 => depth -= 1,
+                b']' => {
+                    self.finished = true;
+                    break;
+                }
+                b',' if depth == 0 => break,
+                _ => {}
+            }
+
+            element.push(b);
+        }
+
+        if element.is_empty() {
+            if self.finished && self.count == 0 {
+                return None;
+            }
+
+            return self.error(start, "Missing JSON array element");
+        }
+
+        self.count += 1;
+        Some(
+            serde_json::from_slice(&element).map_err(|e| RmxError::Record {
+                line: start,
+                field: None,
+                message: e.to_string(),
+            }),
+        )
+    }
+}
+
+fn field_name(headers: Option<&StringRecord>, field: usize) -> String {
+    headers
+        .and_then(|it| it.get(field))
+        .map(str::to_string)
+        .unwrap_or_else(|| format!("#{}", field + 1))
+}
+
+fn record_line(record: &ByteRecord) -> u64 {
+    record.position().map(|it| it.line()).unwrap_or_default()
+}
+
+fn record_error(error: csv::Error) -> RmxError {
+    let line = error.position().map(|it| it.line()).unwrap_or_default();
+    let message = error.to_string();
+
+    match error.into_kind() {
+        csv::ErrorKind::Io(e) => e.into(),
+        _ => RmxError::Record {
+            line,
+            field: None,
+            message,
+        },
+    }
+}
+
+fn decode_record(
+    record: &ByteRecord,
+    encoding: TextEncoding,
+    headers: Option<&StringRecord>,
+) -> Result<StringRecord> {
+    let mut decoded = StringRecord::with_capacity(record.as_slice().len(), record.len());
+
+    for (index, field) in record.iter().enumerate() {
+        let value = encoding
+            .decode(field)
+            .map_err(|valid_up_to| RmxError::Record {
+                line: record_line(record),
+                field: Some(field_name(headers, index)),
+                message: format!("Invalid {} after {} bytes", encoding.name(), valid_up_to),
+            })?;
+        decoded.push_field(&value);
+    }
+
+    decoded.set_position(record.position().cloned());
+    Ok(decoded)
+}
+
+fn deserialize_record<T: de::DeserializeOwned>(
+    record: &StringRecord,
+    headers: Option<&StringRecord>,
+) -> Result<T> {
+    record.deserialize(headers).map_err(|e| {
+        let line = record.position().map(|it| it.line()).unwrap_or_default();
+
+        match e.kind() {
+            csv::ErrorKind::Deserialize { err, .. } => RmxError::Record {
+                line,
+                field: err.field().map(|it| field_name(headers, it as usize)),
+                message: err.kind().to_string(),
+            },
+            _ => RmxError::Record {
+                line,
+                field: None,
+                message: e.to_string(),
+            },
+        }
+    })
+}
+
+// The async counterpart of FileEx for tokio files. The names differ where they would clash with
+// AsyncReadExt and AsyncWriteExt.
+pub trait AsyncFileEx {
+    fn read_lines(&mut self) -> BoxStream<'_, Result<String>>;
+    fn read_lines_filtered<F: Fn(&str) -> bool + Send + 'static>(
+        &mut self,
+        filter: F,
+    ) -> BoxStream<'_, Result<String>>;
+    fn read_batch(&mut self, batch: usize) -> BoxStream<'_, Result<Vec<String>>>;
+    fn read_batch_filtered<F: Fn(&str) -> bool + Send + 'static>(
+        &mut self,
+        batch: usize,
+        filter: F,
+    ) -> BoxStream<'_, Result<Vec<String>>>;
+    fn write_line<T: AsRef<str>>(&mut self, data: &T) -> impl Future<Output = Result<()>> + Send;
+    fn write_lines<T: AsRef<str> + Send>(
+        &mut self,
+        data: impl Iterator<Item = T> + Send,
+    ) -> impl Future<Output = Result<()>> + Send;
+    fn read_json<T: de::DeserializeOwned>(&mut self) -> impl Future<Output = Result<T>> + Send;
+    fn write_json<T: Serialize>(
+        &mut self,
+        data: &T,
+        pretty: Option<bool>,
+    ) -> impl Future<Output = Result<()>> + Send;
+    fn read_delimited(
+        &mut self,
+        delimiter: Option<u8>,
+        has_headers: Option<bool>,
+    ) -> impl Future<Output = Result<BoxStream<'static, Result<csv::StringRecord>>>> + Send;
+    fn write_delimited<T: Serialize + Send>(
+        &mut self,
+        records: impl Stream<Item = T> + Send,
+        delimiter: Option<u8>,
+        has_headers: Option<bool>,
+    ) -> impl Future<Output = Result<usize>> + Send;
+}
+
+impl AsyncFileEx for tokio::fs::File {
+    fn read_lines(&mut self) -> BoxStream<'_, Result<String>> {
+        read_lines_async(self, |_| true).boxed()
+    }
+
+    fn read_lines_filtered<F: Fn(&str) -> bool + Send + 'static>(
+        &mut self,
+        filter: F,
+    ) -> BoxStream<'_, Result<String>> {
+        read_lines_async(self, move |line| !line.is_empty() && filter(line)).boxed()
+    }
+
+    fn read_batch(&mut self, batch: usize) -> BoxStream<'_, Result<Vec<String>>> {
+        read_batch_async(self, batch, |line| !line.is_empty()).boxed()
+    }
+
+    fn read_batch_filtered<F: Fn(&str) -> bool + Send + 'static>(
+        &mut self,
+        batch: usize,
+        filter: F,
+    ) -> BoxStream<'_, Result<Vec<String>>> {
+        read_batch_async(self, batch, move |line| !line.is_empty() && filter(line)).boxed()
+    }
+
+    fn write_line<T: AsRef<str>>(&mut self, data: &T) -> impl Future<Output = Result<()>> + Send {
+        let line = format!("{}\n", data.as_ref());
+        async move {
+            self.write_all(line.as_bytes()).await?;
+            self.flush().await?;
+            Ok(())
+        }
+    }
+
+    async fn write_lines<T: AsRef<str> + Send>(
+        &mut self,
+        data: impl Iterator<Item = T> + Send,
+    ) -> Result<()> {
+        let mut writer = aio::BufWriter::new(&mut *self);
+
+        for line in data {
+            writer.write_all(line.as_ref().as_bytes()).await?;
+            writer.write_all(b"\n").await?;
+        }
+
+        writer.flush().await?;
+        Ok(())
+    }
+
+    async fn read_json<T: de::DeserializeOwned>(&mut self) -> Result<T> {
+        let mut buffer = Vec::new();
+        self.read_to_end(&mut buffer).await?;
+        let data: T = serde_json::from_slice(&buffer)?;
+        Ok(data)
+    }
+
+    fn write_json<T: Serialize>(
+        &mut self,
+        data: &T,
+        pretty: Option<bool>,
+    ) -> impl Future<Output = Result<()>> + Send {
+        let serialize = match pretty {
+            Some(true) => serde_json::to_string_pretty,
+            _ => serde_json::to_string,
+        };
+        let serialized = serialize(data);
+        async move {
+            self.write_all(serialized?.as_bytes()).await?;
+            self.flush().await?;
+            Ok(())
+        }
+    }
+
+    async fn read_delimited(
+        &mut self,
+        delimiter: Option<u8>,
+        has_headers: Option<bool>,
+    ) -> Result<BoxStream<'static, Result<csv::StringRecord>>> {
+        // csv has no async reader, so the records are parsed on a blocking thread and handed
+        // over through a bounded channel.
+        let file = self.try_clone().await?.into_std().await;
+        let (sender, receiver) = mpsc::channel(RECORDS_BUFFER_DEFAULT);
+        task::spawn_blocking(move || {
+            let reader = ReaderBuilder::new()
+                .delimiter(delimiter.unwrap_or(b','))
+                .has_headers(has_headers.unwrap_or(false))
+                .from_reader(file);
+
+            for record in reader.into_records() {
+                if sender.blocking_send(record.map_err(record_error)).is_err() {
+                    break;
+                }
+            }
+        });
+        let records = stream::unfold(receiver, |mut receiver| async move {
+            receiver.recv().await.map(|record| (record, receiver))
+        });
+        Ok(records.boxed())
+    }
+
+    async fn write_delimited<T: Serialize + Send>(
+        &mut self,
+        records: impl Stream<Item = T> + Send,
+        delimiter: Option<u8>,
+        has_headers: Option<bool>,
+    ) -> Result<usize> {
+        let delimiter = delimiter.unwrap_or(b',');
+        let mut records = pin!(records);
+        let mut writer = WriterBuilder::new()
+            .delimiter(delimiter)
+            .has_headers(has_headers.unwrap_or(false))
+            .from_writer(Vec::new());
+        let mut count = 0usize;
+
+        while let Some(record) = records.next().await {
+            writer.serialize(record).map_err(|e| RmxError::Record {
+                line: count as u64 + 1,
+                field: None,
+                message: e.to_string(),
+            })?;
+            count += 1;
+
+            if !count.is_multiple_of(RECORDS_BUFFER_DEFAULT) {
+                continue;
+            }
+
+            let buffer = writer.into_inner().map_err(|e| e.into_error())?;
+            self.write_all(&buffer).await?;
+            // Only the first chunk carries the headers.
+            writer = WriterBuilder::new()
+                .delimiter(delimiter)
+                .has_headers(false)
+                .from_writer(Vec::new());
+        }
+
+        let buffer = writer.into_inner().map_err(|e| e.into_error())?;
+        self.write_all(&buffer).await?;
+        self.flush().await?;
+        Ok(count)
+    }
+}
+
+// Reads the next line without its line ending, None at the end. Like the sync readers, invalid
+// UTF-8 is replaced with U+FFFD and the byte order mark of the first line is skipped.
+async fn read_line_lossy<R: aio::AsyncBufRead + Unpin>(
+    reader: &mut R,
+    buffer: &mut Vec<u8>,
+    first: bool,
+) -> sio::Result<Option<String>> {
+    buffer.clear();
+
+    if reader.read_until(b'\n', buffer).await? == 0 {
+        return Ok(None);
+    }
+
+    let mut bytes = buffer.as_slice();
+
+    if first {
+        bytes = bytes
+            .strip_prefix(TextEncoding::Utf8.bom())
+            .unwrap_or(bytes);
+    }
+
+    if let Some(it) = bytes.strip_suffix(b"\n") {
+        bytes = it.strip_suffix(b"\r").unwrap_or(it);
+    }
+
+    Ok(Some(String::from_utf8_lossy(bytes).into_owned()))
+}
+
+// The stream ends after the first I/O error it yields.
+fn read_lines_async<'a, F: Fn(&str) -> bool + Send + 'a>(
+    file: &'a mut tokio::fs::File,
+    filter: F,
+) -> impl Stream<Item = Result<String>> + Send + 'a {
+    let reader = aio::BufReader::new(file);
+    stream::unfold(
+        (reader, Vec::new(), filter, true, false),
+        |(mut reader, mut buffer, filter, mut first, done)| async move {
+            if done {
+                return None;
+            }
+
+            loop {
+                let line = read_line_lossy(&mut reader, &mut buffer, first).await;
+                first = false;
+
+                match line {
+                    Ok(Some(line)) if filter(&line) => {
+                        return Some((Ok(line), (reader, buffer, filter, false, false)))
+                    }
+                    Ok(Some(_)) => continue,
+                    Ok(None) => return None,
+                    Err(e) => return Some((Err(e.into()), (reader, buffer, filter, false, true))),
+                }
+            }
+        },
+    )
+}
+
+// An I/O error is yielded after the lines read before it, then the stream ends.
+fn read_batch_async<'a, F: Fn(&str) -> bool + Send + 'a>(
+    file: &'a mut tokio::fs::File,
+    batch: usize,
+    filter: F,
+) -> impl Stream<Item = Result<Vec<String>>> + Send + 'a {
+    let batch = if batch == 0 {
+        LINES_BUFFER_DEFAULT
+    } else {
+        batch
+    };
+    let reader = aio::BufReader::new(file);
+    stream::unfold(
+        (reader, Vec::new(), filter, true, None::<sio::Error>, false),
+        move |(mut reader, mut buffer, filter, mut first, error, done)| async move {
+            if let Some(e) = error {
+                return Some((Err(e.into()), (reader, buffer, filter, false, None, true)));
+            }
+
+            if done {
+                return None;
+            }
+
+            let mut lines = Vec::with_capacity(batch);
+            let mut error = None;
+            let mut done = false;
+
+            while lines.len() < batch {
+                let line = read_line_lossy(&mut reader, &mut buffer, first).await;
+                first = false;
+
+                match line {
+                    Ok(None) => {
+                        done = true;
+                        break;
+                    }
+                    Ok(Some(line)) => {
+                        if filter(&line) {
+                            lines.push(line);
+                        }
+                    }
+                    Err(e) => {
+                        error = Some(e);
+                        break;
+                    }
+                }
+            }
+
+            if lines.is_empty() {
+                return error.map(|e| (Err(e.into()), (reader, buffer, filter, false, None, true)));
+            }
+
+            Some((Ok(lines), (reader, buffer, filter, false, error, done)))
+        },
+    )
 }
@@ -4,7 +4,7 @@ pub mod path;
 
 use crossterm::{
     cursor,
-    event::{self, Event, KeyCode, KeyEvent},
+    event::{self, Event, KeyCode, KeyEvent, KeyModifiers},
     terminal::{disable_raw_mode, enable_raw_mode, Clear, ClearType},
     ExecutableCommand,
 };
@@ -12,10 +12,19 @@ use dialoguer::{theme::ColorfulTheme, Select};
 use std::{
     io::{stdin, stdout, Write},
     str::FromStr,
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc, RwLock,
+    },
 };
 
 use crate::{error::RmxError, Result};
 
+type InterruptHandler = Arc<dyn Fn() + Send + Sync>;
+
+static RAW_MODE: AtomicBool = AtomicBool::new(false);
+static INTERRUPT_HANDLER: RwLock<Option<InterruptHandler>> = RwLock::new(None);
+
 pub fn clear_screen() -> Result<()> {
     let mut stdout = stdout();
     stdout
@@ -72,10 +81,18 @@ pub fn get_char(prompt: Option<&str>) -> Result<char> {
     print_prompt(prompt);
     // Enable raw mode to read single characters
     enable_raw_mode()?;
+    RAW_MODE.store(true, Ordering::SeqCst);
 
     let result = loop {
-        if let Ok(Event::Key(KeyEvent { code, .. })) = event::read() {
+        if let Ok(Event::Key(KeyEvent {
+            code, modifiers, ..
+        })) = event::read()
+        {
             match code {
+                // Raw mode swallows Ctrl+C, so no SIGINT is raised for it.
+                KeyCode::Char('c') if modifiers.contains(KeyModifiers::CONTROL) => {
+                    break Err(RmxError::Canceled)
+                }
                 KeyCode::Char(c) => break Ok(c),
                 KeyCode::Esc | KeyCode::Enter => break Err(RmxError::NoInput),
                 _ => continue,
@@ -84,10 +101,43 @@ pub fn get_char(prompt: Option<&str>) -> Result<char> {
     };
 
     // Disable raw mode before returning
-    disable_raw_mode()?;
+    restore_terminal()?;
+
+    if matches!(result, Err(RmxError::Canceled)) {
+        interrupt();
+    }
+
     result
 }
 
+// Called when get_char reads Ctrl+C, which raw mode keeps from raising SIGINT.
+pub fn set_interrupt_handler(handler: impl Fn() + Send + Sync + 'static) {
+    let mut guard = INTERRUPT_HANDLER.write().unwrap();
+    *guard = Some(Arc::new(handler));
+}
+
+pub fn clear_interrupt_handler() {
+    let mut guard = INTERRUPT_HANDLER.write().unwrap();
+    *guard = None;
+}
+
+fn interrupt() {
+    let handler = INTERRUPT_HANDLER.read().unwrap().clone();
+
+    if let Some(handler) = handler {
+        handler();
+    }
+}
+
+// Leaves raw mode if get_char was interrupted while waiting for a key.
+pub fn restore_terminal() -> Result<()> {
+    if RAW_MODE.swap(false, Ordering::SeqCst) {
+        disable_raw_mode()?;
+    }
+
+    Ok(())
+}
+
 pub fn get_numeric<T: FromStr>(prompt: Option<&str>) -> Result<T>
 where
     <T as FromStr>::Err: std::fmt::Display,
@@ -0,0 +1,199 @@
+use futures::Future;
+use std::{sync::Arc, thread};
+use tokio::time::{self, Duration, Instant};
+
+use super::*;
+use crate::{error::*, Result};
+
+const BATCH_SIZE_DEF: usize = 100;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct Batch {
+    pub size: usize,
+    // How long a worker waits for more items before it processes a partial batch.
+    pub linger: Duration,
+}
+
+impl Default for Batch {
+    fn default() -> Self {
+        Self::new(BATCH_SIZE_DEF)
+    }
+}
+
+impl Batch {
+    pub fn new(size: usize) -> Self {
+        Batch {
+            size: size.max(1),
+            linger: Duration::ZERO,
+        }
+    }
+
+    pub fn with_linger(&self, linger: Duration) -> Self {
+        Batch { linger, ..*self }
+    }
+}
+
+// The batch results are matched to the items by position. Only the items that failed are
+// retried, an error for the whole batch counts as a failure of every item.
+pub trait BatchTaskDelegation<TPC: AwaitableConsumer<T>, T: StaticTaskItem, R: StaticTaskItem = ()>:
+    StaticTaskItem
+{
+    fn on_started(&self, pc: &TPC);
+    fn process_batch(
+        &self,
+        pc: &TPC,
+        items: &[T],
+        token: &CancellationToken,
+    ) -> Result<Vec<Result<R>>>;
+    fn on_completed(&self, pc: &TPC, item: &T, result: &TaskResult<R>) -> bool;
+    fn on_cancelled(&self, pc: &TPC);
+    fn on_finished(&self, pc: &TPC);
+}
+
+pub trait AsyncBatchTaskDelegation<
+    TPC: AwaitableConsumer<T>,
+    T: StaticTaskItem,
+    R: StaticTaskItem = (),
+>: StaticTaskItem
+{
+    fn on_started(&self, pc: &TPC);
+    fn process_batch(
+        &self,
+        pc: &TPC,
+        items: &[T],
+        token: &CancellationToken,
+    ) -> impl Future<Output = Result<Vec<Result<R>>>> + Send;
+    fn on_completed(&self, pc: &TPC, item: &T, result: &TaskResult<R>) -> bool;
+    fn on_cancelled(&self, pc: &TPC);
+    fn on_finished(&self, pc: &TPC);
+}
+
+// Tops up the batch with queued items, waiting up to the linger duration for late ones.
+pub(super) fn fill_batch<T>(
+    items: &mut Vec<QueueItem<T>>,
+    batch: &Batch,
+    mut next: impl FnMut() -> Option<QueueItem<T>>,
+) {
+    let deadline = Instant::now() + batch.linger;
+
+    while items.len() < batch.size {
+        if let Some(item) = next() {
+            items.push(item);
+            continue;
+        }
+
+        let remaining = deadline.saturating_duration_since(Instant::now());
+
+        if remaining.is_zero() {
+            break;
+        }
+
+        thread::sleep(remaining.min(PEEK_TIMEOUT_MIN));
+    }
+}
+
+pub(super) async fn fill_batch_async<T>(
+    items: &mut Vec<QueueItem<T>>,
+    batch: &Batch,
+    mut next: impl FnMut() -> Option<QueueItem<T>>,
+) {
+    let deadline = Instant::now() + batch.linger;
+
+    while items.len() < batch.size {
+        if let Some(item) = next() {
+            items.push(item);
+            continue;
+        }
+
+        let remaining = deadline.saturating_duration_since(Instant::now());
+
+        if remaining.is_zero() {
+            break;
+        }
+
+        time::sleep(remaining.min(PEEK_TIMEOUT_MIN)).await;
+    }
+}
+
+pub(super) fn process_batch_with_timeout<
+    H: BatchTaskDelegation<TPC, T, R>,
+    TPC: AwaitableConsumer<T>,
+    T: StaticTaskItem,
+    R: StaticTaskItem,
+>(
+    handler: &H,
+    pc: &TPC,
+    items: &[T],
+    token: &CancellationToken,
+    timeout: Option<Duration>,
+) -> Result<Vec<Result<R>>> {
+    let Some(timeout) = timeout else {
+        return handler.process_batch(pc, items, &token.child());
+    };
+
+    // The timeout covers the whole batch, the same way it covers a single item.
+    let attempt = token.child_with_timeout(timeout);
+    let result = handler.process_batch(pc, items, &attempt);
+
+    if attempt.is_expired() {
+        return Err(RmxError::Timeout);
+    }
+
+    result
+}
+
+pub(super) async fn process_batch_with_timeout_async<
+    H: AsyncBatchTaskDelegation<TPC, T, R>,
+    TPC: AwaitableConsumer<T>,
+    T: StaticTaskItem,
+    R: StaticTaskItem,
+>(
+    handler: &H,
+    pc: &TPC,
+    items: &[T],
+    token: &CancellationToken,
+    timeout: Option<Duration>,
+) -> Result<Vec<Result<R>>> {
+    let Some(timeout) = timeout else {
+        return handler.process_batch(pc, items, &token.child()).await;
+    };
+
+    let attempt = token.child_with_timeout(timeout);
+
+    match time::timeout(timeout, handler.process_batch(pc, items, &attempt)).await {
+        Ok(_) if attempt.is_expired() => Err(RmxError::Timeout),
+        Ok(result) => result,
+        Err(_) => {
+            attempt.cancel();
+            Err(RmxError::Timeout)
+        }
+    }
+}
+
+// Matches the results of a run to its items. An error for the whole batch or a missing result
+// counts as an error for the item.
+pub(super) fn batch_results<R>(
+    count: usize,
+    results: Result<Vec<Result<R>>>,
+) -> Vec<std::result::Result<R, Arc<RmxError>>> {
+    let results = match results {
+        Ok(it) => it,
+        Err(e) => {
+            let error = Arc::new(e);
+            return (0..count).map(|_| Err(error.clone())).collect();
+        }
+    };
+    let mut results = results
+        .into_iter()
+        .take(count)
+        .map(|it| it.map_err(Arc::new))
+        .collect::<Vec<_>>();
+
+    while results.len() < count {
+        results.push(Err(Arc::new(RmxError::InvalidOperation(
+            "The batch returned no result for the item".to_string(),
+        ))));
+    }
+
+    results
+}
@@ -0,0 +1,199 @@
+use futures::future;
+use std::{fmt, sync::Arc, time::Instant};
+use tokio::time::{self, Duration};
+
+use super::*;
+use crate::{error::*, Result};
+
+#[derive(Default)]
+struct TokenState {
+    cancelled: Event,
+    deadline: Option<Instant>,
+}
+
+impl TokenState {
+    fn is_cancelled(&self) -> bool {
+        self.cancelled.is_set() || self.deadline.is_some_and(|it| Instant::now() >= it)
+    }
+}
+
+// A token is cancelled when it or any of its parents is cancelled, or their deadline has passed.
+#[derive(Clone)]
+pub struct CancellationToken {
+    chain: Vec<Arc<TokenState>>,
+}
+
+impl Default for CancellationToken {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl fmt::Debug for CancellationToken {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("CancellationToken")
+            .field("cancelled", &self.is_cancelled())
+            .finish()
+    }
+}
+
+impl CancellationToken {
+    pub fn new() -> Self {
+        CancellationToken {
+            chain: vec![Arc::new(TokenState::default())],
+        }
+    }
+
+    pub fn child(&self) -> Self {
+        let mut chain = Vec::with_capacity(self.chain.len() + 1);
+        chain.push(Arc::new(TokenState::default()));
+        chain.extend(self.chain.iter().cloned());
+        CancellationToken { chain }
+    }
+
+    // A child that also cancels itself once the timeout has elapsed.
+    pub fn child_with_timeout(&self, timeout: Duration) -> Self {
+        self.child_with_deadline(Instant::now() + timeout)
+    }
+
+    pub fn child_with_deadline(&self, deadline: Instant) -> Self {
+        let mut chain = Vec::with_capacity(self.chain.len() + 1);
+        chain.push(Arc::new(TokenState {
+            deadline: Some(deadline),
+            ..Default::default()
+        }));
+        chain.extend(self.chain.iter().cloned());
+        CancellationToken { chain }
+    }
+
+    // The earliest deadline of the token and its parents.
+    pub fn deadline(&self) -> Option<Instant> {
+        self.chain.iter().filter_map(|it| it.deadline).min()
+    }
+
+    pub fn is_cancelled(&self) -> bool {
+        self.chain.iter().any(|it| it.is_cancelled())
+    }
+
+    // Whether a deadline passed rather than someone cancelling the token.
+    pub fn is_expired(&self) -> bool {
+        self.deadline().is_some_and(|it| Instant::now() >= it)
+            && !self.chain.iter().any(|it| it.cancelled.is_set())
+    }
+
+    pub fn cancel(&self) {
+        self.chain[0].cancelled.set();
+    }
+
+    pub fn check(&self) -> Result<()> {
+        if self.is_cancelled() {
+            return Err(RmxError::Canceled);
+        }
+
+        Ok(())
+    }
+
+    // Returns true if the token was cancelled before the timeout. Only the token's own cancel wakes
+    // the wait right away, the parents are checked every PEEK_TIMEOUT_MIN.
+    pub fn wait_timeout(&self, timeout: Duration) -> bool {
+        let mut deadline = Instant::now() + timeout;
+
+        if let Some(it) = self.deadline() {
+            deadline = deadline.min(it);
+        }
+
+        loop {
+            if self.is_cancelled() {
+                return true;
+            }
+
+            let remaining = deadline.saturating_duration_since(Instant::now());
+
+            if remaining.is_zero() {
+                return self.is_cancelled();
+            }
+
+            let wait = if self.chain.len() > 1 {
+                remaining.min(PEEK_TIMEOUT_MIN)
+            } else {
+                remaining
+            };
+            self.chain[0].cancelled.wait_timeout(wait);
+        }
+    }
+
+    pub async fn cancelled(&self) {
+        let cancelled = future::select_all(
+            self.chain
+                .iter()
+                .map(|it| Box::pin(it.cancelled.wait_async())),
+        );
+
+        match self.deadline() {
+            Some(deadline) => {
+                time::timeout_at(deadline.into(), cancelled).await.ok();
+            }
+            None => {
+                cancelled.await;
+            }
+        }
+    }
+}
+
+pub(super) fn process_with_timeout<
+    H: TaskDelegation<TPC, T, R>,
+    TPC: AwaitableConsumer<T>,
+    T: StaticTaskItem,
+    R: StaticTaskItem,
+>(
+    handler: &H,
+    pc: &TPC,
+    item: &T,
+    token: &CancellationToken,
+    timeout: Option<Duration>,
+) -> Result<R> {
+    let Some(timeout) = timeout else {
+        return handler.process(pc, item, &token.child());
+    };
+
+    // A blocking call cannot be interrupted, so the handler runs on the worker and sees the
+    // timeout through its token. The item keeps its key and worker until it really returns, and
+    // whatever it returns after the deadline is reported as timed out.
+    let attempt = token.child_with_timeout(timeout);
+    let result = handler.process(pc, item, &attempt);
+
+    if attempt.is_expired() {
+        return Err(RmxError::Timeout);
+    }
+
+    result
+}
+
+pub(super) async fn process_with_timeout_async<
+    H: AsyncTaskDelegation<TPC, T, R>,
+    TPC: AwaitableConsumer<T>,
+    T: StaticTaskItem,
+    R: StaticTaskItem,
+>(
+    handler: &H,
+    pc: &TPC,
+    item: &T,
+    token: &CancellationToken,
+    timeout: Option<Duration>,
+) -> Result<R> {
+    let Some(timeout) = timeout else {
+        return handler.process(pc, item, &token.child()).await;
+    };
+
+    // Dropping the future stops the task, the token only tells whatever it started.
+    let attempt = token.child_with_timeout(timeout);
+
+    match time::timeout(timeout, handler.process(pc, item, &attempt)).await {
+        Ok(_) if attempt.is_expired() => Err(RmxError::Timeout),
+        Ok(result) => result,
+        Err(_) => {
+            attempt.cancel();
+            Err(RmxError::Timeout)
+        }
+    }
+}
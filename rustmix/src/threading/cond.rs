@@ -3,11 +3,14 @@ use std::{
     time::{Duration, Instant},
 };
 
+// The notifications stick around until the next wait consumes them, and wait_while ignores them.
+#[deprecated(note = "use Event, which has explicit auto and manual reset modes and async waits")]
 #[derive(Clone)]
 pub struct Mutcond {
     pair: Arc<(Mutex<bool>, Condvar)>,
 }
 
+#[allow(deprecated)]
 impl Mutcond {
     pub fn new() -> Self {
         Self {
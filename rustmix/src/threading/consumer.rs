@@ -1,37 +1,60 @@
-use crossbeam::queue::SegQueue;
+use futures::{FutureExt, Stream};
+use indicatif::ProgressBar;
+use serde::{de::DeserializeOwned, Serialize};
 use std::{
     mem,
+    path::Path,
     sync::{
-        atomic::{AtomicBool, AtomicUsize, Ordering},
+        atomic::{AtomicBool, AtomicU64, AtomicUsize, Ordering},
         Arc, Mutex,
     },
     thread,
 };
 use tokio::{
-    sync::Notify,
-    time::{Duration, Instant},
+    runtime::Handle,
+    time::{self, Duration},
 };
 
-use super::{cond::Mutcond, *};
+use super::*;
 use crate::{error::*, Result};
 
 #[derive(Clone, PartialEq, Eq)]
 pub struct ConsumerOptions {
+    pub behavior: QueueBehavior,
     pub threads: usize,
     pub threshold: Duration,
     pub sleep_after_send: Duration,
     pub peek_timeout: Duration,
     pub pause_timeout: Duration,
+    // Items that run longer are reported as timed out. Async handlers are dropped at the deadline,
+    // sync ones are only told through their token.
+    pub item_timeout: Option<Duration>,
+    pub retry: Option<RetryPolicy>,
+    pub results: Option<ResultsOrder>,
+    pub rate_limit: Option<RateLimit>,
+    pub dedup: Option<Dedup>,
+    pub batch: Option<Batch>,
+    pub autoscale: Option<AutoScale>,
+    pub journal_sync: JournalSync,
 }
 
 impl Default for ConsumerOptions {
     fn default() -> Self {
         ConsumerOptions {
+            behavior: QUEUE_BEHAVIOR_DEF,
             threads: THREADS_DEF.clamp(THREADS_MIN, THREADS_MAX),
             threshold: THRESHOLD_DEF,
             sleep_after_send: SLEEP_AFTER_SEND_DEF,
             peek_timeout: PEEK_TIMEOUT_DEF.clamp(PEEK_TIMEOUT_MIN, PEEK_TIMEOUT_MAX),
             pause_timeout: PAUSE_TIMEOUT_DEF.clamp(PAUSE_TIMEOUT_MIN, PAUSE_TIMEOUT_MAX),
+            item_timeout: None,
+            retry: None,
+            results: None,
+            rate_limit: None,
+            dedup: None,
+            batch: None,
+            autoscale: None,
+            journal_sync: JournalSync::default(),
         }
     }
 }
@@ -41,6 +64,13 @@ impl ConsumerOptions {
         Default::default()
     }
 
+    pub fn with_behavior(&self, behavior: QueueBehavior) -> Self {
+        ConsumerOptions {
+            behavior,
+            ..self.clone()
+        }
+    }
+
     pub fn with_threads(&self, threads: usize) -> Self {
         ConsumerOptions {
             threads: threads.clamp(THREADS_MIN, THREADS_MAX),
@@ -61,56 +91,147 @@ impl ConsumerOptions {
             ..self.clone()
         }
     }
+
+    pub fn with_item_timeout(&self, item_timeout: Duration) -> Self {
+        ConsumerOptions {
+            item_timeout: Some(item_timeout),
+            ..self.clone()
+        }
+    }
+
+    pub fn with_retry(&self, retry: RetryPolicy) -> Self {
+        ConsumerOptions {
+            retry: Some(retry),
+            ..self.clone()
+        }
+    }
+
+    pub fn with_results(&self, order: ResultsOrder) -> Self {
+        ConsumerOptions {
+            results: Some(order),
+            ..self.clone()
+        }
+    }
+
+    pub fn with_rate_limit(&self, rate_limit: RateLimit) -> Self {
+        ConsumerOptions {
+            rate_limit: Some(rate_limit),
+            ..self.clone()
+        }
+    }
+
+    pub fn with_dedup(&self, dedup: Dedup) -> Self {
+        ConsumerOptions {
+            dedup: Some(dedup),
+            ..self.clone()
+        }
+    }
+
+    pub fn with_batch(&self, batch: Batch) -> Self {
+        ConsumerOptions {
+            batch: Some(batch),
+            ..self.clone()
+        }
+    }
+
+    pub fn with_autoscale(&self, autoscale: AutoScale) -> Self {
+        ConsumerOptions {
+            autoscale: Some(autoscale),
+            ..self.clone()
+        }
+    }
+
+    pub fn with_journal_sync(&self, journal_sync: JournalSync) -> Self {
+        ConsumerOptions {
+            journal_sync,
+            ..self.clone()
+        }
+    }
 }
 
 #[derive(Clone, Debug)]
-pub struct Consumer<T: StaticTaskItem> {
+pub struct Consumer<T: StaticTaskItem, R: StaticTaskItem = ()> {
     pub options: ConsumerOptions,
-    items: Arc<SegQueue<T>>,
-    items_cond: Arc<Mutcond>,
+    items: Arc<ItemQueue<T>>,
+    items_event: Event,
     started: Arc<Mutex<bool>>,
     finished: Arc<AtomicBool>,
-    finished_cond: Arc<Mutcond>,
-    finished_noti: Arc<Notify>,
+    finished_event: Event,
     completed: Arc<AtomicBool>,
     paused: Arc<AtomicBool>,
     cancelled: Arc<AtomicBool>,
+    token: CancellationToken,
     consumers: Arc<AtomicUsize>,
+    pool: WorkerPool,
     running: Arc<AtomicUsize>,
+    dead_letters: DeadLetterQueue<T>,
+    results: TaskResults<T, R>,
+    rate_limiter: RateLimiter<T>,
+    dedup: Deduplicator<T>,
+    key_locks: KeyedLocks<T>,
+    stats: TaskStats,
+    events: QueueEvents<T, R>,
+    journal: Option<TaskJournal<T>>,
+    submitted: Arc<AtomicU64>,
 }
 
-impl<T: StaticTaskItem> Consumer<T> {
+impl<T: StaticTaskItem, R: StaticTaskItem> Consumer<T, R> {
     pub fn new() -> Self {
         Consumer {
             options: Default::default(),
-            items: Arc::new(SegQueue::new()),
-            items_cond: Arc::new(Mutcond::new()),
+            items: Arc::new(ItemQueue::new(QUEUE_BEHAVIOR_DEF)),
+            items_event: Event::default(),
             started: Arc::new(Mutex::new(false)),
             finished: Arc::new(AtomicBool::new(false)),
-            finished_cond: Arc::new(Mutcond::new()),
-            finished_noti: Arc::new(Notify::new()),
+            finished_event: Event::manual_reset(),
             completed: Arc::new(AtomicBool::new(false)),
             paused: Arc::new(AtomicBool::new(false)),
             cancelled: Arc::new(AtomicBool::new(false)),
+            token: CancellationToken::new(),
             consumers: Arc::new(AtomicUsize::new(0)),
+            pool: WorkerPool::new(THREADS_DEF),
             running: Arc::new(AtomicUsize::new(0)),
+            dead_letters: DeadLetterQueue::new(),
+            results: TaskResults::new(None),
+            rate_limiter: RateLimiter::new(None),
+            dedup: Deduplicator::new(None),
+            key_locks: KeyedLocks::new(),
+            stats: TaskStats::new(),
+            events: QueueEvents::new(),
+            journal: None,
+            submitted: Arc::new(AtomicU64::new(0)),
         }
     }
 
     pub fn with_options(options: ConsumerOptions) -> Self {
+        let items = Arc::new(ItemQueue::new(options.behavior));
+        let results = TaskResults::new(options.results);
+        let rate_limiter = RateLimiter::new(options.rate_limit);
+        let dedup = Deduplicator::new(options.dedup);
+        let pool = WorkerPool::new(options.threads);
         Consumer {
             options,
-            items: Arc::new(SegQueue::new()),
-            items_cond: Arc::new(Mutcond::new()),
+            items,
+            items_event: Event::default(),
             started: Arc::new(Mutex::new(false)),
             finished: Arc::new(AtomicBool::new(false)),
-            finished_cond: Arc::new(Mutcond::new()),
-            finished_noti: Arc::new(Notify::new()),
+            finished_event: Event::manual_reset(),
             completed: Arc::new(AtomicBool::new(false)),
             paused: Arc::new(AtomicBool::new(false)),
             cancelled: Arc::new(AtomicBool::new(false)),
+            token: CancellationToken::new(),
             consumers: Arc::new(AtomicUsize::new(0)),
+            pool,
             running: Arc::new(AtomicUsize::new(0)),
+            dead_letters: DeadLetterQueue::new(),
+            results,
+            rate_limiter,
+            dedup,
+            key_locks: KeyedLocks::new(),
+            stats: TaskStats::new(),
+            events: QueueEvents::new(),
+            journal: None,
+            submitted: Arc::new(AtomicU64::new(0)),
         }
     }
 
@@ -120,6 +241,11 @@ impl<T: StaticTaskItem> Consumer<T> {
 
     fn set_started(&self, value: bool) -> bool {
         let mut started = self.started.lock().unwrap();
+
+        if *started && value {
+            return false;
+        }
+
         *started = value;
         true
     }
@@ -136,6 +262,10 @@ impl<T: StaticTaskItem> Consumer<T> {
         self.cancelled.load(Ordering::SeqCst)
     }
 
+    pub fn token(&self) -> &CancellationToken {
+        &self.token
+    }
+
     pub fn is_finished(&self) -> bool {
         self.finished.load(Ordering::SeqCst)
     }
@@ -161,8 +291,9 @@ impl<T: StaticTaskItem> Consumer<T> {
     }
 
     fn dec_consumers(&self) -> bool {
-        self.consumers.fetch_sub(1, Ordering::SeqCst);
-        self.consumers() == 0 && (self.is_completed() || self.is_cancelled())
+        self.pool.exit(&self.consumers, || {
+            self.is_completed() || self.is_cancelled()
+        })
     }
 
     fn finish(&self) {
@@ -170,11 +301,15 @@ impl<T: StaticTaskItem> Consumer<T> {
             return;
         }
 
+        journal_finished(self.journal.as_ref(), self.is_cancelled());
         self.completed.store(true, Ordering::SeqCst);
         self.finished.store(true, Ordering::SeqCst);
         self.set_started(false);
-        self.finished_cond.notify_all();
-        self.finished_noti.notify_waiters();
+        self.stats.finish();
+        self.results.close();
+        self.pool.clear_spawner();
+        self.events.emit(|| QueueEvent::Finished);
+        self.finished_event.set();
         thread::sleep(Duration::ZERO);
     }
 
@@ -182,15 +317,59 @@ impl<T: StaticTaskItem> Consumer<T> {
         self.running.load(Ordering::SeqCst)
     }
 
-    fn inc_running(&self) {
-        self.running.fetch_add(1, Ordering::SeqCst);
+    pub fn threads(&self) -> usize {
+        self.pool.threads()
+    }
+
+    pub fn set_threads(&self, threads: usize) {
+        self.pool.resize(threads, &self.consumers);
+    }
+
+    pub fn dead_letters(&self) -> &DeadLetterQueue<T> {
+        &self.dead_letters
+    }
+
+    pub fn results(&self) -> &TaskResults<T, R> {
+        &self.results
     }
 
-    fn dec_running(&self) {
-        self.running.fetch_sub(1, Ordering::SeqCst);
+    pub fn rate_limiter(&self) -> &RateLimiter<T> {
+        &self.rate_limiter
     }
 
-    pub fn start<H: TaskDelegation<Consumer<T>, T>>(&self, handler: &H) -> Result<()> {
+    pub fn deduplicator(&self) -> &Deduplicator<T> {
+        &self.dedup
+    }
+
+    pub fn key_locks(&self) -> &KeyedLocks<T> {
+        &self.key_locks
+    }
+
+    pub fn stats(&self) -> QueueStats {
+        self.stats.snapshot(self.len(), self.running())
+    }
+
+    pub fn events(&self) -> &QueueEvents<T, R> {
+        &self.events
+    }
+
+    pub fn subscribe(&self) -> QueueEventReceiver<T, R> {
+        self.events.subscribe()
+    }
+
+    pub fn set_progress(&self, pb: ProgressBar) {
+        self.stats.set_progress(pb);
+    }
+
+    pub fn clear_progress(&self) {
+        self.stats.clear_progress();
+    }
+
+    pub fn journal(&self) -> Option<&TaskJournal<T>> {
+        self.journal.as_ref()
+    }
+
+    pub fn start<H: TaskDelegation<Consumer<T, R>, T, R>>(&self, handler: &H) -> Result<()> {
         if self.is_cancelled() {
             return Err(CanceledError.into());
         }
@@ -203,168 +382,427 @@ impl<T: StaticTaskItem> Consumer<T> {
             return Err(QueueStartedError.into());
         }
 
-        self.set_consumers(self.options.threads);
+        self.set_consumers(self.threads());
+        self.stats.start();
         handler.on_started(self);
+        self.events.emit(|| QueueEvent::Started);
+
+        let this = self.clone();
+        let spawner = handler.clone();
+        self.pool.set_spawner(move || this.spawn_consumer(&spawner));
+
+        for _ in 0..self.threads() {
+            self.spawn_consumer(handler);
+        }
+
+        if let Some(scale) = self.options.autoscale {
+            autoscale(self, scale, &self.finished_event);
+        }
+
+        Ok(())
+    }
 
-        for _ in 0..self.options.threads {
-            let this = self.clone();
-            let handler = handler.clone();
-            thread::spawn(move || {
-                if this.options.threshold.is_zero() {
-                    loop {
-                        if this.is_cancelled() || (!this.is_busy() && this.is_completed()) {
-                            break;
-                        }
-
-                        if this.is_paused() {
-                            thread::sleep(this.options.pause_timeout);
-                            continue;
-                        }
-
-                        let Some(item) = this.dequeue_wait() else {
-                            continue;
-                        };
-                        this.inc_running();
-                        match handler.process(&this, &item) {
-                            Ok(it) => {
-                                if !handler.on_completed(&this, &item, &it) {
-                                    this.dec_running();
-                                    break;
-                                }
-                            }
-                            Err(e) => {
-                                if !handler.on_completed(
-                                    &this,
-                                    &item,
-                                    &TaskResult::Error(e.get_message()),
-                                ) {
-                                    this.dec_running();
-                                    break;
-                                }
-                            }
-                        }
-                        this.dec_running();
-                    }
-
-                    if !this.dec_consumers() {
-                        return;
-                    }
-
-                    if this.is_cancelled() {
-                        handler.on_cancelled(&this);
-                    } else {
-                        handler.on_finished(&this);
-                    }
-
-                    this.finish();
+    fn spawn_consumer<H: TaskDelegation<Consumer<T, R>, T, R>>(&self, handler: &H) {
+        let this = self.clone();
+        let handler = handler.clone();
+        thread::spawn(move || {
+            loop {
+                if this.pool.retire(&this.consumers) {
                     return;
                 }
 
-                loop {
-                    if this.is_cancelled() || (!this.is_busy() && this.is_completed()) {
-                        break;
-                    }
-
-                    if this.is_paused() {
-                        thread::sleep(this.options.pause_timeout);
-                        continue;
-                    }
-
-                    let Some(item) = this.dequeue_wait() else {
-                        continue;
-                    };
-                    this.inc_running();
-                    match handler.process(&this, &item) {
-                        Ok(it) => {
-                            if !handler.on_completed(&this, &item, &it) {
-                                this.dec_running();
-                                break;
-                            }
-
-                            if !this.options.threshold.is_zero() {
-                                let time = Instant::now();
-
-                                if time.elapsed() < this.options.threshold {
-                                    let remaining = this.options.threshold - time.elapsed();
-                                    thread::sleep(remaining);
-                                }
-                            }
-                        }
-                        Err(e) => {
-                            if !handler.on_completed(
-                                &this,
-                                &item,
-                                &TaskResult::Error(e.get_message()),
-                            ) {
-                                this.dec_running();
-                                break;
-                            }
-                        }
-                    }
-                    this.dec_running();
+                if this.is_cancelled() || (!this.is_busy() && this.is_completed()) {
+                    break;
                 }
 
-                if !this.dec_consumers() {
-                    return;
+                if this.is_paused() {
+                    thread::sleep(this.options.pause_timeout);
+                    continue;
                 }
 
-                if this.is_cancelled() {
-                    handler.on_cancelled(&this);
-                } else {
-                    handler.on_finished(&this);
+                let Some(QueueItem { index, item, .. }) = this.deq(true) else {
+                    continue;
+                };
+
+                if !run_item(&this, &handler, index, item) {
+                    break;
                 }
+            }
+
+            if !this.dec_consumers() {
+                return;
+            }
+
+            if this.is_cancelled() {
+                handler.on_cancelled(&this);
+            } else {
+                handler.on_finished(&this);
+            }
+
+            this.finish();
+        });
+    }
+
+    pub fn start_async<H: AsyncTaskDelegation<Consumer<T, R>, T, R>>(
+        &self,
+        handler: &H,
+    ) -> Result<()> {
+        if self.is_cancelled() {
+            return Err(CanceledError.into());
+        }
+
+        if self.is_completed() && self.is_empty() {
+            return Err(QueueCompletedError.into());
+        }
+
+        let runtime = runtime_handle()?;
+
+        if !self.set_started(true) {
+            return Err(QueueStartedError.into());
+        }
+
+        self.set_consumers(self.threads());
+        self.stats.start();
+        handler.on_started(self);
+        self.events.emit(|| QueueEvent::Started);
+
+        let this = self.clone();
+        let spawner = handler.clone();
+        let handle = runtime.clone();
+        self.pool
+            .set_spawner(move || this.spawn_consumer_async(&spawner, &handle));
+
+        for _ in 0..self.threads() {
+            self.spawn_consumer_async(handler, &runtime);
+        }
 
-                this.finish();
-            });
+        if let Some(scale) = self.options.autoscale {
+            autoscale(self, scale, &self.finished_event);
         }
 
         Ok(())
     }
 
-    pub fn enqueue(&self, item: T) -> Result<()> {
+    fn spawn_consumer_async<H: AsyncTaskDelegation<Consumer<T, R>, T, R>>(
+        &self,
+        handler: &H,
+        runtime: &Handle,
+    ) {
+        let this = self.clone();
+        let handler = handler.clone();
+        runtime.spawn(async move {
+            loop {
+                if this.pool.retire(&this.consumers) {
+                    return;
+                }
+
+                if this.is_cancelled() || (!this.is_busy() && this.is_completed()) {
+                    break;
+                }
+
+                if this.is_paused() {
+                    time::sleep(this.options.pause_timeout).await;
+                    continue;
+                }
+
+                let Some(QueueItem { index, item, .. }) = this.deq(false) else {
+                    time::sleep(this.options.peek_timeout).await;
+                    continue;
+                };
+
+                if !run_item_async(&this, &handler, index, item).await {
+                    break;
+                }
+            }
+
+            if !this.dec_consumers() {
+                return;
+            }
+
+            if this.is_cancelled() {
+                handler.on_cancelled(&this);
+            } else {
+                handler.on_finished(&this);
+            }
+
+            this.finish();
+        });
+    }
+
+    pub fn start_batch<H: BatchTaskDelegation<Consumer<T, R>, T, R>>(
+        &self,
+        handler: &H,
+    ) -> Result<()> {
         if self.is_cancelled() {
             return Err(CanceledError.into());
         }
 
-        if self.is_completed() {
+        if self.is_completed() && self.is_empty() {
             return Err(QueueCompletedError.into());
         }
 
-        self.items.push(item);
+        if !self.set_started(true) {
+            return Err(QueueStartedError.into());
+        }
 
-        if !self.options.sleep_after_send.is_zero() {
-            thread::sleep(self.options.sleep_after_send);
+        self.set_consumers(self.threads());
+        self.stats.start();
+        handler.on_started(self);
+        self.events.emit(|| QueueEvent::Started);
+
+        let this = self.clone();
+        let spawner = handler.clone();
+        self.pool
+            .set_spawner(move || this.spawn_batch_consumer(&spawner));
+
+        for _ in 0..self.threads() {
+            self.spawn_batch_consumer(handler);
+        }
+
+        if let Some(scale) = self.options.autoscale {
+            autoscale(self, scale, &self.finished_event);
         }
 
-        self.items_cond.notify_all();
         Ok(())
     }
 
-    pub fn dequeue(&self) -> Option<T> {
-        self.deq(false)
+    fn spawn_batch_consumer<H: BatchTaskDelegation<Consumer<T, R>, T, R>>(&self, handler: &H) {
+        let this = self.clone();
+        let handler = handler.clone();
+        let batch = self.options.batch.unwrap_or_default();
+        thread::spawn(move || {
+            loop {
+                if this.pool.retire(&this.consumers) {
+                    return;
+                }
+
+                if this.is_cancelled() || (!this.is_busy() && this.is_completed()) {
+                    break;
+                }
+
+                if this.is_paused() {
+                    thread::sleep(this.options.pause_timeout);
+                    continue;
+                }
+
+                let Some(first) = this.deq(true) else {
+                    continue;
+                };
+                let mut items = vec![first];
+                fill_batch(&mut items, &batch, || this.deq(false));
+
+                if !run_batch(&this, &handler, items) {
+                    break;
+                }
+            }
+
+            if !this.dec_consumers() {
+                return;
+            }
+
+            if this.is_cancelled() {
+                handler.on_cancelled(&this);
+            } else {
+                handler.on_finished(&this);
+            }
+
+            this.finish();
+        });
     }
 
-    pub fn dequeue_wait(&self) -> Option<T> {
-        self.deq(true)
+    pub fn start_batch_async<H: AsyncBatchTaskDelegation<Consumer<T, R>, T, R>>(
+        &self,
+        handler: &H,
+    ) -> Result<()> {
+        if self.is_cancelled() {
+            return Err(CanceledError.into());
+        }
+
+        if self.is_completed() && self.is_empty() {
+            return Err(QueueCompletedError.into());
+        }
+
+        let runtime = runtime_handle()?;
+
+        if !self.set_started(true) {
+            return Err(QueueStartedError.into());
+        }
+
+        self.set_consumers(self.threads());
+        self.stats.start();
+        handler.on_started(self);
+        self.events.emit(|| QueueEvent::Started);
+
+        let this = self.clone();
+        let spawner = handler.clone();
+        let handle = runtime.clone();
+        self.pool
+            .set_spawner(move || this.spawn_batch_consumer_async(&spawner, &handle));
+
+        for _ in 0..self.threads() {
+            self.spawn_batch_consumer_async(handler, &runtime);
+        }
+
+        if let Some(scale) = self.options.autoscale {
+            autoscale(self, scale, &self.finished_event);
+        }
+
+        Ok(())
     }
 
-    fn deq(&self, wait_for_item: bool) -> Option<T> {
-        if wait_for_item {
-            while self.items.is_empty() && !self.is_cancelled() && !self.is_completed() {
-                if !self
-                    .items_cond
-                    .wait_timeout(self.options.peek_timeout)
-                    .unwrap()
-                {
+    fn spawn_batch_consumer_async<H: AsyncBatchTaskDelegation<Consumer<T, R>, T, R>>(
+        &self,
+        handler: &H,
+        runtime: &Handle,
+    ) {
+        let this = self.clone();
+        let handler = handler.clone();
+        let batch = self.options.batch.unwrap_or_default();
+        runtime.spawn(async move {
+            loop {
+                if this.pool.retire(&this.consumers) {
+                    return;
+                }
+
+                if this.is_cancelled() || (!this.is_busy() && this.is_completed()) {
+                    break;
+                }
+
+                if this.is_paused() {
+                    time::sleep(this.options.pause_timeout).await;
                     continue;
                 }
 
-                if self.is_cancelled() || self.is_completed() {
-                    return None;
+                let Some(first) = this.deq(false) else {
+                    time::sleep(this.options.peek_timeout).await;
+                    continue;
+                };
+                let mut items = vec![first];
+                fill_batch_async(&mut items, &batch, || this.deq(false)).await;
+
+                if !run_batch_async(&this, &handler, items).await {
+                    break;
                 }
+            }
 
-                return self.items.pop();
+            if !this.dec_consumers() {
+                return;
             }
+
+            if this.is_cancelled() {
+                handler.on_cancelled(&this);
+            } else {
+                handler.on_finished(&this);
+            }
+
+            this.finish();
+        });
+    }
+
+    pub fn enqueue(&self, item: T) -> Result<()> {
+        self.submit(item, PRIORITY_DEF)?;
+
+        if !self.options.sleep_after_send.is_zero() {
+            thread::sleep(self.options.sleep_after_send);
+        }
+
+        Ok(())
+    }
+
+    // Fails unless the queue has the Priority behavior, the others would drop the priority.
+    pub fn enqueue_with_priority(&self, item: T, priority: i32) -> Result<()> {
+        check_priority(self.options.behavior)?;
+        self.submit(item, priority)?;
+
+        if !self.options.sleep_after_send.is_zero() {
+            thread::sleep(self.options.sleep_after_send);
+        }
+
+        Ok(())
+    }
+
+    pub async fn enqueue_async(&self, item: T) -> Result<()> {
+        self.submit(item, PRIORITY_DEF)?;
+
+        if !self.options.sleep_after_send.is_zero() {
+            time::sleep(self.options.sleep_after_send).await;
+        }
+
+        Ok(())
+    }
+
+    pub async fn enqueue_with_priority_async(&self, item: T, priority: i32) -> Result<()> {
+        check_priority(self.options.behavior)?;
+        self.submit(item, priority)?;
+
+        if !self.options.sleep_after_send.is_zero() {
+            time::sleep(self.options.sleep_after_send).await;
+        }
+
+        Ok(())
+    }
+
+    pub async fn enqueue_all(&self, items: impl Stream<Item = T>) -> Result<usize> {
+        enqueue_stream(items, |item| self.enqueue_async(item)).await
+    }
+
+    pub fn sink(&self) -> QueueSink<T> {
+        let this = self.clone();
+        let completer = self.clone();
+        QueueSink::new(
+            move |item| {
+                let this = this.clone();
+                async move { this.enqueue_async(item).await }.boxed()
+            },
+            move || completer.complete(),
+        )
+    }
+
+    pub fn stream(&self) -> TaskOutputStream<T, R> {
+        self.results.subscribe()
+    }
+
+    fn submit(&self, item: T, priority: i32) -> Result<()> {
+        if self.is_cancelled() {
+            return Err(CanceledError.into());
+        }
+
+        if self.is_completed() {
+            return Err(QueueCompletedError.into());
+        }
+
+        // Duplicates are dropped quietly, the deduplicator keeps count of them.
+        if self.dedup.is_duplicate(&item) {
+            return Ok(());
+        }
+
+        let index = self.submitted.fetch_add(1, Ordering::SeqCst);
+        let item = QueueItem::with_priority(index, item, priority);
+        journal_enqueued(self.journal.as_ref(), &item)?;
+        self.stats.enqueued();
+        self.events.emit(|| QueueEvent::Enqueued {
+            index: item.index,
+            item: item.item.clone(),
+        });
+        self.items.push(item);
+        self.items_event.notify();
+        Ok(())
+    }
+
+    pub fn dequeue(&self) -> Option<T> {
+        self.deq(false).map(|it| it.item)
+    }
+
+    pub fn dequeue_wait(&self) -> Option<T> {
+        self.deq(true).map(|it| it.item)
+    }
+
+    fn deq(&self, wait_for_item: bool) -> Option<QueueItem<T>> {
+        if wait_for_item {
+            self.items_event.wait_timeout_while(
+                || self.items.is_empty() && !self.is_cancelled() && !self.is_completed(),
+                self.options.peek_timeout,
+            );
         }
 
         if self.items.is_empty() || self.is_cancelled() {
@@ -375,7 +813,10 @@ impl<T: StaticTaskItem> Consumer<T> {
     }
 
     pub fn clear(&mut self) {
-        self.items = mem::replace(&mut self.items, Arc::new(SegQueue::new()));
+        self.items = mem::replace(
+            &mut self.items,
+            Arc::new(ItemQueue::new(self.options.behavior)),
+        );
     }
 
     pub fn stop(&self, enforce: bool) {
@@ -387,71 +828,159 @@ impl<T: StaticTaskItem> Consumer<T> {
     }
 
     pub fn complete(&self) {
-        self.completed.store(true, Ordering::SeqCst);
-        self.items_cond.notify_all();
+        if !self.completed.swap(true, Ordering::SeqCst) {
+            self.events.emit(|| QueueEvent::Completed);
+        }
+        self.items_event.notify();
     }
 
     pub fn cancel(&self) {
-        self.cancelled.store(true, Ordering::SeqCst);
-        self.items_cond.notify_all();
+        if !self.cancelled.swap(true, Ordering::SeqCst) {
+            self.events.emit(|| QueueEvent::Cancelled);
+        }
+        self.token.cancel();
+        self.items_event.notify();
+        self.finished_event.notify();
     }
 
     pub fn pause(&self) {
-        self.paused.store(true, Ordering::SeqCst);
+        if !self.paused.swap(true, Ordering::SeqCst) {
+            self.events.emit(|| QueueEvent::Paused);
+        }
     }
 
     pub fn resume(&self) {
-        self.paused.store(false, Ordering::SeqCst);
-        self.items_cond.notify_all();
+        if self.paused.swap(false, Ordering::SeqCst) {
+            self.events.emit(|| QueueEvent::Resumed);
+        }
+        self.items_event.notify();
     }
 
     pub fn wait(&self) -> Result<()> {
-        wait(self, &self.finished_cond)
+        wait(self, &self.finished_event)
     }
 
     pub async fn wait_async(&self) -> Result<()> {
-        wait_async(self, &self.finished_noti).await
+        wait_async(self, &self.finished_event).await
     }
 
-    pub fn wait_until(&self, cond: impl Fn(&Consumer<T>) -> bool) -> Result<()> {
-        wait_until(self, &self.finished_cond, cond)
+    pub fn wait_until(&self, cond: impl Fn(&Consumer<T, R>) -> bool) -> Result<()> {
+        wait_until(self, &self.finished_event, cond)
     }
 
     pub async fn wait_until_async(
         &self,
-        cond: impl Fn(&Consumer<T>) -> Pin<Box<dyn Future<Output = bool> + Send>>,
+        cond: impl Fn(&Consumer<T, R>) -> Pin<Box<dyn Future<Output = bool> + Send>>,
     ) -> Result<()> {
-        wait_until_async(self, &self.finished_noti, cond).await
+        wait_until_async(self, &self.finished_event, cond).await
     }
 
     pub fn wait_for(&self, timeout: Duration) -> Result<()> {
-        wait_for(self, timeout, &self.finished_cond)
+        wait_for(self, timeout, &self.finished_event)
     }
 
     pub async fn wait_for_async(&self, timeout: Duration) -> Result<()> {
-        wait_for_async(self, timeout, &self.finished_noti).await
+        wait_for_async(self, timeout, &self.finished_event).await
     }
 
     pub fn wait_for_until(
         &self,
         timeout: Duration,
-        cond: impl Fn(&Consumer<T>) -> bool,
+        cond: impl Fn(&Consumer<T, R>) -> bool,
     ) -> Result<()> {
-        wait_for_until(self, timeout, &self.finished_cond, cond)
+        wait_for_until(self, timeout, &self.finished_event, cond)
     }
 
     pub async fn wait_for_until_async<
-        F: Fn(&Consumer<T>) -> Pin<Box<dyn Future<Output = bool> + Send>>,
+        F: Fn(&Consumer<T, R>) -> Pin<Box<dyn Future<Output = bool> + Send>>,
     >(
         &self,
         timeout: Duration,
         cond: F,
     ) -> Result<()> {
-        wait_for_until_async(self, timeout, &self.finished_noti, cond).await
+        wait_for_until_async(self, timeout, &self.finished_event, cond).await
+    }
+}
+
+impl<T: StaticTaskItem + Serialize + DeserializeOwned, R: StaticTaskItem> Consumer<T, R> {
+    // Starts a new journal, an existing file at the path is truncated. Only resume_from continues
+    // an old journal.
+    pub fn with_journal<P: AsRef<Path>>(options: ConsumerOptions, path: P) -> Result<Self> {
+        let sync = options.journal_sync;
+        let mut this = Self::with_options(options);
+        this.journal = Some(TaskJournal::create(path)?.with_sync(sync));
+        Ok(this)
+    }
+
+    pub fn resume_from<P: AsRef<Path>>(path: P, options: ConsumerOptions) -> Result<Self> {
+        let (items, next) = TaskJournal::<T>::compact(&path)?;
+        let sync = options.journal_sync;
+        let mut this = Self::with_options(options);
+        this.journal = Some(TaskJournal::open(path)?.with_sync(sync));
+        this.submitted.store(next, Ordering::SeqCst);
+
+        for item in items {
+            this.dedup.remember(&item.item);
+            this.stats.enqueued();
+            this.items.push(item);
+        }
+
+        this.items_event.notify();
+
+        Ok(this)
+    }
+}
+
+impl<T: StaticTaskItem, R: StaticTaskItem> ScalableConsumer for Consumer<T, R> {
+    fn is_cancelled(&self) -> bool {
+        Consumer::is_cancelled(self)
+    }
+
+    fn is_finished(&self) -> bool {
+        Consumer::is_finished(self)
+    }
+
+    fn len(&self) -> usize {
+        Consumer::len(self)
+    }
+
+    fn running(&self) -> usize {
+        Consumer::running(self)
+    }
+
+    fn threads(&self) -> usize {
+        Consumer::threads(self)
+    }
+
+    fn set_threads(&self, threads: usize) {
+        Consumer::set_threads(self, threads)
+    }
+
+    fn stats(&self) -> QueueStats {
+        Consumer::stats(self)
+    }
+}
+
+impl<T: StaticTaskItem, R: StaticTaskItem> WorkerQueue<T, R> for Consumer<T, R> {
+    fn parts(&self) -> WorkerParts<'_, T, R> {
+        WorkerParts {
+            token: &self.token,
+            key_locks: &self.key_locks,
+            rate_limiter: &self.rate_limiter,
+            retry: self.options.retry.as_ref(),
+            dead_letters: &self.dead_letters,
+            item_timeout: self.options.item_timeout,
+            threshold: self.options.threshold,
+            results: &self.results,
+            stats: &self.stats,
+            journal: self.journal.as_ref(),
+            events: &self.events,
+            running: &self.running,
+        }
     }
 }
 
-impl<T: StaticTaskItem> AwaitableConsumer<T> for Consumer<T> {
+impl<T: StaticTaskItem, R: StaticTaskItem> AwaitableConsumer<T> for Consumer<T, R> {
     fn is_cancelled(&self) -> bool {
         Consumer::is_cancelled(self)
     }
@@ -0,0 +1,197 @@
+use std::{
+    fmt,
+    sync::{Arc, Condvar, Mutex},
+};
+use tokio::{
+    sync::Notify,
+    time::{self, Duration},
+};
+
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum EventMode {
+    // Stays set until reset, releasing every waiter.
+    #[default]
+    ManualReset,
+    // Releases a single waiter and resets itself.
+    AutoReset,
+}
+
+impl fmt::Display for EventMode {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            EventMode::ManualReset => write!(f, "ManualReset"),
+            EventMode::AutoReset => write!(f, "AutoReset"),
+        }
+    }
+}
+
+#[derive(Debug, Default)]
+struct EventState {
+    mode: EventMode,
+    signaled: Mutex<bool>,
+    cond: Condvar,
+    notify: Notify,
+}
+
+// An event that can be waited on from threads and tasks alike. The predicate waits are
+// re-checked every time the event is set or notified, so the state they look at has to be
+// changed before calling either.
+#[derive(Clone, Default)]
+pub struct Event {
+    state: Arc<EventState>,
+}
+
+impl fmt::Debug for Event {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("Event")
+            .field("mode", &self.state.mode)
+            .field("set", &self.is_set())
+            .finish()
+    }
+}
+
+impl Event {
+    pub fn new(mode: EventMode) -> Self {
+        Self::with_state(mode, false)
+    }
+
+    pub fn with_state(mode: EventMode, signaled: bool) -> Self {
+        Event {
+            state: Arc::new(EventState {
+                mode,
+                signaled: Mutex::new(signaled),
+                cond: Condvar::new(),
+                notify: Notify::new(),
+            }),
+        }
+    }
+
+    pub fn manual_reset() -> Self {
+        Self::new(EventMode::ManualReset)
+    }
+
+    pub fn auto_reset() -> Self {
+        Self::new(EventMode::AutoReset)
+    }
+
+    pub fn mode(&self) -> EventMode {
+        self.state.mode
+    }
+
+    pub fn is_set(&self) -> bool {
+        *self.state.signaled.lock().unwrap()
+    }
+
+    pub fn set(&self) {
+        let mut signaled = self.state.signaled.lock().unwrap();
+        *signaled = true;
+        self.state.cond.notify_all();
+        self.state.notify.notify_waiters();
+    }
+
+    pub fn reset(&self) {
+        let mut signaled = self.state.signaled.lock().unwrap();
+        *signaled = false;
+    }
+
+    // Wakes the predicate waiters without changing the state.
+    pub fn notify(&self) {
+        let _guard = self.state.signaled.lock().unwrap();
+        self.state.cond.notify_all();
+        self.state.notify.notify_waiters();
+    }
+
+    fn consume(&self, signaled: &mut bool) -> bool {
+        if !*signaled {
+            return false;
+        }
+
+        if self.state.mode == EventMode::AutoReset {
+            *signaled = false;
+        }
+
+        true
+    }
+
+    pub fn wait(&self) {
+        let mut signaled = self.state.signaled.lock().unwrap();
+
+        while !self.consume(&mut signaled) {
+            signaled = self.state.cond.wait(signaled).unwrap();
+        }
+    }
+
+    pub fn wait_timeout(&self, timeout: Duration) -> bool {
+        let signaled = self.state.signaled.lock().unwrap();
+        let (mut signaled, _) = self
+            .state
+            .cond
+            .wait_timeout_while(signaled, timeout, |it| !*it)
+            .unwrap();
+        self.consume(&mut signaled)
+    }
+
+    pub fn wait_while(&self, condition: impl Fn() -> bool) {
+        let mut signaled = self.state.signaled.lock().unwrap();
+
+        while condition() {
+            signaled = self.state.cond.wait(signaled).unwrap();
+        }
+    }
+
+    // Returns false if the condition still holds when the timeout expires.
+    pub fn wait_timeout_while(&self, condition: impl Fn() -> bool, timeout: Duration) -> bool {
+        let signaled = self.state.signaled.lock().unwrap();
+        let (_signaled, result) = self
+            .state
+            .cond
+            .wait_timeout_while(signaled, timeout, |_| condition())
+            .unwrap();
+        !result.timed_out()
+    }
+
+    pub async fn wait_async(&self) {
+        loop {
+            let notified = self.state.notify.notified();
+            tokio::pin!(notified);
+            // Registering before checking the state is what keeps a set() in between from
+            // being missed.
+            notified.as_mut().enable();
+
+            if self.consume(&mut self.state.signaled.lock().unwrap()) {
+                return;
+            }
+
+            notified.await;
+        }
+    }
+
+    pub async fn wait_timeout_async(&self, timeout: Duration) -> bool {
+        time::timeout(timeout, self.wait_async()).await.is_ok()
+    }
+
+    pub async fn wait_while_async(&self, condition: impl Fn() -> bool) {
+        loop {
+            let notified = self.state.notify.notified();
+            tokio::pin!(notified);
+            notified.as_mut().enable();
+
+            if !condition() {
+                return;
+            }
+
+            notified.await;
+        }
+    }
+
+    pub async fn wait_timeout_while_async(
+        &self,
+        condition: impl Fn() -> bool,
+        timeout: Duration,
+    ) -> bool {
+        time::timeout(timeout, self.wait_while_async(&condition))
+            .await
+            .is_ok()
+            || !condition()
+    }
+}
@@ -1,20 +1,26 @@
 use crossbeam::deque::{Injector, Steal, Stealer, Worker};
+use futures::{FutureExt, Stream};
+use indicatif::ProgressBar;
+use serde::{de::DeserializeOwned, Serialize};
 use std::{
     mem,
+    path::Path,
     sync::{
-        atomic::{AtomicBool, AtomicUsize, Ordering},
-        Arc, Mutex,
+        atomic::{AtomicBool, AtomicU64, AtomicUsize, Ordering},
+        Arc, Mutex, OnceLock,
     },
     thread,
 };
 use tokio::{
-    sync::Notify,
-    time::{Duration, Instant},
+    runtime::Handle,
+    time::{self, Duration, Instant},
 };
 
-use super::{cond::Mutcond, *};
+use super::*;
 use crate::{error::*, Result};
 
+const STEAL_BATCH_DEF: usize = 10;
+
 #[derive(Clone, PartialEq, Eq)]
 pub struct InjectorWorkerOptions {
     pub behavior: QueueBehavior,
@@ -22,6 +28,16 @@ pub struct InjectorWorkerOptions {
     pub threshold: Duration,
     pub sleep_after_send: Duration,
     pub pause_timeout: Duration,
+    pub item_timeout: Option<Duration>,
+    pub retry: Option<RetryPolicy>,
+    pub results: Option<ResultsOrder>,
+    pub rate_limit: Option<RateLimit>,
+    pub dedup: Option<Dedup>,
+    pub batch: Option<Batch>,
+    pub autoscale: Option<AutoScale>,
+    // How many items a worker takes at once from the global queue or another worker.
+    pub steal_batch: usize,
+    pub journal_sync: JournalSync,
 }
 
 impl Default for InjectorWorkerOptions {
@@ -32,6 +48,15 @@ impl Default for InjectorWorkerOptions {
             threshold: THRESHOLD_DEF,
             sleep_after_send: SLEEP_AFTER_SEND_DEF,
             pause_timeout: PAUSE_TIMEOUT_DEF.clamp(PAUSE_TIMEOUT_MIN, PAUSE_TIMEOUT_MAX),
+            item_timeout: None,
+            retry: None,
+            results: None,
+            rate_limit: None,
+            dedup: None,
+            batch: None,
+            autoscale: None,
+            steal_batch: STEAL_BATCH_DEF,
+            journal_sync: JournalSync::default(),
         }
     }
 }
@@ -68,59 +93,328 @@ impl InjectorWorkerOptions {
             ..self.clone()
         }
     }
+
+    pub fn with_item_timeout(&self, item_timeout: Duration) -> Self {
+        InjectorWorkerOptions {
+            item_timeout: Some(item_timeout),
+            ..self.clone()
+        }
+    }
+
+    pub fn with_retry(&self, retry: RetryPolicy) -> Self {
+        InjectorWorkerOptions {
+            retry: Some(retry),
+            ..self.clone()
+        }
+    }
+
+    pub fn with_results(&self, order: ResultsOrder) -> Self {
+        InjectorWorkerOptions {
+            results: Some(order),
+            ..self.clone()
+        }
+    }
+
+    pub fn with_rate_limit(&self, rate_limit: RateLimit) -> Self {
+        InjectorWorkerOptions {
+            rate_limit: Some(rate_limit),
+            ..self.clone()
+        }
+    }
+
+    pub fn with_dedup(&self, dedup: Dedup) -> Self {
+        InjectorWorkerOptions {
+            dedup: Some(dedup),
+            ..self.clone()
+        }
+    }
+
+    pub fn with_batch(&self, batch: Batch) -> Self {
+        InjectorWorkerOptions {
+            batch: Some(batch),
+            ..self.clone()
+        }
+    }
+
+    pub fn with_autoscale(&self, autoscale: AutoScale) -> Self {
+        InjectorWorkerOptions {
+            autoscale: Some(autoscale),
+            ..self.clone()
+        }
+    }
+
+    pub fn with_steal_batch(&self, steal_batch: usize) -> Self {
+        InjectorWorkerOptions {
+            steal_batch: steal_batch.max(1),
+            ..self.clone()
+        }
+    }
+
+    pub fn with_journal_sync(&self, journal_sync: JournalSync) -> Self {
+        InjectorWorkerOptions {
+            journal_sync,
+            ..self.clone()
+        }
+    }
+}
+
+struct WorkerSlot<T> {
+    pinned: ItemQueue<T>,
+    stealer: Stealer<QueueItem<T>>,
+    // The local queue waits here while no worker holds the slot. Its stealer outlives the worker,
+    // so the items left in it are still stolen by the others.
+    local: Mutex<Option<Worker<QueueItem<T>>>>,
+    taken: AtomicBool,
+}
+
+// A fixed array with a slot per worker index. The slots are created on first use and never move,
+// so stealing and looking for orphaned items go through them without a lock. Workers take the
+// lowest free slot, so the indices stay within the thread count and the items pinned to a slot
+// reach whichever worker holds it.
+struct WorkerSlots<T> {
+    behavior: QueueBehavior,
+    slots: Arc<[OnceLock<WorkerSlot<T>>]>,
+    // The slots from this index on were never used.
+    used: Arc<AtomicUsize>,
+}
+
+impl<T> Clone for WorkerSlots<T> {
+    fn clone(&self) -> Self {
+        WorkerSlots {
+            behavior: self.behavior,
+            slots: self.slots.clone(),
+            used: self.used.clone(),
+        }
+    }
+}
+
+impl<T> WorkerSlots<T> {
+    fn new(behavior: QueueBehavior) -> Self {
+        WorkerSlots {
+            behavior,
+            slots: (0..THREADS_MAX).map(|_| OnceLock::new()).collect(),
+            used: Arc::new(AtomicUsize::new(0)),
+        }
+    }
+
+    fn slot(&self, index: usize) -> &WorkerSlot<T> {
+        let slot = self.slots[index].get_or_init(|| {
+            // Each worker owns the local queue while it holds the slot, the others only reach it
+            // through its stealer.
+            let local = if self.behavior == QueueBehavior::LIFO {
+                Worker::<QueueItem<T>>::new_lifo()
+            } else {
+                Worker::<QueueItem<T>>::new_fifo()
+            };
+            // Pinned items keep their priority order in a Priority queue, the others take them in
+            // order.
+            let pinned = if self.behavior == QueueBehavior::Priority {
+                ItemQueue::new(QueueBehavior::Priority)
+            } else {
+                ItemQueue::new(QueueBehavior::FIFO)
+            };
+            WorkerSlot {
+                pinned,
+                stealer: local.stealer(),
+                local: Mutex::new(Some(local)),
+                taken: AtomicBool::new(false),
+            }
+        });
+        self.used.fetch_max(index + 1, Ordering::SeqCst);
+        slot
+    }
+
+    fn used(&self) -> impl Iterator<Item = &WorkerSlot<T>> {
+        self.slots[..self.used.load(Ordering::SeqCst)]
+            .iter()
+            .filter_map(|it| it.get())
+    }
+
+    fn take(&self) -> WorkerSlotGuard<T> {
+        loop {
+            for index in 0..self.slots.len() {
+                let slot = self.slot(index);
+
+                if slot
+                    .taken
+                    .compare_exchange(false, true, Ordering::SeqCst, Ordering::SeqCst)
+                    .is_err()
+                {
+                    continue;
+                }
+
+                let local = slot.local.lock().unwrap().take();
+                return WorkerSlotGuard {
+                    slots: self.clone(),
+                    index,
+                    local,
+                };
+            }
+
+            // A retiring worker lets go of its slot in a moment.
+            thread::yield_now();
+        }
+    }
+
+    // Starts after the worker's own slot, so the workers do not all go for the first one.
+    fn steal(
+        &self,
+        index: usize,
+        local: &Worker<QueueItem<T>>,
+        limit: usize,
+    ) -> Steal<QueueItem<T>> {
+        let used = self.used.load(Ordering::SeqCst);
+        (1..=used)
+            .filter_map(|offset| self.slots[(index + offset) % used].get())
+            .map(|it| it.stealer.steal_batch_with_limit_and_pop(local, limit))
+            .find(|it| it.is_success())
+            .unwrap_or(Steal::Empty)
+    }
+
+    fn pin(&self, index: usize, item: QueueItem<T>) {
+        self.slot(index).pinned.push(item);
+    }
+
+    // Items pinned to a slot that no worker holds would never run otherwise.
+    fn pop_orphaned(&self) -> Option<QueueItem<T>> {
+        self.used()
+            .filter(|it| !it.taken.load(Ordering::SeqCst))
+            .find_map(|it| it.pinned.pop())
+    }
+
+    fn clear(&self) {
+        for slot in self.used() {
+            while slot.pinned.pop().is_some() {}
+            while !slot.stealer.steal().is_empty() {}
+        }
+    }
+}
+
+pub(super) struct WorkerSlotGuard<T> {
+    slots: WorkerSlots<T>,
+    index: usize,
+    local: Option<Worker<QueueItem<T>>>,
+}
+
+impl<T> WorkerSlotGuard<T> {
+    fn slot(&self) -> &WorkerSlot<T> {
+        // Taking the slot created it.
+        self.slots.slots[self.index].get().unwrap()
+    }
+
+    fn pinned(&self) -> &ItemQueue<T> {
+        &self.slot().pinned
+    }
+
+    fn local(&self) -> &Worker<QueueItem<T>> {
+        self.local.as_ref().unwrap()
+    }
+}
+
+impl<T> Drop for WorkerSlotGuard<T> {
+    fn drop(&mut self) {
+        // The local queue goes back to the slot with whatever is left in it, for the next worker
+        // to take over and the others to steal from meanwhile.
+        let local = self.local.take();
+        let slot = self.slot();
+        *slot.local.lock().unwrap() = local;
+        slot.taken.store(false, Ordering::SeqCst);
+    }
 }
 
 #[derive(Clone)]
-pub struct InjectorWorker<T: StaticTaskItem> {
+pub struct InjectorWorker<T: StaticTaskItem, R: StaticTaskItem = ()> {
     pub options: InjectorWorkerOptions,
-    injector: Arc<Injector<T>>,
-    stealers: Arc<Mutex<Vec<Stealer<T>>>>,
+    injector: Arc<Injector<QueueItem<T>>>,
+    prioritized: Arc<ItemQueue<T>>,
+    slots: WorkerSlots<T>,
     len: Arc<AtomicUsize>,
+    // Only the Priority queue waits on it, work stealing has to poll the other workers anyway.
+    items_event: Event,
     started: Arc<Mutex<bool>>,
     finished: Arc<AtomicBool>,
-    finished_cond: Arc<Mutcond>,
-    finished_noti: Arc<Notify>,
+    finished_event: Event,
     completed: Arc<AtomicBool>,
     paused: Arc<AtomicBool>,
     cancelled: Arc<AtomicBool>,
+    token: CancellationToken,
     workers: Arc<AtomicUsize>,
+    pool: WorkerPool,
     running: Arc<AtomicUsize>,
+    dead_letters: DeadLetterQueue<T>,
+    results: TaskResults<T, R>,
+    rate_limiter: RateLimiter<T>,
+    dedup: Deduplicator<T>,
+    key_locks: KeyedLocks<T>,
+    stats: TaskStats,
+    events: QueueEvents<T, R>,
+    journal: Option<TaskJournal<T>>,
+    submitted: Arc<AtomicU64>,
 }
 
-impl<T: StaticTaskItem> InjectorWorker<T> {
+impl<T: StaticTaskItem, R: StaticTaskItem> InjectorWorker<T, R> {
     pub fn new() -> Self {
         InjectorWorker {
             options: Default::default(),
             injector: Arc::new(Injector::new()),
-            stealers: Arc::new(Mutex::new(Vec::new())),
+            prioritized: Arc::new(ItemQueue::new(QueueBehavior::Priority)),
+            slots: WorkerSlots::new(QUEUE_BEHAVIOR_DEF),
             len: Arc::new(AtomicUsize::new(0)),
+            items_event: Event::default(),
             started: Arc::new(Mutex::new(false)),
             finished: Arc::new(AtomicBool::new(false)),
-            finished_cond: Arc::new(Mutcond::new()),
-            finished_noti: Arc::new(Notify::new()),
+            finished_event: Event::manual_reset(),
             completed: Arc::new(AtomicBool::new(false)),
             paused: Arc::new(AtomicBool::new(false)),
             cancelled: Arc::new(AtomicBool::new(false)),
+            token: CancellationToken::new(),
             workers: Arc::new(AtomicUsize::new(0)),
+            pool: WorkerPool::new(THREADS_DEF),
             running: Arc::new(AtomicUsize::new(0)),
+            dead_letters: DeadLetterQueue::new(),
+            results: TaskResults::new(None),
+            rate_limiter: RateLimiter::new(None),
+            dedup: Deduplicator::new(None),
+            key_locks: KeyedLocks::new(),
+            stats: TaskStats::new(),
+            events: QueueEvents::new(),
+            journal: None,
+            submitted: Arc::new(AtomicU64::new(0)),
         }
     }
 
     pub fn with_options(options: InjectorWorkerOptions) -> Self {
+        let results = TaskResults::new(options.results);
+        let rate_limiter = RateLimiter::new(options.rate_limit);
+        let dedup = Deduplicator::new(options.dedup);
+        let pool = WorkerPool::new(options.threads);
+        let slots = WorkerSlots::new(options.behavior);
         InjectorWorker {
             options,
             injector: Arc::new(Injector::new()),
-            stealers: Arc::new(Mutex::new(Vec::new())),
+            prioritized: Arc::new(ItemQueue::new(QueueBehavior::Priority)),
+            slots,
             len: Arc::new(AtomicUsize::new(0)),
+            items_event: Event::default(),
             started: Arc::new(Mutex::new(false)),
             finished: Arc::new(AtomicBool::new(false)),
-            finished_cond: Arc::new(Mutcond::new()),
-            finished_noti: Arc::new(Notify::new()),
+            finished_event: Event::manual_reset(),
             completed: Arc::new(AtomicBool::new(false)),
             paused: Arc::new(AtomicBool::new(false)),
             cancelled: Arc::new(AtomicBool::new(false)),
+            token: CancellationToken::new(),
             workers: Arc::new(AtomicUsize::new(0)),
+            pool,
             running: Arc::new(AtomicUsize::new(0)),
+            dead_letters: DeadLetterQueue::new(),
+            results,
+            rate_limiter,
+            dedup,
+            key_locks: KeyedLocks::new(),
+            stats: TaskStats::new(),
+            events: QueueEvents::new(),
+            journal: None,
+            submitted: Arc::new(AtomicU64::new(0)),
         }
     }
 
@@ -135,7 +429,7 @@ impl<T: StaticTaskItem> InjectorWorker<T> {
             return false;
         }
 
-        *started = true;
+        *started = value;
         true
     }
 
@@ -151,6 +445,10 @@ impl<T: StaticTaskItem> InjectorWorker<T> {
         self.cancelled.load(Ordering::SeqCst)
     }
 
+    pub fn token(&self) -> &CancellationToken {
+        &self.token
+    }
+
     pub fn is_finished(&self) -> bool {
         self.finished.load(Ordering::SeqCst)
     }
@@ -176,8 +474,8 @@ impl<T: StaticTaskItem> InjectorWorker<T> {
     }
 
     fn dec_workers(&self) -> bool {
-        self.workers.fetch_sub(1, Ordering::SeqCst);
-        self.workers() == 0 && (self.is_completed() || self.is_cancelled())
+        self.pool
+            .exit(&self.workers, || self.is_completed() || self.is_cancelled())
     }
 
     fn finish(&self) {
@@ -185,11 +483,15 @@ impl<T: StaticTaskItem> InjectorWorker<T> {
             return;
         }
 
+        journal_finished(self.journal.as_ref(), self.is_cancelled());
         self.completed.store(true, Ordering::SeqCst);
         self.finished.store(true, Ordering::SeqCst);
         self.set_started(false);
-        self.finished_cond.notify_all();
-        self.finished_noti.notify_waiters();
+        self.stats.finish();
+        self.results.close();
+        self.pool.clear_spawner();
+        self.events.emit(|| QueueEvent::Finished);
+        self.finished_event.set();
         thread::sleep(Duration::ZERO);
     }
 
@@ -197,15 +499,59 @@ impl<T: StaticTaskItem> InjectorWorker<T> {
         self.running.load(Ordering::SeqCst)
     }
 
-    fn inc_running(&self) {
-        self.running.fetch_add(1, Ordering::SeqCst);
+    pub fn threads(&self) -> usize {
+        self.pool.threads()
+    }
+
+    pub fn set_threads(&self, threads: usize) {
+        self.pool.resize(threads, &self.workers);
+    }
+
+    pub fn dead_letters(&self) -> &DeadLetterQueue<T> {
+        &self.dead_letters
+    }
+
+    pub fn results(&self) -> &TaskResults<T, R> {
+        &self.results
+    }
+
+    pub fn rate_limiter(&self) -> &RateLimiter<T> {
+        &self.rate_limiter
+    }
+
+    pub fn deduplicator(&self) -> &Deduplicator<T> {
+        &self.dedup
+    }
+
+    pub fn key_locks(&self) -> &KeyedLocks<T> {
+        &self.key_locks
+    }
+
+    pub fn stats(&self) -> QueueStats {
+        self.stats.snapshot(self.len(), self.running())
     }
 
-    fn dec_running(&self) {
-        self.running.fetch_sub(1, Ordering::SeqCst);
+    pub fn events(&self) -> &QueueEvents<T, R> {
+        &self.events
     }
 
-    pub fn start<H: TaskDelegation<InjectorWorker<T>, T>>(&self, handler: &H) -> Result<()> {
+    pub fn subscribe(&self) -> QueueEventReceiver<T, R> {
+        self.events.subscribe()
+    }
+
+    pub fn set_progress(&self, pb: ProgressBar) {
+        self.stats.set_progress(pb);
+    }
+
+    pub fn clear_progress(&self) {
+        self.stats.clear_progress();
+    }
+
+    pub fn journal(&self) -> Option<&TaskJournal<T>> {
+        self.journal.as_ref()
+    }
+
+    pub fn start<H: TaskDelegation<InjectorWorker<T, R>, T, R>>(&self, handler: &H) -> Result<()> {
         if self.is_cancelled() {
             return Err(CanceledError.into());
         }
@@ -218,147 +564,350 @@ impl<T: StaticTaskItem> InjectorWorker<T> {
             return Err(QueueStartedError.into());
         }
 
-        self.set_workers(self.options.threads);
+        self.set_workers(self.threads());
+        self.stats.start();
         handler.on_started(self);
-        let mut mutstealers = self.stealers.lock().unwrap();
-        mutstealers.clear();
+        self.events.emit(|| QueueEvent::Started);
 
-        for _ in 0..self.options.threads {
-            let worker = if self.options.behavior == QueueBehavior::LIFO {
-                Worker::<T>::new_lifo()
-            } else {
-                Worker::<T>::new_fifo()
-            };
-            let stealer = worker.stealer();
-            mutstealers.push(stealer);
-            let this = self.clone();
-            let handler = handler.clone();
-            let global = self.injector.clone();
-            let local = Arc::new(Mutex::new(worker));
-            let stealers = self.stealers.clone();
-            thread::spawn(move || {
-                if this.options.threshold.is_zero() {
-                    loop {
-                        if this.is_cancelled() || (this.is_empty() && this.is_completed()) {
-                            break;
-                        }
-
-                        if this.is_paused() {
-                            thread::sleep(this.options.pause_timeout);
-                            continue;
-                        }
-
-                        let Some(item) = this.dequeue_wait(&global, &local, &stealers) else {
-                            continue;
-                        };
-                        this.inc_running();
-                        match handler.process(&this, &item) {
-                            Ok(it) => {
-                                if !handler.on_completed(&this, &item, &it) {
-                                    this.dec_running();
-                                    break;
-                                }
-                            }
-                            Err(e) => {
-                                if !handler.on_completed(
-                                    &this,
-                                    &item,
-                                    &TaskResult::Error(e.get_message()),
-                                ) {
-                                    this.dec_running();
-                                    break;
-                                }
-                            }
-                        }
-                        this.dec_running();
-                    }
+        let this = self.clone();
+        let spawner = handler.clone();
+        self.pool.set_spawner(move || this.spawn_worker(&spawner));
 
-                    if !this.dec_workers() {
-                        return;
-                    }
+        for _ in 0..self.threads() {
+            self.spawn_worker(handler);
+        }
 
-                    if this.is_cancelled() {
-                        handler.on_cancelled(&this);
-                    } else {
-                        handler.on_finished(&this);
-                    }
+        if let Some(scale) = self.options.autoscale {
+            autoscale(self, scale, &self.finished_event);
+        }
 
-                    this.finish();
+        Ok(())
+    }
+
+    fn spawn_worker<H: TaskDelegation<InjectorWorker<T, R>, T, R>>(&self, handler: &H) {
+        let slot = self.slots.take();
+        let this = self.clone();
+        let handler = handler.clone();
+        thread::spawn(move || {
+            loop {
+                if this.pool.retire(&this.workers) {
+                    // Dropping the slot hands the local items back to the remaining workers.
                     return;
                 }
 
-                loop {
-                    if this.is_cancelled() || (this.is_empty() && this.is_completed()) {
-                        break;
-                    }
+                if this.is_cancelled() || (this.is_empty() && this.is_completed()) {
+                    break;
+                }
 
-                    if this.is_paused() {
-                        thread::sleep(this.options.pause_timeout);
-                        continue;
-                    }
+                if this.is_paused() {
+                    thread::sleep(this.options.pause_timeout);
+                    continue;
+                }
 
-                    let Some(item) = this.dequeue_wait(&global, &local, &stealers) else {
-                        continue;
-                    };
-                    this.inc_running();
-                    match handler.process(&this, &item) {
-                        Ok(it) => {
-                            if !handler.on_completed(&this, &item, &it) {
-                                this.dec_running();
-                                break;
-                            }
-
-                            if !this.options.threshold.is_zero() {
-                                let time = Instant::now();
-
-                                if time.elapsed() < this.options.threshold {
-                                    let remaining = this.options.threshold - time.elapsed();
-                                    thread::sleep(remaining);
-                                }
-                            }
-                        }
-                        Err(e) => {
-                            if !handler.on_completed(
-                                &this,
-                                &item,
-                                &TaskResult::Error(e.get_message()),
-                            ) {
-                                this.dec_running();
-                                break;
-                            }
-                        }
-                    }
-                    this.dec_running();
+                let Some(QueueItem { index, item, .. }) = this.dequeue_wait(&slot) else {
+                    continue;
+                };
+
+                if !run_item(&this, &handler, index, item) {
+                    break;
                 }
+            }
+
+            if !this.dec_workers() {
+                return;
+            }
+
+            if this.is_cancelled() {
+                handler.on_cancelled(&this);
+            } else {
+                handler.on_finished(&this);
+            }
+
+            this.finish();
+        });
+    }
 
-                if !this.dec_workers() {
+    pub fn start_async<H: AsyncTaskDelegation<InjectorWorker<T, R>, T, R>>(
+        &self,
+        handler: &H,
+    ) -> Result<()> {
+        if self.is_cancelled() {
+            return Err(CanceledError.into());
+        }
+
+        if self.is_completed() && self.is_empty() {
+            return Err(QueueCompletedError.into());
+        }
+
+        let runtime = runtime_handle()?;
+
+        if !self.set_started(true) {
+            return Err(QueueStartedError.into());
+        }
+
+        self.set_workers(self.threads());
+        self.stats.start();
+        handler.on_started(self);
+        self.events.emit(|| QueueEvent::Started);
+
+        let this = self.clone();
+        let spawner = handler.clone();
+        let handle = runtime.clone();
+        self.pool
+            .set_spawner(move || this.spawn_worker_async(&spawner, &handle));
+
+        for _ in 0..self.threads() {
+            self.spawn_worker_async(handler, &runtime);
+        }
+
+        if let Some(scale) = self.options.autoscale {
+            autoscale(self, scale, &self.finished_event);
+        }
+
+        Ok(())
+    }
+
+    fn spawn_worker_async<H: AsyncTaskDelegation<InjectorWorker<T, R>, T, R>>(
+        &self,
+        handler: &H,
+        runtime: &Handle,
+    ) {
+        let slot = self.slots.take();
+        let this = self.clone();
+        let handler = handler.clone();
+        runtime.spawn(async move {
+            loop {
+                if this.pool.retire(&this.workers) {
+                    // Dropping the slot hands the local items back to the remaining workers.
                     return;
                 }
 
-                if this.is_cancelled() {
-                    handler.on_cancelled(&this);
-                } else {
-                    handler.on_finished(&this);
+                if this.is_cancelled() || (this.is_empty() && this.is_completed()) {
+                    break;
+                }
+
+                if this.is_paused() {
+                    time::sleep(this.options.pause_timeout).await;
+                    continue;
+                }
+
+                let Some(QueueItem { index, item, .. }) = this.dequeue(&slot) else {
+                    this.wait_for_item_async(slot.pinned()).await;
+                    continue;
+                };
+
+                if !run_item_async(&this, &handler, index, item).await {
+                    break;
                 }
+            }
+
+            if !this.dec_workers() {
+                return;
+            }
+
+            if this.is_cancelled() {
+                handler.on_cancelled(&this);
+            } else {
+                handler.on_finished(&this);
+            }
+
+            this.finish();
+        });
+    }
 
-                this.finish();
-            });
+    pub fn start_batch<H: BatchTaskDelegation<InjectorWorker<T, R>, T, R>>(
+        &self,
+        handler: &H,
+    ) -> Result<()> {
+        if self.is_cancelled() {
+            return Err(CanceledError.into());
+        }
+
+        if self.is_completed() && self.is_empty() {
+            return Err(QueueCompletedError.into());
+        }
+
+        if !self.set_started(true) {
+            return Err(QueueStartedError.into());
+        }
+
+        self.set_workers(self.threads());
+        self.stats.start();
+        handler.on_started(self);
+        self.events.emit(|| QueueEvent::Started);
+
+        let this = self.clone();
+        let spawner = handler.clone();
+        self.pool
+            .set_spawner(move || this.spawn_batch_worker(&spawner));
+
+        for _ in 0..self.threads() {
+            self.spawn_batch_worker(handler);
+        }
+
+        if let Some(scale) = self.options.autoscale {
+            autoscale(self, scale, &self.finished_event);
         }
 
         Ok(())
     }
 
-    pub fn enqueue(&self, item: T) -> Result<()> {
+    fn spawn_batch_worker<H: BatchTaskDelegation<InjectorWorker<T, R>, T, R>>(&self, handler: &H) {
+        let slot = self.slots.take();
+        let this = self.clone();
+        let handler = handler.clone();
+        let batch = self.options.batch.unwrap_or_default();
+        thread::spawn(move || {
+            loop {
+                if this.pool.retire(&this.workers) {
+                    // Dropping the slot hands the local items back to the remaining workers.
+                    return;
+                }
+
+                if this.is_cancelled() || (this.is_empty() && this.is_completed()) {
+                    break;
+                }
+
+                if this.is_paused() {
+                    thread::sleep(this.options.pause_timeout);
+                    continue;
+                }
+
+                let Some(first) = this.dequeue_wait(&slot) else {
+                    continue;
+                };
+                let mut items = vec![first];
+                fill_batch(&mut items, &batch, || this.dequeue(&slot));
+
+                if !run_batch(&this, &handler, items) {
+                    break;
+                }
+            }
+
+            if !this.dec_workers() {
+                return;
+            }
+
+            if this.is_cancelled() {
+                handler.on_cancelled(&this);
+            } else {
+                handler.on_finished(&this);
+            }
+
+            this.finish();
+        });
+    }
+
+    pub fn start_batch_async<H: AsyncBatchTaskDelegation<InjectorWorker<T, R>, T, R>>(
+        &self,
+        handler: &H,
+    ) -> Result<()> {
         if self.is_cancelled() {
             return Err(CanceledError.into());
         }
 
-        if self.is_completed() {
+        if self.is_completed() && self.is_empty() {
             return Err(QueueCompletedError.into());
         }
 
-        self.injector.push(item);
-        self.len.fetch_add(1, Ordering::SeqCst);
+        let runtime = runtime_handle()?;
+
+        if !self.set_started(true) {
+            return Err(QueueStartedError.into());
+        }
+
+        self.set_workers(self.threads());
+        self.stats.start();
+        handler.on_started(self);
+        self.events.emit(|| QueueEvent::Started);
+
+        let this = self.clone();
+        let spawner = handler.clone();
+        let handle = runtime.clone();
+        self.pool
+            .set_spawner(move || this.spawn_batch_worker_async(&spawner, &handle));
+
+        for _ in 0..self.threads() {
+            self.spawn_batch_worker_async(handler, &runtime);
+        }
+
+        if let Some(scale) = self.options.autoscale {
+            autoscale(self, scale, &self.finished_event);
+        }
+
+        Ok(())
+    }
+
+    fn spawn_batch_worker_async<H: AsyncBatchTaskDelegation<InjectorWorker<T, R>, T, R>>(
+        &self,
+        handler: &H,
+        runtime: &Handle,
+    ) {
+        let slot = self.slots.take();
+        let this = self.clone();
+        let handler = handler.clone();
+        let batch = self.options.batch.unwrap_or_default();
+        runtime.spawn(async move {
+            loop {
+                if this.pool.retire(&this.workers) {
+                    // Dropping the slot hands the local items back to the remaining workers.
+                    return;
+                }
+
+                if this.is_cancelled() || (this.is_empty() && this.is_completed()) {
+                    break;
+                }
+
+                if this.is_paused() {
+                    time::sleep(this.options.pause_timeout).await;
+                    continue;
+                }
+
+                let Some(first) = this.dequeue(&slot) else {
+                    this.wait_for_item_async(slot.pinned()).await;
+                    continue;
+                };
+                let mut items = vec![first];
+                // The local queue is not Sync, so it cannot be borrowed across the linger sleeps
+                // the way fill_batch_async would.
+                let deadline = Instant::now() + batch.linger;
+
+                while items.len() < batch.size {
+                    if let Some(item) = this.dequeue(&slot) {
+                        items.push(item);
+                        continue;
+                    }
+
+                    let remaining = deadline.saturating_duration_since(Instant::now());
+
+                    if remaining.is_zero() {
+                        break;
+                    }
+
+                    time::sleep(remaining.min(PEEK_TIMEOUT_MIN)).await;
+                }
+
+                if !run_batch_async(&this, &handler, items).await {
+                    break;
+                }
+            }
+
+            if !this.dec_workers() {
+                return;
+            }
+
+            if this.is_cancelled() {
+                handler.on_cancelled(&this);
+            } else {
+                handler.on_finished(&this);
+            }
+
+            this.finish();
+        });
+    }
+
+    pub fn enqueue(&self, item: T) -> Result<()> {
+        self.submit(item, PRIORITY_DEF, None)?;
 
         if !self.options.sleep_after_send.is_zero() {
             thread::sleep(self.options.sleep_after_send);
@@ -367,34 +916,135 @@ impl<T: StaticTaskItem> InjectorWorker<T> {
         Ok(())
     }
 
-    pub fn dequeue(
-        &self,
-        global: &Arc<Injector<T>>,
-        local: &Arc<Mutex<Worker<T>>>,
-        stealers: &Arc<Mutex<Vec<Stealer<T>>>>,
-    ) -> Option<T> {
-        self.deq(false, global, local, stealers)
+    // Fails unless the queue has the Priority behavior, the others would drop the priority.
+    pub fn enqueue_with_priority(&self, item: T, priority: i32) -> Result<()> {
+        check_priority(self.options.behavior)?;
+        self.submit(item, priority, None)?;
+
+        if !self.options.sleep_after_send.is_zero() {
+            thread::sleep(self.options.sleep_after_send);
+        }
+
+        Ok(())
     }
 
-    pub fn dequeue_wait(
-        &self,
-        global: &Arc<Injector<T>>,
-        local: &Arc<Mutex<Worker<T>>>,
-        stealers: &Arc<Mutex<Vec<Stealer<T>>>>,
-    ) -> Option<T> {
-        self.deq(true, global, local, stealers)
+    // Pins the item to a worker slot. It is only a hint: the index wraps around the thread count
+    // and the item goes to another worker if its slot is left empty. In a priority queue, the
+    // worker still takes a shared item first if it has a higher priority.
+    pub fn enqueue_pinned(&self, item: T, worker: usize) -> Result<()> {
+        self.submit(item, PRIORITY_DEF, Some(worker))?;
+
+        if !self.options.sleep_after_send.is_zero() {
+            thread::sleep(self.options.sleep_after_send);
+        }
+
+        Ok(())
     }
 
-    fn deq(
-        &self,
-        wait_for_item: bool,
-        global: &Arc<Injector<T>>,
-        local: &Arc<Mutex<Worker<T>>>,
-        stealers: &Arc<Mutex<Vec<Stealer<T>>>>,
-    ) -> Option<T> {
-        let local = local.lock().unwrap();
-        // Pop a task from the local queue, if not empty.
-        let item = local.pop().or_else(|| {
+    pub async fn enqueue_async(&self, item: T) -> Result<()> {
+        self.submit(item, PRIORITY_DEF, None)?;
+
+        if !self.options.sleep_after_send.is_zero() {
+            time::sleep(self.options.sleep_after_send).await;
+        }
+
+        Ok(())
+    }
+
+    pub async fn enqueue_with_priority_async(&self, item: T, priority: i32) -> Result<()> {
+        check_priority(self.options.behavior)?;
+        self.submit(item, priority, None)?;
+
+        if !self.options.sleep_after_send.is_zero() {
+            time::sleep(self.options.sleep_after_send).await;
+        }
+
+        Ok(())
+    }
+
+    pub async fn enqueue_all(&self, items: impl Stream<Item = T>) -> Result<usize> {
+        enqueue_stream(items, |item| self.enqueue_async(item)).await
+    }
+
+    pub fn sink(&self) -> QueueSink<T> {
+        let this = self.clone();
+        let completer = self.clone();
+        QueueSink::new(
+            move |item| {
+                let this = this.clone();
+                async move { this.enqueue_async(item).await }.boxed()
+            },
+            move || completer.complete(),
+        )
+    }
+
+    pub fn stream(&self) -> TaskOutputStream<T, R> {
+        self.results.subscribe()
+    }
+
+    fn submit(&self, item: T, priority: i32, worker: Option<usize>) -> Result<()> {
+        if self.is_cancelled() {
+            return Err(CanceledError.into());
+        }
+
+        if self.is_completed() {
+            return Err(QueueCompletedError.into());
+        }
+
+        // Duplicates are dropped quietly, the deduplicator keeps count of them.
+        if self.dedup.is_duplicate(&item) {
+            return Ok(());
+        }
+
+        let index = self.submitted.fetch_add(1, Ordering::SeqCst);
+        let item = QueueItem::with_priority(index, item, priority);
+        journal_enqueued(self.journal.as_ref(), &item)?;
+        self.push(item, worker);
+        Ok(())
+    }
+
+    fn push(&self, item: QueueItem<T>, worker: Option<usize>) {
+        self.stats.enqueued();
+        self.events.emit(|| QueueEvent::Enqueued {
+            index: item.index,
+            item: item.item.clone(),
+        });
+
+        if let Some(worker) = worker {
+            self.slots.pin(worker % self.threads().max(1), item);
+        } else if self.options.behavior == QueueBehavior::Priority {
+            self.prioritized.push(item);
+        } else {
+            self.injector.push(item);
+        }
+
+        self.len.fetch_add(1, Ordering::SeqCst);
+
+        if self.options.behavior == QueueBehavior::Priority {
+            self.items_event.notify();
+        }
+    }
+
+    pub(super) fn dequeue(&self, slot: &WorkerSlotGuard<T>) -> Option<QueueItem<T>> {
+        self.deq(false, slot)
+    }
+
+    pub(super) fn dequeue_wait(&self, slot: &WorkerSlotGuard<T>) -> Option<QueueItem<T>> {
+        self.deq(true, slot)
+    }
+
+    fn deq(&self, wait_for_item: bool, slot: &WorkerSlotGuard<T>) -> Option<QueueItem<T>> {
+        let pinned = slot.pinned();
+
+        if self.options.behavior == QueueBehavior::Priority {
+            return self.deq_prioritized(wait_for_item, pinned);
+        }
+
+        let local = slot.local();
+
+        let steal_batch = self.options.steal_batch.max(1);
+        // Take the items pinned to this worker first, then pop a task from the local queue.
+        let item = pinned.pop().or_else(|| local.pop()).or_else(|| {
             // Otherwise, we need to look for a task elsewhere.
             if self.is_cancelled() {
                 return None;
@@ -406,19 +1056,12 @@ impl<T: StaticTaskItem> InjectorWorker<T> {
             }
 
             // Try stealing a batch of tasks from the global queue.
-            global
-                .steal_batch_with_limit_and_pop(&local, 10)
+            self.injector
+                .steal_batch_with_limit_and_pop(local, steal_batch)
                 // Or try stealing a task from one of the other threads.
-                .or_else(|| {
-                    stealers
-                        .lock()
-                        .unwrap()
-                        .iter()
-                        .map(|s| s.steal_batch_with_limit_and_pop(&local, 10))
-                        .find(|s| s.is_success())
-                        .unwrap_or_else(|| Steal::Empty)
-                })
+                .or_else(|| self.slots.steal(slot.index, local, steal_batch))
                 .success()
+                .or_else(|| self.slots.pop_orphaned())
         });
 
         if item.is_some() {
@@ -429,10 +1072,64 @@ impl<T: StaticTaskItem> InjectorWorker<T> {
         None
     }
 
+    fn deq_prioritized(&self, wait_for_item: bool, pinned: &ItemQueue<T>) -> Option<QueueItem<T>> {
+        // Work stealing would reorder items, so all workers share one sorted queue next to the
+        // sorted queue of the items pinned to each of them.
+        if self.is_cancelled() {
+            return None;
+        }
+
+        if wait_for_item && self.is_paused() {
+            thread::sleep(self.options.pause_timeout);
+            return None;
+        }
+
+        if wait_for_item {
+            self.items_event
+                .wait_timeout_while(|| self.is_starved(pinned), self.options.pause_timeout);
+        }
+
+        // The pinned item only goes first if it sorts ahead of the shared one, so a pin never
+        // breaks the priority order. An empty queue has no key and sorts last.
+        let item = if pinned.peek_key() >= self.prioritized.peek_key() {
+            pinned.pop().or_else(|| self.prioritized.pop())
+        } else {
+            self.prioritized.pop().or_else(|| pinned.pop())
+        }
+        .or_else(|| self.slots.pop_orphaned());
+
+        if item.is_some() {
+            self.len.fetch_sub(1, Ordering::SeqCst);
+            return item;
+        }
+
+        None
+    }
+
+    fn is_starved(&self, pinned: &ItemQueue<T>) -> bool {
+        self.prioritized.is_empty()
+            && pinned.is_empty()
+            && !self.is_cancelled()
+            && !self.is_completed()
+            && !self.is_paused()
+    }
+
+    // The async workers of a priority queue wait for the next item instead of polling.
+    async fn wait_for_item_async(&self, pinned: &ItemQueue<T>) {
+        if self.options.behavior != QueueBehavior::Priority {
+            time::sleep(self.options.pause_timeout).await;
+            return;
+        }
+
+        self.items_event
+            .wait_timeout_while_async(|| self.is_starved(pinned), self.options.pause_timeout)
+            .await;
+    }
+
     pub fn clear(&mut self) {
         self.injector = mem::replace(&mut self.injector, Arc::new(Injector::new()));
-        let mut stealers = self.stealers.lock().unwrap();
-        stealers.clear();
+        self.prioritized = Arc::new(ItemQueue::new(QueueBehavior::Priority));
+        self.slots.clear();
         self.len.store(0, Ordering::SeqCst);
     }
 
@@ -445,70 +1142,157 @@ impl<T: StaticTaskItem> InjectorWorker<T> {
     }
 
     pub fn complete(&self) {
-        self.completed.store(true, Ordering::SeqCst);
+        if !self.completed.swap(true, Ordering::SeqCst) {
+            self.events.emit(|| QueueEvent::Completed);
+        }
+        self.items_event.notify();
     }
 
     pub fn cancel(&self) {
-        self.cancelled.store(true, Ordering::SeqCst);
+        if !self.cancelled.swap(true, Ordering::SeqCst) {
+            self.events.emit(|| QueueEvent::Cancelled);
+        }
+        self.token.cancel();
+        self.items_event.notify();
+        self.finished_event.notify();
     }
 
     pub fn pause(&self) {
-        self.paused.store(true, Ordering::SeqCst);
+        if !self.paused.swap(true, Ordering::SeqCst) {
+            self.events.emit(|| QueueEvent::Paused);
+        }
     }
 
     pub fn resume(&self) {
-        self.paused.store(false, Ordering::SeqCst);
+        if self.paused.swap(false, Ordering::SeqCst) {
+            self.events.emit(|| QueueEvent::Resumed);
+        }
     }
 
     pub fn wait(&self) -> Result<()> {
-        wait(self, &self.finished_cond)
+        wait(self, &self.finished_event)
     }
 
     pub async fn wait_async(&self) -> Result<()> {
-        wait_async(self, &self.finished_noti).await
+        wait_async(self, &self.finished_event).await
     }
 
-    pub fn wait_until(&self, cond: impl Fn(&InjectorWorker<T>) -> bool) -> Result<()> {
-        wait_until(self, &self.finished_cond, cond)
+    pub fn wait_until(&self, cond: impl Fn(&InjectorWorker<T, R>) -> bool) -> Result<()> {
+        wait_until(self, &self.finished_event, cond)
     }
 
     pub async fn wait_until_async<
-        F: Fn(&InjectorWorker<T>) -> Pin<Box<dyn Future<Output = bool> + Send>>,
+        F: Fn(&InjectorWorker<T, R>) -> Pin<Box<dyn Future<Output = bool> + Send>>,
     >(
         &self,
         cond: F,
     ) -> Result<()> {
-        wait_until_async(self, &self.finished_noti, cond).await
+        wait_until_async(self, &self.finished_event, cond).await
     }
 
     pub fn wait_for(&self, timeout: Duration) -> Result<()> {
-        wait_for(self, timeout, &self.finished_cond)
+        wait_for(self, timeout, &self.finished_event)
     }
 
     pub async fn wait_for_async(&self, timeout: Duration) -> Result<()> {
-        wait_for_async(self, timeout, &self.finished_noti).await
+        wait_for_async(self, timeout, &self.finished_event).await
     }
 
     pub fn wait_for_until(
         &self,
         timeout: Duration,
-        cond: impl Fn(&InjectorWorker<T>) -> bool,
+        cond: impl Fn(&InjectorWorker<T, R>) -> bool,
     ) -> Result<()> {
-        wait_for_until(self, timeout, &self.finished_cond, cond)
+        wait_for_until(self, timeout, &self.finished_event, cond)
     }
 
     pub async fn wait_for_until_async<
-        F: Fn(&InjectorWorker<T>) -> Pin<Box<dyn Future<Output = bool> + Send>>,
+        F: Fn(&InjectorWorker<T, R>) -> Pin<Box<dyn Future<Output = bool> + Send>>,
     >(
         &self,
         timeout: Duration,
         cond: F,
     ) -> Result<()> {
-        wait_for_until_async(self, timeout, &self.finished_noti, cond).await
+        wait_for_until_async(self, timeout, &self.finished_event, cond).await
+    }
+}
+
+impl<T: StaticTaskItem + Serialize + DeserializeOwned, R: StaticTaskItem> InjectorWorker<T, R> {
+    // Starts a new journal, an existing file at the path is truncated. Only resume_from continues
+    // an old journal.
+    pub fn with_journal<P: AsRef<Path>>(options: InjectorWorkerOptions, path: P) -> Result<Self> {
+        let sync = options.journal_sync;
+        let mut this = Self::with_options(options);
+        this.journal = Some(TaskJournal::create(path)?.with_sync(sync));
+        Ok(this)
+    }
+
+    pub fn resume_from<P: AsRef<Path>>(path: P, options: InjectorWorkerOptions) -> Result<Self> {
+        let (items, next) = TaskJournal::<T>::compact(&path)?;
+        let sync = options.journal_sync;
+        let mut this = Self::with_options(options);
+        this.journal = Some(TaskJournal::open(path)?.with_sync(sync));
+        this.submitted.store(next, Ordering::SeqCst);
+
+        for item in items {
+            this.dedup.remember(&item.item);
+            this.push(item, None);
+        }
+
+        Ok(this)
+    }
+}
+
+impl<T: StaticTaskItem, R: StaticTaskItem> ScalableConsumer for InjectorWorker<T, R> {
+    fn is_cancelled(&self) -> bool {
+        InjectorWorker::is_cancelled(self)
+    }
+
+    fn is_finished(&self) -> bool {
+        InjectorWorker::is_finished(self)
+    }
+
+    fn len(&self) -> usize {
+        InjectorWorker::len(self)
+    }
+
+    fn running(&self) -> usize {
+        InjectorWorker::running(self)
+    }
+
+    fn threads(&self) -> usize {
+        InjectorWorker::threads(self)
+    }
+
+    fn set_threads(&self, threads: usize) {
+        InjectorWorker::set_threads(self, threads)
+    }
+
+    fn stats(&self) -> QueueStats {
+        InjectorWorker::stats(self)
+    }
+}
+
+impl<T: StaticTaskItem, R: StaticTaskItem> WorkerQueue<T, R> for InjectorWorker<T, R> {
+    fn parts(&self) -> WorkerParts<'_, T, R> {
+        WorkerParts {
+            token: &self.token,
+            key_locks: &self.key_locks,
+            rate_limiter: &self.rate_limiter,
+            retry: self.options.retry.as_ref(),
+            dead_letters: &self.dead_letters,
+            item_timeout: self.options.item_timeout,
+            threshold: self.options.threshold,
+            results: &self.results,
+            stats: &self.stats,
+            journal: self.journal.as_ref(),
+            events: &self.events,
+            running: &self.running,
+        }
     }
 }
 
-impl<T: StaticTaskItem> AwaitableConsumer<T> for InjectorWorker<T> {
+impl<T: StaticTaskItem, R: StaticTaskItem> AwaitableConsumer<T> for InjectorWorker<T, R> {
     fn is_cancelled(&self) -> bool {
         self.is_cancelled()
     }
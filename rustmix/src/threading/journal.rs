@@ -0,0 +1,305 @@
+use serde::{de::DeserializeOwned, Deserialize, Serialize};
+use std::{
+    collections::BTreeMap,
+    fmt,
+    fs::File,
+    io::Write,
+    path::{Path, PathBuf},
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Arc, Mutex,
+    },
+    time::{Duration, Instant},
+};
+
+use super::*;
+use crate::{
+    io::file::{self, FileEx, FileOpenOptions},
+    Result,
+};
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(tag = "op", rename_all = "lowercase")]
+enum JournalEntry<T> {
+    Enqueued { index: u64, priority: i32, item: T },
+    Completed { index: u64 },
+}
+
+// When the enqueued entries are synced to the disk. Completed entries are never synced on their
+// own, a lost one only runs the item again on resume.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum JournalSync {
+    // Every enqueue waits for its entry to reach the disk. Concurrent enqueues share one sync.
+    #[default]
+    Item,
+    // The journal is synced at most once per interval. A power loss can lose the items enqueued
+    // since the last sync, a crash of the process alone loses nothing.
+    Interval(Duration),
+}
+
+#[derive(Debug)]
+struct SyncState {
+    index: u64,
+    at: Instant,
+}
+
+pub struct TaskJournal<T> {
+    path: PathBuf,
+    file: Arc<Mutex<File>>,
+    handle: Arc<File>,
+    sync: JournalSync,
+    written: Arc<AtomicU64>,
+    synced: Arc<Mutex<SyncState>>,
+    serialize: fn(&JournalEntry<T>) -> serde_json::Result<String>,
+}
+
+// Not derived, the item type does not need to be Clone to share the file.
+impl<T> Clone for TaskJournal<T> {
+    fn clone(&self) -> Self {
+        TaskJournal {
+            path: self.path.clone(),
+            file: self.file.clone(),
+            handle: self.handle.clone(),
+            sync: self.sync,
+            written: self.written.clone(),
+            synced: self.synced.clone(),
+            serialize: self.serialize,
+        }
+    }
+}
+
+impl<T> fmt::Debug for TaskJournal<T> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("TaskJournal")
+            .field("path", &self.path)
+            .field("sync", &self.sync)
+            .finish()
+    }
+}
+
+impl<T: Serialize> TaskJournal<T> {
+    // Continues the journal at the path. The caller must carry on from its next free index, see
+    // load and compact.
+    pub fn open<P: AsRef<Path>>(path: P) -> Result<Self> {
+        Self::open_with(path, FileOpenOptions::Append)
+    }
+
+    // Starts an empty journal, an existing file at the path is truncated.
+    pub fn create<P: AsRef<Path>>(path: P) -> Result<Self> {
+        Self::open_with(path, FileOpenOptions::Truncate)
+    }
+
+    fn open_with<P: AsRef<Path>>(path: P, options: FileOpenOptions) -> Result<Self> {
+        let path = path.as_ref();
+        let file = file::create_with(path, options)?;
+        let handle = file.try_clone()?;
+        Ok(TaskJournal {
+            path: path.to_path_buf(),
+            file: Arc::new(Mutex::new(file)),
+            handle: Arc::new(handle),
+            sync: JournalSync::default(),
+            written: Arc::new(AtomicU64::new(0)),
+            synced: Arc::new(Mutex::new(SyncState {
+                index: 0,
+                at: Instant::now(),
+            })),
+            serialize: serde_json::to_string,
+        })
+    }
+}
+
+impl<T: Serialize + DeserializeOwned> TaskJournal<T> {
+    // Like load, but also rewrites the journal with only the unfinished items, so it does not keep
+    // growing across resumes. The next free index is kept even when nothing is left.
+    pub fn compact<P: AsRef<Path>>(path: P) -> Result<(Vec<QueueItem<T>>, u64)> {
+        let path = path.as_ref();
+        let (items, next) = Self::load(path)?;
+
+        if !file::exists(path) {
+            return Ok((items, next));
+        }
+
+        let mut lines = Vec::with_capacity(items.len() + 1);
+
+        for item in &items {
+            lines.push(serde_json::to_string(&JournalEntry::Enqueued {
+                index: item.index,
+                priority: item.priority,
+                item: &item.item,
+            })?);
+        }
+
+        if items.is_empty() && next > 0 {
+            lines.push(serde_json::to_string(&JournalEntry::<T>::Completed {
+                index: next - 1,
+            })?);
+        }
+
+        file::write_lines_atomic(path, lines.iter())?;
+        Ok((items, next))
+    }
+}
+
+impl<T: DeserializeOwned> TaskJournal<T> {
+    // Returns the unfinished items in submission order and the next free index.
+    pub fn load<P: AsRef<Path>>(path: P) -> Result<(Vec<QueueItem<T>>, u64)> {
+        let path = path.as_ref();
+
+        if !file::exists(path) {
+            return Ok((Vec::new(), 0));
+        }
+
+        let mut pending = BTreeMap::new();
+        let mut next = 0;
+
+        for line in file::open(path)?.read()? {
+            if line.trim().is_empty() {
+                continue;
+            }
+
+            // A crash can leave the last line half written.
+            let Ok(entry) = serde_json::from_str::<JournalEntry<T>>(&line) else {
+                continue;
+            };
+
+            match entry {
+                JournalEntry::Enqueued {
+                    index,
+                    priority,
+                    item,
+                } => {
+                    next = next.max(index + 1);
+                    pending.insert(index, QueueItem::with_priority(index, item, priority));
+                }
+                JournalEntry::Completed { index } => {
+                    next = next.max(index + 1);
+                    pending.remove(&index);
+                }
+            }
+        }
+
+        Ok((pending.into_values().collect(), next))
+    }
+}
+
+impl<T> TaskJournal<T> {
+    pub fn with_sync(&self, sync: JournalSync) -> Self {
+        TaskJournal {
+            sync,
+            ..self.clone()
+        }
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    pub fn sync(&self) -> JournalSync {
+        self.sync
+    }
+
+    fn write(&self, entry: &JournalEntry<T>, durable: bool) -> Result<()> {
+        let line = (self.serialize)(entry)?;
+        let index = {
+            let mut file = self.file.lock().unwrap();
+            writeln!(file, "{}", line)?;
+            file.flush()?;
+            self.written.fetch_add(1, Ordering::SeqCst) + 1
+        };
+
+        if durable {
+            self.sync_to(index)?;
+        }
+
+        Ok(())
+    }
+
+    // Group commit: one sync covers everything written so far, so the writers queued up behind
+    // it find their entries synced already and return without another one.
+    fn sync_to(&self, index: u64) -> Result<()> {
+        let mut synced = self.synced.lock().unwrap();
+
+        if synced.index >= index {
+            return Ok(());
+        }
+
+        if let JournalSync::Interval(interval) = self.sync {
+            if synced.at.elapsed() < interval {
+                return Ok(());
+            }
+        }
+
+        let written = self.written.load(Ordering::SeqCst);
+        self.handle.sync_data()?;
+        synced.index = written;
+        synced.at = Instant::now();
+        Ok(())
+    }
+
+    // Empties the journal once every item is done, the next run starts from scratch.
+    pub(super) fn clear(&self) -> Result<()> {
+        let file = self.file.lock().unwrap();
+        file.set_len(0)?;
+        file.sync_data()?;
+        Ok(())
+    }
+}
+
+impl<T: Clone> TaskJournal<T> {
+    // An accepted item must survive a crash, so the entry is synced as the sync mode says before
+    // the enqueue returns.
+    pub(super) fn enqueued(&self, item: &QueueItem<T>) -> Result<()> {
+        self.write(
+            &JournalEntry::Enqueued {
+                index: item.index,
+                priority: item.priority,
+                item: item.item.clone(),
+            },
+            true,
+        )
+    }
+
+    pub(super) fn completed(&self, index: u64) -> Result<()> {
+        self.write(&JournalEntry::Completed { index }, false)
+    }
+}
+
+pub(super) fn journal_enqueued<T: Clone>(
+    journal: Option<&TaskJournal<T>>,
+    item: &QueueItem<T>,
+) -> Result<()> {
+    match journal {
+        Some(journal) => journal.enqueued(item),
+        None => Ok(()),
+    }
+}
+
+// Called when the queue finishes. A cancelled queue keeps its journal for the next resume, with
+// anything an interval sync has not covered yet synced now.
+pub(super) fn journal_finished<T>(journal: Option<&TaskJournal<T>>, cancelled: bool) {
+    let Some(journal) = journal else {
+        return;
+    };
+
+    if cancelled {
+        journal.handle.sync_data().ok();
+    } else {
+        journal.clear().ok();
+    }
+}
+
+pub(super) fn journal_completed<T: Clone, R>(
+    journal: Option<&TaskJournal<T>>,
+    index: u64,
+    result: &TaskResult<R>,
+) {
+    // Cancelled items did not run to the end, so they stay pending for the next resume.
+    if matches!(result, TaskResult::Cancelled) {
+        return;
+    }
+
+    if let Some(journal) = journal {
+        // The item has been processed already, a failed write only means it runs again on resume.
+        journal.completed(index).ok();
+    }
+}
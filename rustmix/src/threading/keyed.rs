@@ -0,0 +1,416 @@
+use std::{
+    collections::{HashMap, HashSet, VecDeque},
+    fmt, mem,
+    sync::{Arc, Mutex, RwLock},
+};
+use tokio::time::{Duration, Instant};
+
+pub type TaskKey<T> = Arc<dyn Fn(&T) -> String + Send + Sync>;
+
+fn key_of<T: fmt::Debug>(key: &RwLock<Option<TaskKey<T>>>, item: &T) -> String {
+    match &*key.read().unwrap() {
+        Some(key) => key(item),
+        None => format!("{:?}", item),
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct Dedup {
+    // The number of keys remembered before the oldest ones are forgotten.
+    pub capacity: usize,
+    // Keys older than this are forgotten, so the item can be enqueued again.
+    pub window: Option<Duration>,
+}
+
+impl Dedup {
+    pub fn new(capacity: usize) -> Self {
+        Dedup {
+            capacity: capacity.max(1),
+            window: None,
+        }
+    }
+
+    pub fn with_window(&self, window: Duration) -> Self {
+        Dedup {
+            window: Some(window),
+            ..*self
+        }
+    }
+}
+
+#[derive(Debug, Default)]
+struct SeenKeys {
+    keys: HashMap<String, Instant>,
+    order: VecDeque<(String, Instant)>,
+    duplicates: usize,
+}
+
+impl SeenKeys {
+    fn purge(&mut self, dedup: &Dedup, now: Instant) {
+        while let Some((key, seen)) = self.order.front() {
+            let expired = dedup
+                .window
+                .is_some_and(|it| now.saturating_duration_since(*seen) >= it);
+
+            if !expired && self.order.len() <= dedup.capacity {
+                break;
+            }
+
+            // A forgotten key may have been seen again since, so only its own entry goes.
+            if self.keys.get(key) == Some(seen) {
+                self.keys.remove(key);
+            }
+
+            self.order.pop_front();
+        }
+    }
+
+    fn forget(&mut self, key: &str) -> bool {
+        let Some(seen) = self.keys.remove(key) else {
+            return false;
+        };
+
+        // The entry would otherwise keep counting against the capacity.
+        if let Some(index) = self
+            .order
+            .iter()
+            .position(|(it, at)| it == key && *at == seen)
+        {
+            self.order.remove(index);
+        }
+
+        true
+    }
+}
+
+// Items are keyed by their Debug output unless a key function is set.
+#[derive(Clone)]
+pub struct Deduplicator<T> {
+    dedup: Option<Dedup>,
+    key: Arc<RwLock<Option<TaskKey<T>>>>,
+    seen: Arc<Mutex<SeenKeys>>,
+}
+
+impl<T> fmt::Debug for Deduplicator<T> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("Deduplicator")
+            .field("dedup", &self.dedup)
+            .field("keyed", &self.key.read().unwrap().is_some())
+            .field("seen", &self.seen.lock().unwrap().keys.len())
+            .finish()
+    }
+}
+
+impl<T: fmt::Debug> Deduplicator<T> {
+    pub fn new(dedup: Option<Dedup>) -> Self {
+        Deduplicator {
+            dedup,
+            key: Arc::new(RwLock::new(None)),
+            seen: Arc::new(Mutex::new(SeenKeys::default())),
+        }
+    }
+
+    pub fn dedup(&self) -> Option<Dedup> {
+        self.dedup
+    }
+
+    pub fn set_key(&self, key: impl Fn(&T) -> String + Send + Sync + 'static) {
+        let mut guard = self.key.write().unwrap();
+        *guard = Some(Arc::new(key));
+    }
+
+    pub fn clear_key(&self) {
+        let mut guard = self.key.write().unwrap();
+        *guard = None;
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    pub fn len(&self) -> usize {
+        self.seen.lock().unwrap().keys.len()
+    }
+
+    // The number of items that were dropped as duplicates.
+    pub fn duplicates(&self) -> usize {
+        self.seen.lock().unwrap().duplicates
+    }
+
+    // Remembers the item and returns true if it was already seen.
+    pub fn is_duplicate(&self, item: &T) -> bool {
+        let Some(dedup) = self.dedup else {
+            return false;
+        };
+        let key = key_of(&self.key, item);
+        let now = Instant::now();
+        let mut seen = self.seen.lock().unwrap();
+        seen.purge(&dedup, now);
+
+        if seen.keys.contains_key(&key) {
+            seen.duplicates += 1;
+            return true;
+        }
+
+        seen.keys.insert(key.clone(), now);
+        seen.order.push_back((key, now));
+        seen.purge(&dedup, now);
+        false
+    }
+
+    // Remembers the item without counting or dropping it, used for items restored from a journal.
+    pub fn remember(&self, item: &T) {
+        let Some(dedup) = self.dedup else {
+            return;
+        };
+        let key = key_of(&self.key, item);
+        let now = Instant::now();
+        let mut seen = self.seen.lock().unwrap();
+        seen.purge(&dedup, now);
+
+        if !seen.keys.contains_key(&key) {
+            seen.keys.insert(key.clone(), now);
+            seen.order.push_back((key, now));
+        }
+
+        seen.purge(&dedup, now);
+    }
+
+    pub fn forget(&self, item: &T) -> bool {
+        let key = key_of(&self.key, item);
+        self.seen.lock().unwrap().forget(&key)
+    }
+
+    pub fn clear(&self) {
+        let mut seen = self.seen.lock().unwrap();
+        seen.keys.clear();
+        seen.order.clear();
+    }
+}
+
+// The keys being processed, with the items deferred to whoever holds them.
+type BusyKeys<T> = HashMap<String, VecDeque<(u64, T)>>;
+
+// Releases the key when dropped, the items still deferred to it are dropped with it.
+pub struct KeyGuard<T> {
+    key: String,
+    busy: Arc<Mutex<BusyKeys<T>>>,
+    released: bool,
+}
+
+impl<T> fmt::Debug for KeyGuard<T> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("KeyGuard")
+            .field("key", &self.key)
+            .field("released", &self.released)
+            .finish()
+    }
+}
+
+impl<T> Drop for KeyGuard<T> {
+    fn drop(&mut self) {
+        if !self.released {
+            self.busy.lock().unwrap().remove(&self.key);
+        }
+    }
+}
+
+impl<T> KeyGuard<T> {
+    pub fn key(&self) -> &str {
+        &self.key
+    }
+
+    pub fn is_released(&self) -> bool {
+        self.released
+    }
+
+    // Takes the oldest item deferred to the key and keeps it locked, or releases the key when
+    // there is none. Both happen under one lock, so no item is deferred to a released key.
+    pub fn next(&mut self) -> Option<(u64, T)> {
+        if self.released {
+            return None;
+        }
+
+        let mut busy = self.busy.lock().unwrap();
+        let next = busy.get_mut(&self.key).and_then(|it| it.pop_front());
+
+        if next.is_none() {
+            busy.remove(&self.key);
+            self.released = true;
+        }
+
+        next
+    }
+
+    // Like next, but takes every item deferred so far.
+    pub fn take_deferred(&mut self) -> Vec<(u64, T)> {
+        if self.released {
+            return Vec::new();
+        }
+
+        let mut busy = self.busy.lock().unwrap();
+        let deferred = busy.get_mut(&self.key).map(mem::take).unwrap_or_default();
+
+        if deferred.is_empty() {
+            busy.remove(&self.key);
+            self.released = true;
+        }
+
+        deferred.into()
+    }
+
+    // Releases the key and returns the number of deferred items dropped with it.
+    pub fn release(mut self) -> usize {
+        self.released = true;
+        self.busy
+            .lock()
+            .unwrap()
+            .remove(&self.key)
+            .map_or(0, |it| it.len())
+    }
+}
+
+#[derive(Debug)]
+pub enum KeyLock<T> {
+    // The item has no key to lock.
+    Free,
+    Locked(KeyGuard<T>),
+    // The key was busy, so the item was handed to the holder of the key to run next.
+    Deferred,
+}
+
+// Items that share a key are never processed at the same time. Nothing is locked until a key
+// function is set. A worker never waits for a busy key, the item is deferred to the worker that
+// holds the key instead, which keeps the other workers free and the items of a key in order.
+#[derive(Clone)]
+pub struct KeyedLocks<T> {
+    key: Arc<RwLock<Option<TaskKey<T>>>>,
+    busy: Arc<Mutex<BusyKeys<T>>>,
+}
+
+impl<T> fmt::Debug for KeyedLocks<T> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("KeyedLocks")
+            .field("keyed", &self.key.read().unwrap().is_some())
+            .field("busy", &self.busy.lock().unwrap().len())
+            .finish()
+    }
+}
+
+impl<T: fmt::Debug> Default for KeyedLocks<T> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<T: fmt::Debug> KeyedLocks<T> {
+    pub fn new() -> Self {
+        KeyedLocks {
+            key: Arc::new(RwLock::new(None)),
+            busy: Arc::new(Mutex::new(HashMap::new())),
+        }
+    }
+
+    pub fn set_key(&self, key: impl Fn(&T) -> String + Send + Sync + 'static) {
+        let mut guard = self.key.write().unwrap();
+        *guard = Some(Arc::new(key));
+    }
+
+    pub fn clear_key(&self) {
+        let mut guard = self.key.write().unwrap();
+        *guard = None;
+    }
+
+    pub fn is_keyed(&self) -> bool {
+        self.key.read().unwrap().is_some()
+    }
+
+    // The number of keys that are being processed.
+    pub fn busy(&self) -> usize {
+        self.busy.lock().unwrap().len()
+    }
+
+    // The number of items waiting for their key.
+    pub fn deferred(&self) -> usize {
+        self.busy.lock().unwrap().values().map(|it| it.len()).sum()
+    }
+
+    fn key(&self, item: &T) -> Option<String> {
+        self.key.read().unwrap().as_ref().map(|key| key(item))
+    }
+
+    fn guard(&self, key: String) -> KeyGuard<T> {
+        KeyGuard {
+            key,
+            busy: self.busy.clone(),
+            released: false,
+        }
+    }
+
+    pub fn try_lock(&self, item: &T) -> Option<KeyGuard<T>> {
+        let key = self.key(item)?;
+        let mut busy = self.busy.lock().unwrap();
+
+        if busy.contains_key(&key) {
+            return None;
+        }
+
+        busy.insert(key.clone(), VecDeque::new());
+        Some(self.guard(key))
+    }
+
+    pub fn lock_or_defer(&self, index: u64, item: &T) -> KeyLock<T>
+    where
+        T: Clone,
+    {
+        let Some(key) = self.key(item) else {
+            return KeyLock::Free;
+        };
+        let mut busy = self.busy.lock().unwrap();
+
+        if let Some(deferred) = busy.get_mut(&key) {
+            deferred.push_back((index, item.clone()));
+            return KeyLock::Deferred;
+        }
+
+        busy.insert(key.clone(), VecDeque::new());
+        KeyLock::Locked(self.guard(key))
+    }
+
+    // Locks the free keys of a batch and defers the items whose key another worker holds. Returns
+    // the guards and the items left to run. Items of the same batch share their key's guard.
+    pub fn lock_or_defer_all(&self, items: Vec<(u64, T)>) -> (Vec<KeyGuard<T>>, Vec<(u64, T)>) {
+        let keyed = items
+            .into_iter()
+            .map(|it| (self.key(&it.1), it))
+            .collect::<Vec<_>>();
+        let mut busy = self.busy.lock().unwrap();
+        let mut held = HashSet::new();
+        let mut guards = Vec::new();
+        let mut kept = Vec::with_capacity(keyed.len());
+
+        for (key, item) in keyed {
+            let Some(key) = key else {
+                kept.push(item);
+                continue;
+            };
+
+            if held.contains(&key) {
+                kept.push(item);
+                continue;
+            }
+
+            if let Some(deferred) = busy.get_mut(&key) {
+                deferred.push_back(item);
+                continue;
+            }
+
+            busy.insert(key.clone(), VecDeque::new());
+            held.insert(key.clone());
+            guards.push(self.guard(key));
+            kept.push(item);
+        }
+
+        (guards, kept)
+    }
+}
@@ -0,0 +1,72 @@
+use tokio::{sync::broadcast, time::Duration};
+
+use super::*;
+
+const EVENTS_CAPACITY_DEF: usize = 1024;
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum QueueEvent<T, R = ()> {
+    Started,
+    Enqueued {
+        index: u64,
+        item: T,
+    },
+    ItemStarted {
+        index: u64,
+        item: T,
+    },
+    ItemCompleted {
+        index: u64,
+        item: T,
+        result: TaskResult<R>,
+        elapsed: Duration,
+    },
+    Paused,
+    Resumed,
+    Completed,
+    Cancelled,
+    Finished,
+}
+
+pub type QueueEventReceiver<T, R = ()> = broadcast::Receiver<QueueEvent<T, R>>;
+
+// Every subscriber gets its own copy of the events. One that falls more than the capacity behind
+// loses the oldest events and is told so with RecvError::Lagged.
+#[derive(Debug, Clone)]
+pub struct QueueEvents<T: StaticTaskItem, R: StaticTaskItem = ()> {
+    sender: broadcast::Sender<QueueEvent<T, R>>,
+}
+
+impl<T: StaticTaskItem, R: StaticTaskItem> Default for QueueEvents<T, R> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<T: StaticTaskItem, R: StaticTaskItem> QueueEvents<T, R> {
+    pub fn new() -> Self {
+        Self::with_capacity(EVENTS_CAPACITY_DEF)
+    }
+
+    pub fn with_capacity(capacity: usize) -> Self {
+        let (sender, _) = broadcast::channel(capacity.max(1));
+        QueueEvents { sender }
+    }
+
+    pub fn subscribe(&self) -> QueueEventReceiver<T, R> {
+        self.sender.subscribe()
+    }
+
+    pub fn subscribers(&self) -> usize {
+        self.sender.receiver_count()
+    }
+
+    // The event is only built when someone listens, so the items are not cloned for nothing.
+    pub(super) fn emit(&self, event: impl FnOnce() -> QueueEvent<T, R>) {
+        if self.sender.receiver_count() == 0 {
+            return;
+        }
+
+        self.sender.send(event()).ok();
+    }
+}
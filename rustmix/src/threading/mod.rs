@@ -1,25 +1,60 @@
+mod batch;
+pub use self::batch::*;
+mod cancellation;
+pub use self::cancellation::*;
 mod cond;
 pub use self::cond::*;
 mod consumer;
 pub use self::consumer::*;
+mod event;
+pub use self::event::*;
 mod injector_consumer;
 pub use self::injector_consumer::*;
+mod journal;
+pub use self::journal::*;
+mod keyed;
+pub use self::keyed::*;
+mod lifecycle;
+pub use self::lifecycle::*;
+mod parallel;
+pub use self::parallel::*;
+mod pipeline;
+pub use self::pipeline::*;
+mod priority;
+pub use self::priority::*;
+mod progress;
+pub use self::progress::*;
 mod producer_consumer;
 pub use self::producer_consumer::*;
+mod rate_limit;
+pub use self::rate_limit::*;
+mod results;
+pub use self::results::*;
+mod retry;
+pub use self::retry::*;
+mod scaling;
+pub use self::scaling::*;
+mod scheduler;
+pub use self::scheduler::*;
+mod shutdown;
+pub use self::shutdown::*;
 mod spinner;
 pub use self::spinner::*;
+mod stats;
+pub use self::stats::*;
+mod stream;
+pub use self::stream::*;
+mod worker;
+use self::worker::*;
 
 use futures::Future;
-use std::{fmt, pin::Pin, sync::Arc, thread};
+use std::{fmt, pin::Pin};
 use tokio::{
-    sync::Notify,
+    runtime::Handle,
     time::{self, Duration},
 };
 
-use crate::{
-    error::{CanceledError, TimedoutError},
-    Result,
-};
+use crate::{error::*, Result};
 
 const CAPACITY_DEF: usize = 0;
 const THREADS_DEF: usize = 1;
@@ -36,33 +71,72 @@ const PAUSE_TIMEOUT_MIN: Duration = Duration::from_millis(10);
 const PAUSE_TIMEOUT_MAX: Duration = Duration::from_secs(5);
 pub const INTERVAL: u64 = 100;
 
-#[derive(Default, Clone, PartialEq, Eq, PartialOrd, Ord, Hash)]
-pub enum TaskResult {
+#[derive(Debug, Default, Clone, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub enum TaskResult<R = ()> {
     #[default]
     None,
     Cancelled,
     TimedOut,
     Error(String),
-    Success,
+    Success(R),
+}
+
+impl<R> TaskResult<R> {
+    pub fn is_success(&self) -> bool {
+        matches!(self, TaskResult::Success(_))
+    }
+
+    pub fn output(&self) -> Option<&R> {
+        match self {
+            TaskResult::Success(it) => Some(it),
+            _ => None,
+        }
+    }
+}
+
+impl<R> From<&RmxError> for TaskResult<R> {
+    fn from(error: &RmxError) -> Self {
+        match error {
+            RmxError::Canceled => TaskResult::Cancelled,
+            RmxError::Timeout => TaskResult::TimedOut,
+            _ => TaskResult::Error(error.get_message()),
+        }
+    }
+}
+
+impl<R> From<RmxError> for TaskResult<R> {
+    fn from(error: RmxError) -> Self {
+        (&error).into()
+    }
+}
+
+impl<R> From<Result<R>> for TaskResult<R> {
+    fn from(result: Result<R>) -> Self {
+        match result {
+            Ok(it) => TaskResult::Success(it),
+            Err(e) => e.into(),
+        }
+    }
 }
 
-impl fmt::Display for TaskResult {
+impl<R> fmt::Display for TaskResult<R> {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
         match self {
             TaskResult::Cancelled => write!(f, "Cancelled"),
             TaskResult::TimedOut => write!(f, "Timedout"),
             TaskResult::Error(e) => write!(f, "Error: {}", e),
-            TaskResult::Success => write!(f, "Success"),
+            TaskResult::Success(_) => write!(f, "Success"),
             _ => Ok(()),
         }
     }
 }
 
-#[derive(Default, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
 pub enum QueueBehavior {
     #[default]
     FIFO,
     LIFO,
+    Priority,
 }
 
 impl fmt::Display for QueueBehavior {
@@ -70,6 +144,7 @@ impl fmt::Display for QueueBehavior {
         match self {
             QueueBehavior::FIFO => write!(f, "FIFO"),
             QueueBehavior::LIFO => write!(f, "LIFO"),
+            QueueBehavior::Priority => write!(f, "Priority"),
         }
     }
 }
@@ -80,10 +155,50 @@ impl<T: Clone + Send + Sync + fmt::Debug> TaskItem for T {}
 pub trait StaticTaskItem: TaskItem + 'static {}
 impl<T: TaskItem + 'static> StaticTaskItem for T {}
 
-pub trait TaskDelegation<TPC: AwaitableConsumer<T>, T: StaticTaskItem>: StaticTaskItem {
+#[derive(Debug, Clone)]
+pub struct QueueItem<T> {
+    pub index: u64,
+    pub priority: i32,
+    pub item: T,
+}
+
+impl<T> QueueItem<T> {
+    pub fn new(index: u64, item: T) -> Self {
+        Self::with_priority(index, item, PRIORITY_DEF)
+    }
+
+    pub fn with_priority(index: u64, item: T, priority: i32) -> Self {
+        QueueItem {
+            index,
+            priority,
+            item,
+        }
+    }
+}
+
+pub trait TaskDelegation<TPC: AwaitableConsumer<T>, T: StaticTaskItem, R: StaticTaskItem = ()>:
+    StaticTaskItem
+{
+    fn on_started(&self, pc: &TPC);
+    // The item timeout is cooperative here, it only cancels the token. A handler that does not
+    // check the token keeps its worker busy until it returns, and is then reported as timed out.
+    fn process(&self, pc: &TPC, item: &T, token: &CancellationToken) -> Result<R>;
+    fn on_completed(&self, pc: &TPC, item: &T, result: &TaskResult<R>) -> bool;
+    fn on_cancelled(&self, pc: &TPC);
+    fn on_finished(&self, pc: &TPC);
+}
+
+pub trait AsyncTaskDelegation<TPC: AwaitableConsumer<T>, T: StaticTaskItem, R: StaticTaskItem = ()>:
+    StaticTaskItem
+{
     fn on_started(&self, pc: &TPC);
-    fn process(&self, pc: &TPC, item: &T) -> Result<TaskResult>;
-    fn on_completed(&self, pc: &TPC, item: &T, result: &TaskResult) -> bool;
+    fn process(
+        &self,
+        pc: &TPC,
+        item: &T,
+        token: &CancellationToken,
+    ) -> impl Future<Output = Result<R>> + Send;
+    fn on_completed(&self, pc: &TPC, item: &T, result: &TaskResult<R>) -> bool;
     fn on_cancelled(&self, pc: &TPC);
     fn on_finished(&self, pc: &TPC);
 }
@@ -93,34 +208,11 @@ pub trait AwaitableConsumer<T: TaskItem>: StaticTaskItem {
     fn is_finished(&self) -> bool;
 }
 
-fn wait<TPC: AwaitableConsumer<T>, T: StaticTaskItem>(
-    this: &TPC,
-    finished: &Arc<Mutcond>,
-) -> Result<()> {
-    match finished.wait_while(|| !this.is_cancelled() && !this.is_finished()) {
-        Ok(_) => {
-            if this.is_cancelled() {
-                Err(CanceledError.into())
-            } else {
-                Ok(())
-            }
-        }
-        Err(_) => Err(CanceledError.into()),
-    }
+fn runtime_handle() -> Result<Handle> {
+    Handle::try_current().map_err(|e| RmxError::InvalidOperation(e.to_string()))
 }
 
-async fn wait_async<TPC: AwaitableConsumer<T>, T: StaticTaskItem>(
-    this: &TPC,
-    finished: &Arc<Notify>,
-) -> Result<()> {
-    let mut notified = false;
-
-    while !notified && !this.is_finished() && !this.is_cancelled() {
-        thread::sleep(Duration::ZERO);
-        finished.notified().await;
-        notified = true;
-    }
-
+fn wait_result<TPC: AwaitableConsumer<T>, T: StaticTaskItem>(this: &TPC) -> Result<()> {
     if this.is_cancelled() {
         return Err(CanceledError.into());
     }
@@ -128,21 +220,34 @@ async fn wait_async<TPC: AwaitableConsumer<T>, T: StaticTaskItem>(
     Ok(())
 }
 
+fn is_running<TPC: AwaitableConsumer<T>, T: StaticTaskItem>(this: &TPC) -> bool {
+    !this.is_cancelled() && !this.is_finished()
+}
+
+fn wait<TPC: AwaitableConsumer<T>, T: StaticTaskItem>(this: &TPC, finished: &Event) -> Result<()> {
+    finished.wait_while(|| is_running(this));
+    wait_result(this)
+}
+
+async fn wait_async<TPC: AwaitableConsumer<T>, T: StaticTaskItem>(
+    this: &TPC,
+    finished: &Event,
+) -> Result<()> {
+    finished.wait_while_async(|| is_running(this)).await;
+    wait_result(this)
+}
+
+// The condition is not tied to the event, so it is polled in between.
 fn wait_until<TPC: AwaitableConsumer<T>, T: StaticTaskItem>(
     this: &TPC,
-    finished: &Arc<Mutcond>,
+    finished: &Event,
     cond: impl Fn(&TPC) -> bool,
 ) -> Result<()> {
-    match finished.wait_while(|| !this.is_cancelled() && !this.is_finished() && !cond(this)) {
-        Ok(_) => {
-            if this.is_cancelled() {
-                Err(CanceledError.into())
-            } else {
-                Ok(())
-            }
-        }
-        Err(_) => Err(CanceledError.into()),
+    while is_running(this) && !cond(this) {
+        finished.wait_timeout_while(|| is_running(this), PEEK_TIMEOUT_DEF);
     }
+
+    wait_result(this)
 }
 
 async fn wait_until_async<
@@ -151,88 +256,69 @@ async fn wait_until_async<
     F: Fn(&TPC) -> Pin<Box<dyn Future<Output = bool> + Send>>,
 >(
     this: &TPC,
-    finished: &Arc<Notify>,
+    finished: &Event,
     cond: F,
 ) -> Result<()> {
-    let mut notified = false;
-
-    while !cond(this).await && !notified && !this.is_cancelled() && !this.is_finished() {
-        finished.notified().await;
-        notified = true;
+    while is_running(this) && !cond(this).await {
+        finished
+            .wait_timeout_while_async(|| is_running(this), PEEK_TIMEOUT_DEF)
+            .await;
     }
 
-    if this.is_cancelled() {
-        return Err(CanceledError.into());
-    }
-
-    Ok(())
+    wait_result(this)
 }
 
 fn wait_for<TPC: AwaitableConsumer<T>, T: StaticTaskItem>(
     this: &TPC,
     timeout: Duration,
-    finished: &Arc<Mutcond>,
+    finished: &Event,
 ) -> Result<()> {
-    if timeout.is_zero() {
+    if timeout.is_zero() || !finished.wait_timeout_while(|| is_running(this), timeout) {
         return Err(TimedoutError.into());
     }
 
-    match finished.wait_timeout_while(|| !this.is_cancelled() && !this.is_finished(), timeout) {
-        Ok(_) => {
-            if this.is_cancelled() {
-                Err(CanceledError.into())
-            } else {
-                Ok(())
-            }
-        }
-        Err(_) => Err(TimedoutError.into()),
-    }
+    wait_result(this)
 }
 
 async fn wait_for_async<TPC: AwaitableConsumer<T>, T: StaticTaskItem>(
     this: &TPC,
     timeout: Duration,
-    finished: &Arc<Notify>,
+    finished: &Event,
 ) -> Result<()> {
-    if timeout.is_zero() {
+    if timeout.is_zero()
+        || !finished
+            .wait_timeout_while_async(|| is_running(this), timeout)
+            .await
+    {
         return Err(TimedoutError.into());
     }
 
-    let result = time::timeout(timeout, finished.notified()).await;
-    match result {
-        Ok(_) => {
-            if this.is_cancelled() {
-                Err(CanceledError.into())
-            } else {
-                Ok(())
-            }
-        }
-        Err(_) => Err(TimedoutError.into()),
-    }
+    wait_result(this)
 }
 
 fn wait_for_until<TPC: AwaitableConsumer<T>, T: StaticTaskItem>(
     this: &TPC,
     timeout: Duration,
-    finished: &Arc<Mutcond>,
+    finished: &Event,
     cond: impl Fn(&TPC) -> bool,
 ) -> Result<()> {
     if timeout.is_zero() {
         return Err(TimedoutError.into());
     }
-    match finished.wait_timeout_while(
-        || !this.is_cancelled() && !this.is_finished() && !cond(this),
-        timeout,
-    ) {
-        Ok(_) => {
-            if this.is_cancelled() {
-                Err(CanceledError.into())
-            } else {
-                Ok(())
-            }
+
+    let deadline = time::Instant::now() + timeout;
+
+    while is_running(this) && !cond(this) {
+        let remaining = deadline.saturating_duration_since(time::Instant::now());
+
+        if remaining.is_zero() {
+            return Err(TimedoutError.into());
         }
-        Err(_) => Err(TimedoutError.into()),
+
+        finished.wait_timeout_while(|| is_running(this), remaining.min(PEEK_TIMEOUT_DEF));
     }
+
+    wait_result(this)
 }
 
 async fn wait_for_until_async<
@@ -242,39 +328,26 @@ async fn wait_for_until_async<
 >(
     this: &TPC,
     timeout: Duration,
-    finished: &Arc<Notify>,
+    finished: &Event,
     cond: F,
 ) -> Result<()> {
     if timeout.is_zero() {
         return Err(TimedoutError.into());
     }
 
-    let start = time::Instant::now();
+    let deadline = time::Instant::now() + timeout;
 
-    while !cond(this).await {
-        if this.is_cancelled() {
-            return Err(CanceledError.into());
-        }
+    while is_running(this) && !cond(this).await {
+        let remaining = deadline.saturating_duration_since(time::Instant::now());
 
-        if time::Instant::now().duration_since(start) > timeout {
+        if remaining.is_zero() {
             return Err(TimedoutError.into());
         }
 
-        match time::timeout(PEEK_TIMEOUT_DEF, finished.notified()).await {
-            Ok(_) => {
-                if this.is_cancelled() {
-                    return Err(CanceledError.into());
-                }
-
-                return Ok(());
-            }
-            Err(_) => {
-                if time::Instant::now().duration_since(start) > timeout {
-                    return Err(TimedoutError.into());
-                }
-            }
-        }
+        finished
+            .wait_timeout_while_async(|| is_running(this), remaining.min(PEEK_TIMEOUT_DEF))
+            .await;
     }
 
-    Ok(())
+    wait_result(this)
 }
@@ -0,0 +1,204 @@
+use rayon::{prelude::*, ThreadPool, ThreadPoolBuilder};
+use std::{
+    io::{BufRead, BufReader},
+    path::{Path, PathBuf},
+};
+
+use super::{THREADS_MAX, THREADS_MIN};
+use crate::{
+    error::*,
+    io::{file, path},
+    is_debug, system, Result,
+};
+
+const LINES_BATCH_DEF: usize = 1000;
+
+#[derive(Debug, Default, Clone)]
+pub struct ThreadPoolOptions {
+    pub threads: Option<usize>,
+    pub name: Option<String>,
+    pub stack_size: Option<usize>,
+}
+
+impl ThreadPoolOptions {
+    pub fn new() -> Self {
+        Default::default()
+    }
+
+    pub fn with_threads(&self, threads: usize) -> Self {
+        ThreadPoolOptions {
+            threads: Some(threads),
+            ..self.clone()
+        }
+    }
+
+    pub fn with_name(&self, name: impl Into<String>) -> Self {
+        ThreadPoolOptions {
+            name: Some(name.into()),
+            ..self.clone()
+        }
+    }
+
+    pub fn with_stack_size(&self, stack_size: usize) -> Self {
+        ThreadPoolOptions {
+            stack_size: Some(stack_size),
+            ..self.clone()
+        }
+    }
+
+    // As many threads as cpus unless a count is given, and a single one in debug so the work stays
+    // easy to follow.
+    pub fn threads(&self) -> usize {
+        if is_debug() {
+            return 1;
+        }
+
+        match self.threads {
+            Some(threads) => threads.clamp(THREADS_MIN, THREADS_MAX),
+            None => system::num_cpus().max(THREADS_MIN),
+        }
+    }
+
+    pub fn build(&self) -> Result<ThreadPool> {
+        let mut builder = ThreadPoolBuilder::new().num_threads(self.threads());
+
+        if let Some(name) = &self.name {
+            let name = name.clone();
+            builder = builder.thread_name(move |index| format!("{}-{}", name, index));
+        }
+
+        if let Some(stack_size) = self.stack_size {
+            builder = builder.stack_size(stack_size);
+        }
+
+        builder
+            .build()
+            .map_err(|e| RmxError::InvalidOperation(e.to_string()))
+    }
+}
+
+pub fn thread_pool() -> Result<ThreadPool> {
+    ThreadPoolOptions::default().build()
+}
+
+// Runs the handler over every file matching the pattern. The results keep the order of the matches.
+pub fn par_process_files<T: AsRef<str>, R: Send, F: Fn(&Path) -> Result<R> + Send + Sync>(
+    pattern: T,
+    handler: F,
+) -> Result<Vec<(PathBuf, Result<R>)>> {
+    par_process_files_with(&thread_pool()?, pattern, handler)
+}
+
+pub fn par_process_files_with<T: AsRef<str>, R: Send, F: Fn(&Path) -> Result<R> + Send + Sync>(
+    pool: &ThreadPool,
+    pattern: T,
+    handler: F,
+) -> Result<Vec<(PathBuf, Result<R>)>> {
+    let files = path::lst_match(pattern)?
+        .filter(|it| it.is_file())
+        .collect::<Vec<_>>();
+    let results = pool.install(|| {
+        files
+            .into_par_iter()
+            .map(|file| {
+                let result = handler(&file);
+                (file, result)
+            })
+            .collect()
+    });
+    Ok(results)
+}
+
+// Maps every line of the file. The file is read in batches, but the results of all the lines are
+// collected in their order, use par_map_lines_batch to hand over each batch instead. The lines are
+// decoded lossily, so a line with invalid UTF-8 bytes is still mapped.
+pub fn par_map_lines<T: AsRef<Path>, R: Send, F: Fn(&str) -> R + Send + Sync>(
+    file: T,
+    f: F,
+) -> Result<Vec<R>> {
+    par_map_lines_with(&thread_pool()?, file, LINES_BATCH_DEF, f)
+}
+
+pub fn par_map_lines_with<T: AsRef<Path>, R: Send, F: Fn(&str) -> R + Send + Sync>(
+    pool: &ThreadPool,
+    file: T,
+    batch: usize,
+    f: F,
+) -> Result<Vec<R>> {
+    let mut results = Vec::new();
+    par_map_lines_batch_with(pool, file, batch, f, |_, mapped| {
+        results.extend(mapped);
+        true
+    })?;
+    Ok(results)
+}
+
+// Like par_map_lines, but only one batch of lines and results is in memory at a time. The callback
+// gets the batch number and the mapped batch, returning false stops reading. Returns the number of
+// batches.
+pub fn par_map_lines_batch<
+    T: AsRef<Path>,
+    R: Send,
+    F: Fn(&str) -> R + Send + Sync,
+    C: FnMut(u32, Vec<R>) -> bool,
+>(
+    file: T,
+    batch: usize,
+    f: F,
+    callback: C,
+) -> Result<u32> {
+    par_map_lines_batch_with(&thread_pool()?, file, batch, f, callback)
+}
+
+pub fn par_map_lines_batch_with<
+    T: AsRef<Path>,
+    R: Send,
+    F: Fn(&str) -> R + Send + Sync,
+    C: FnMut(u32, Vec<R>) -> bool,
+>(
+    pool: &ThreadPool,
+    file: T,
+    batch: usize,
+    f: F,
+    mut callback: C,
+) -> Result<u32> {
+    let batch = if batch == 0 { LINES_BATCH_DEF } else { batch };
+    let mut reader = BufReader::new(file::open(file)?);
+    let mut buffer = Vec::new();
+    let mut batch_number = 0u32;
+
+    loop {
+        let mut chunk = Vec::with_capacity(batch);
+
+        while chunk.len() < batch {
+            buffer.clear();
+
+            if reader.read_until(b'\n', &mut buffer)? == 0 {
+                break;
+            }
+
+            if buffer.ends_with(b"\n") {
+                buffer.pop();
+
+                if buffer.ends_with(b"\r") {
+                    buffer.pop();
+                }
+            }
+
+            chunk.push(String::from_utf8_lossy(&buffer).into_owned());
+        }
+
+        if chunk.is_empty() {
+            break;
+        }
+
+        let mapped: Vec<R> = pool.install(|| chunk.par_iter().map(|line| f(line)).collect());
+        batch_number += 1;
+
+        if !callback(batch_number, mapped) {
+            break;
+        }
+    }
+
+    Ok(batch_number)
+}
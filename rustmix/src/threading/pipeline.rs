@@ -0,0 +1,388 @@
+use super::*;
+use crate::{error::*, Result};
+use std::{
+    fmt,
+    sync::{
+        atomic::{AtomicBool, AtomicUsize, Ordering},
+        Arc, RwLock,
+    },
+};
+
+type StageForward<T> = Arc<dyn Fn(T) -> Result<()> + Send + Sync>;
+type StageStarter = Arc<dyn Fn() -> Result<()> + Send + Sync>;
+
+trait PipelineNode: Send + Sync {
+    fn start(&self) -> Result<()>;
+    fn is_source(&self) -> bool;
+    fn complete(&self);
+    fn cancel(&self);
+    fn upstream_finished(&self);
+}
+
+#[derive(Clone)]
+struct Downstream<T> {
+    forward: StageForward<T>,
+    node: Arc<dyn PipelineNode>,
+}
+
+#[derive(Debug, Clone)]
+struct PipelineState {
+    pending: Arc<AtomicUsize>,
+    dropped: Arc<AtomicUsize>,
+    cancelled: Arc<AtomicBool>,
+    finished: Event,
+}
+
+impl PipelineState {
+    fn new() -> Self {
+        PipelineState {
+            pending: Arc::new(AtomicUsize::new(0)),
+            dropped: Arc::new(AtomicUsize::new(0)),
+            cancelled: Arc::new(AtomicBool::new(false)),
+            finished: Event::manual_reset(),
+        }
+    }
+
+    fn stage_done(&self) {
+        self.stages_done(1);
+    }
+
+    fn stages_done(&self, count: usize) {
+        if count == 0 || self.pending.fetch_sub(count, Ordering::SeqCst) > count {
+            return;
+        }
+
+        self.finished.set();
+    }
+}
+
+#[derive(Clone)]
+struct StageHandler<H, R> {
+    inner: H,
+    downstream: Arc<RwLock<Vec<Downstream<R>>>>,
+    state: PipelineState,
+}
+
+impl<H: fmt::Debug, R> fmt::Debug for StageHandler<H, R> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("StageHandler")
+            .field("inner", &self.inner)
+            .field("downstream", &self.downstream.read().unwrap().len())
+            .finish()
+    }
+}
+
+impl<H, R: StaticTaskItem> StageHandler<H, R> {
+    fn finish(&self) {
+        for next in self.downstream.read().unwrap().iter() {
+            next.node.upstream_finished();
+        }
+
+        self.state.stage_done();
+    }
+}
+
+impl<H, T, R> TaskDelegation<ProducerConsumer<T, R>, T, R> for StageHandler<H, R>
+where
+    H: TaskDelegation<ProducerConsumer<T, R>, T, R>,
+    T: StaticTaskItem,
+    R: StaticTaskItem,
+{
+    fn on_started(&self, pc: &ProducerConsumer<T, R>) {
+        self.inner.on_started(pc);
+    }
+
+    fn process(
+        &self,
+        pc: &ProducerConsumer<T, R>,
+        item: &T,
+        token: &CancellationToken,
+    ) -> Result<R> {
+        self.inner.process(pc, item, token)
+    }
+
+    fn on_completed(&self, pc: &ProducerConsumer<T, R>, item: &T, result: &TaskResult<R>) -> bool {
+        if let TaskResult::Success(output) = result {
+            // Sending blocks while the next stage is full, which throttles this one. A stage that is
+            // completed, cancelled or has no consumers left refuses items, and the output is
+            // counted as dropped.
+            for next in self.downstream.read().unwrap().iter() {
+                if (next.forward)(output.clone()).is_err() {
+                    self.state.dropped.fetch_add(1, Ordering::SeqCst);
+                }
+            }
+        }
+
+        self.inner.on_completed(pc, item, result)
+    }
+
+    fn on_cancelled(&self, pc: &ProducerConsumer<T, R>) {
+        self.inner.on_cancelled(pc);
+        self.finish();
+    }
+
+    fn on_finished(&self, pc: &ProducerConsumer<T, R>) {
+        self.inner.on_finished(pc);
+        self.finish();
+    }
+}
+
+#[derive(Clone)]
+pub struct PipelineStage<T: StaticTaskItem, R: StaticTaskItem = ()> {
+    pc: ProducerConsumer<T, R>,
+    downstream: Arc<RwLock<Vec<Downstream<R>>>>,
+    upstreams: Arc<AtomicUsize>,
+    starter: StageStarter,
+}
+
+impl<T: StaticTaskItem, R: StaticTaskItem> fmt::Debug for PipelineStage<T, R> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("PipelineStage")
+            .field("pc", &self.pc)
+            .field("downstream", &self.downstream.read().unwrap().len())
+            .field("upstreams", &self.upstreams.load(Ordering::SeqCst))
+            .finish()
+    }
+}
+
+impl<T: StaticTaskItem, R: StaticTaskItem> PipelineStage<T, R> {
+    pub fn queue(&self) -> &ProducerConsumer<T, R> {
+        &self.pc
+    }
+
+    pub fn enqueue(&self, item: T) -> Result<()> {
+        self.pc.enqueue(item)
+    }
+
+    pub fn connect<R2: StaticTaskItem>(&self, next: &PipelineStage<R, R2>) -> Result<()> {
+        if self.pc.is_started() || next.pc.is_started() {
+            return Err(RmxError::InvalidOperation(
+                "Stages cannot be connected after the pipeline is started".to_string(),
+            ));
+        }
+
+        let pc = next.pc.clone();
+        next.upstreams.fetch_add(1, Ordering::SeqCst);
+        self.downstream.write().unwrap().push(Downstream {
+            forward: Arc::new(move |item| pc.enqueue(item)),
+            node: Arc::new(next.clone()),
+        });
+        Ok(())
+    }
+}
+
+impl<T: StaticTaskItem, R: StaticTaskItem> PipelineNode for PipelineStage<T, R> {
+    fn start(&self) -> Result<()> {
+        (self.starter)()
+    }
+
+    fn is_source(&self) -> bool {
+        self.upstreams.load(Ordering::SeqCst) == 0
+    }
+
+    fn complete(&self) {
+        self.pc.complete();
+    }
+
+    fn cancel(&self) {
+        self.pc.cancel();
+    }
+
+    fn upstream_finished(&self) {
+        // Fan-in stages complete only after every stage feeding them is done.
+        if self.upstreams.fetch_sub(1, Ordering::SeqCst) == 1 {
+            self.pc.complete();
+        }
+    }
+}
+
+#[derive(Clone)]
+pub struct Pipeline {
+    stages: Arc<RwLock<Vec<Arc<dyn PipelineNode>>>>,
+    sources: Arc<RwLock<Vec<Arc<dyn PipelineNode>>>>,
+    started: Arc<AtomicBool>,
+    state: PipelineState,
+}
+
+impl fmt::Debug for Pipeline {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("Pipeline")
+            .field("stages", &self.stages.read().unwrap().len())
+            .field("started", &self.is_started())
+            .field("pending", &self.pending())
+            .field("dropped", &self.dropped())
+            .finish()
+    }
+}
+
+impl Default for Pipeline {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Pipeline {
+    pub fn new() -> Self {
+        Pipeline {
+            stages: Arc::new(RwLock::new(Vec::new())),
+            sources: Arc::new(RwLock::new(Vec::new())),
+            started: Arc::new(AtomicBool::new(false)),
+            state: PipelineState::new(),
+        }
+    }
+
+    pub fn stage<T, R, H>(
+        &self,
+        options: ProducerConsumerOptions,
+        handler: H,
+    ) -> PipelineStage<T, R>
+    where
+        T: StaticTaskItem,
+        R: StaticTaskItem,
+        H: TaskDelegation<ProducerConsumer<T, R>, T, R>,
+    {
+        let pc = ProducerConsumer::with_options(options);
+        let downstream = Arc::new(RwLock::new(Vec::new()));
+        let handler = StageHandler {
+            inner: handler,
+            downstream: downstream.clone(),
+            state: self.state.clone(),
+        };
+        let starter = {
+            let pc = pc.clone();
+            Arc::new(move || pc.start(&handler))
+        };
+        let stage = PipelineStage {
+            pc,
+            downstream,
+            upstreams: Arc::new(AtomicUsize::new(0)),
+            starter,
+        };
+        self.stages.write().unwrap().push(Arc::new(stage.clone()));
+        stage
+    }
+
+    pub fn is_started(&self) -> bool {
+        self.started.load(Ordering::SeqCst)
+    }
+
+    pub fn is_cancelled(&self) -> bool {
+        self.state.cancelled.load(Ordering::SeqCst)
+    }
+
+    pub fn is_finished(&self) -> bool {
+        self.is_started() && self.pending() == 0
+    }
+
+    pub fn len(&self) -> usize {
+        self.stages.read().unwrap().len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    // The number of stages that did not finish yet.
+    pub fn pending(&self) -> usize {
+        self.state.pending.load(Ordering::SeqCst)
+    }
+
+    // The number of outputs that could not be handed to the next stage.
+    pub fn dropped(&self) -> usize {
+        self.state.dropped.load(Ordering::SeqCst)
+    }
+
+    // A pipeline that never started has no stages running to finish, so there is nothing to wait on.
+    fn check_started(&self) -> Result<()> {
+        if !self.is_started() {
+            return Err(RmxError::InvalidOperation(
+                "The pipeline is not started".to_string(),
+            ));
+        }
+
+        Ok(())
+    }
+
+    pub fn start(&self) -> Result<()> {
+        if self.is_cancelled() {
+            return Err(CanceledError.into());
+        }
+
+        if self.started.swap(true, Ordering::SeqCst) {
+            return Err(QueueStartedError.into());
+        }
+
+        let stages = self.stages.read().unwrap();
+        let mut sources = self.sources.write().unwrap();
+        sources.clear();
+        self.state.pending.store(stages.len(), Ordering::SeqCst);
+
+        for (started, stage) in stages.iter().enumerate() {
+            if stage.is_source() {
+                sources.push(stage.clone());
+            }
+
+            if let Err(e) = stage.start() {
+                // The stages that never started will not report back, and the running ones are
+                // stopped, so waiting on the pipeline returns.
+                self.state.cancelled.store(true, Ordering::SeqCst);
+
+                for stage in stages[..started].iter() {
+                    stage.cancel();
+                }
+
+                self.state.stages_done(stages.len() - started);
+                return Err(e);
+            }
+        }
+
+        Ok(())
+    }
+
+    pub fn complete(&self) -> Result<()> {
+        self.check_started()?;
+
+        for stage in self.sources.read().unwrap().iter() {
+            stage.complete();
+        }
+
+        Ok(())
+    }
+
+    pub fn cancel(&self) {
+        self.state.cancelled.store(true, Ordering::SeqCst);
+        self.state.finished.notify();
+
+        for stage in self.stages.read().unwrap().iter() {
+            stage.cancel();
+        }
+    }
+
+    // Returns as soon as the pipeline is cancelled, without waiting for the stages to stop.
+    pub fn wait(&self) -> Result<()> {
+        self.check_started()?;
+        self.state
+            .finished
+            .wait_while(|| self.pending() > 0 && !self.is_cancelled());
+
+        if self.is_cancelled() {
+            return Err(CanceledError.into());
+        }
+
+        Ok(())
+    }
+
+    pub async fn wait_async(&self) -> Result<()> {
+        self.check_started()?;
+        self.state
+            .finished
+            .wait_while_async(|| self.pending() > 0 && !self.is_cancelled())
+            .await;
+
+        if self.is_cancelled() {
+            return Err(CanceledError.into());
+        }
+
+        Ok(())
+    }
+}
@@ -0,0 +1,106 @@
+use crossbeam::queue::SegQueue;
+use std::{cmp::Ordering, collections::BinaryHeap, sync::Mutex};
+
+use super::*;
+use crate::{error::*, Result};
+
+pub const PRIORITY_DEF: i32 = 0;
+
+// Only a Priority queue orders by priority, the others would drop it quietly.
+pub(super) fn check_priority(behavior: QueueBehavior) -> Result<()> {
+    if behavior == QueueBehavior::Priority {
+        return Ok(());
+    }
+
+    Err(RmxError::InvalidOperation(format!(
+        "A {} queue does not take priorities",
+        behavior
+    )))
+}
+
+#[derive(Debug)]
+pub(super) struct SortedItem<T> {
+    key: (i32, i64),
+    item: QueueItem<T>,
+}
+
+impl<T> SortedItem<T> {
+    fn new(behavior: QueueBehavior, item: QueueItem<T>) -> Self {
+        // BinaryHeap pops the greatest key first
+        let key = match behavior {
+            QueueBehavior::LIFO => (PRIORITY_DEF, item.index as i64),
+            _ => (item.priority, -(item.index as i64)),
+        };
+        SortedItem { key, item }
+    }
+}
+
+impl<T> PartialEq for SortedItem<T> {
+    fn eq(&self, other: &Self) -> bool {
+        self.key == other.key
+    }
+}
+
+impl<T> Eq for SortedItem<T> {}
+
+impl<T> PartialOrd for SortedItem<T> {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl<T> Ord for SortedItem<T> {
+    fn cmp(&self, other: &Self) -> Ordering {
+        self.key.cmp(&other.key)
+    }
+}
+
+#[derive(Debug)]
+pub(super) enum ItemQueue<T> {
+    FIFO(SegQueue<QueueItem<T>>),
+    Sorted(QueueBehavior, Mutex<BinaryHeap<SortedItem<T>>>),
+}
+
+impl<T> ItemQueue<T> {
+    pub fn new(behavior: QueueBehavior) -> Self {
+        match behavior {
+            QueueBehavior::FIFO => ItemQueue::FIFO(SegQueue::new()),
+            _ => ItemQueue::Sorted(behavior, Mutex::new(BinaryHeap::new())),
+        }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    pub fn len(&self) -> usize {
+        match self {
+            ItemQueue::FIFO(items) => items.len(),
+            ItemQueue::Sorted(_, items) => items.lock().unwrap().len(),
+        }
+    }
+
+    pub fn push(&self, item: QueueItem<T>) {
+        match self {
+            ItemQueue::FIFO(items) => items.push(item),
+            ItemQueue::Sorted(behavior, items) => {
+                items.lock().unwrap().push(SortedItem::new(*behavior, item))
+            }
+        }
+    }
+
+    // The sort key of the item pop returns next. FIFO queues are not sorted, so they have none.
+    pub fn peek_key(&self) -> Option<(i32, i64)> {
+        match self {
+            ItemQueue::FIFO(_) => None,
+            ItemQueue::Sorted(_, items) => items.lock().unwrap().peek().map(|it| it.key),
+        }
+    }
+
+    pub fn pop(&self) -> Option<QueueItem<T>> {
+        match self {
+            ItemQueue::FIFO(items) => items.pop(),
+            ItemQueue::Sorted(_, items) => items.lock().unwrap().pop().map(|it| it.item),
+        }
+    }
+}
@@ -1,17 +1,23 @@
 use crossbeam::channel;
+use futures::{FutureExt, Stream};
+use indicatif::ProgressBar;
+use serde::{de::DeserializeOwned, Serialize};
 use std::{
+    collections::VecDeque,
+    path::Path,
     sync::{
-        atomic::{AtomicBool, AtomicUsize, Ordering},
+        atomic::{AtomicBool, AtomicU64, AtomicUsize, Ordering},
         Arc, Mutex,
     },
     thread,
 };
 use tokio::{
-    sync::Notify,
-    time::{Duration, Instant},
+    runtime::Handle,
+    task,
+    time::{self, Duration},
 };
 
-use super::{cond::Mutcond, *};
+use super::*;
 use crate::{error::*, Result};
 
 #[derive(Clone, PartialEq, Eq)]
@@ -22,6 +28,14 @@ pub struct ProducerConsumerOptions {
     pub sleep_after_send: Duration,
     pub peek_timeout: Duration,
     pub pause_timeout: Duration,
+    pub item_timeout: Option<Duration>,
+    pub retry: Option<RetryPolicy>,
+    pub results: Option<ResultsOrder>,
+    pub rate_limit: Option<RateLimit>,
+    pub dedup: Option<Dedup>,
+    pub batch: Option<Batch>,
+    pub autoscale: Option<AutoScale>,
+    pub journal_sync: JournalSync,
 }
 
 impl Default for ProducerConsumerOptions {
@@ -33,6 +47,14 @@ impl Default for ProducerConsumerOptions {
             sleep_after_send: SLEEP_AFTER_SEND_DEF,
             peek_timeout: PEEK_TIMEOUT_DEF.clamp(PEEK_TIMEOUT_MIN, PEEK_TIMEOUT_MAX),
             pause_timeout: PAUSE_TIMEOUT_DEF.clamp(PAUSE_TIMEOUT_MIN, PAUSE_TIMEOUT_MAX),
+            item_timeout: None,
+            retry: None,
+            results: None,
+            rate_limit: None,
+            dedup: None,
+            batch: None,
+            autoscale: None,
+            journal_sync: JournalSync::default(),
         }
     }
 }
@@ -69,59 +91,157 @@ impl ProducerConsumerOptions {
             ..self.clone()
         }
     }
+
+    pub fn with_item_timeout(&self, item_timeout: Duration) -> Self {
+        ProducerConsumerOptions {
+            item_timeout: Some(item_timeout),
+            ..self.clone()
+        }
+    }
+
+    pub fn with_retry(&self, retry: RetryPolicy) -> Self {
+        ProducerConsumerOptions {
+            retry: Some(retry),
+            ..self.clone()
+        }
+    }
+
+    pub fn with_results(&self, order: ResultsOrder) -> Self {
+        ProducerConsumerOptions {
+            results: Some(order),
+            ..self.clone()
+        }
+    }
+
+    pub fn with_rate_limit(&self, rate_limit: RateLimit) -> Self {
+        ProducerConsumerOptions {
+            rate_limit: Some(rate_limit),
+            ..self.clone()
+        }
+    }
+
+    pub fn with_dedup(&self, dedup: Dedup) -> Self {
+        ProducerConsumerOptions {
+            dedup: Some(dedup),
+            ..self.clone()
+        }
+    }
+
+    pub fn with_batch(&self, batch: Batch) -> Self {
+        ProducerConsumerOptions {
+            batch: Some(batch),
+            ..self.clone()
+        }
+    }
+
+    pub fn with_autoscale(&self, autoscale: AutoScale) -> Self {
+        ProducerConsumerOptions {
+            autoscale: Some(autoscale),
+            ..self.clone()
+        }
+    }
+
+    pub fn with_journal_sync(&self, journal_sync: JournalSync) -> Self {
+        ProducerConsumerOptions {
+            journal_sync,
+            ..self.clone()
+        }
+    }
 }
 
 #[derive(Clone, Debug)]
-pub struct ProducerConsumer<T: StaticTaskItem> {
+pub struct ProducerConsumer<T: StaticTaskItem, R: StaticTaskItem = ()> {
     options: ProducerConsumerOptions,
     started: Arc<Mutex<bool>>,
     finished: Arc<AtomicBool>,
-    finished_cond: Arc<Mutcond>,
-    finished_noti: Arc<Notify>,
+    finished_event: Event,
     completed: Arc<AtomicBool>,
     paused: Arc<AtomicBool>,
     cancelled: Arc<AtomicBool>,
+    token: CancellationToken,
     consumers: Arc<AtomicUsize>,
+    pool: WorkerPool,
     running: Arc<AtomicUsize>,
-    sender: channel::Sender<T>,
-    receiver: channel::Receiver<T>,
+    dead_letters: DeadLetterQueue<T>,
+    results: TaskResults<T, R>,
+    rate_limiter: RateLimiter<T>,
+    dedup: Deduplicator<T>,
+    key_locks: KeyedLocks<T>,
+    stats: TaskStats,
+    events: QueueEvents<T, R>,
+    journal: Option<TaskJournal<T>>,
+    submitted: Arc<AtomicU64>,
+    // Recovered items waiting for room in the channel. The count drops only once an item is in
+    // the channel, so the queue never looks empty in between.
+    pending: Arc<Mutex<VecDeque<QueueItem<T>>>>,
+    pending_len: Arc<AtomicUsize>,
+    sender: channel::Sender<QueueItem<T>>,
+    receiver: channel::Receiver<QueueItem<T>>,
 }
 
-impl<T: StaticTaskItem> ProducerConsumer<T> {
+impl<T: StaticTaskItem, R: StaticTaskItem> ProducerConsumer<T, R> {
     pub fn new() -> Self {
         let options: ProducerConsumerOptions = Default::default();
-        let (sender, receiver) = channel::bounded::<T>(options.capacity);
+        let (sender, receiver) = channel::bounded::<QueueItem<T>>(options.capacity);
         ProducerConsumer {
             options,
             sender,
             receiver,
             started: Arc::new(Mutex::new(false)),
             finished: Arc::new(AtomicBool::new(false)),
-            finished_cond: Arc::new(Mutcond::new()),
-            finished_noti: Arc::new(Notify::new()),
+            finished_event: Event::manual_reset(),
             completed: Arc::new(AtomicBool::new(false)),
             paused: Arc::new(AtomicBool::new(false)),
             cancelled: Arc::new(AtomicBool::new(false)),
+            token: CancellationToken::new(),
             consumers: Arc::new(AtomicUsize::new(0)),
+            pool: WorkerPool::new(THREADS_DEF),
             running: Arc::new(AtomicUsize::new(0)),
+            dead_letters: DeadLetterQueue::new(),
+            results: TaskResults::new(None),
+            rate_limiter: RateLimiter::new(None),
+            dedup: Deduplicator::new(None),
+            key_locks: KeyedLocks::new(),
+            stats: TaskStats::new(),
+            events: QueueEvents::new(),
+            journal: None,
+            submitted: Arc::new(AtomicU64::new(0)),
+            pending: Arc::new(Mutex::new(VecDeque::new())),
+            pending_len: Arc::new(AtomicUsize::new(0)),
         }
     }
 
     pub fn with_options(options: ProducerConsumerOptions) -> Self {
-        let (sender, receiver) = channel::bounded::<T>(options.capacity);
+        let results = TaskResults::new(options.results);
+        let rate_limiter = RateLimiter::new(options.rate_limit);
+        let dedup = Deduplicator::new(options.dedup);
+        let pool = WorkerPool::new(options.threads);
+        let (sender, receiver) = channel::bounded::<QueueItem<T>>(options.capacity);
         ProducerConsumer {
             options,
             sender,
             receiver,
             started: Arc::new(Mutex::new(false)),
             finished: Arc::new(AtomicBool::new(false)),
-            finished_cond: Arc::new(Mutcond::new()),
-            finished_noti: Arc::new(Notify::new()),
+            finished_event: Event::manual_reset(),
             completed: Arc::new(AtomicBool::new(false)),
             paused: Arc::new(AtomicBool::new(false)),
             cancelled: Arc::new(AtomicBool::new(false)),
+            token: CancellationToken::new(),
             consumers: Arc::new(AtomicUsize::new(0)),
+            pool,
             running: Arc::new(AtomicUsize::new(0)),
+            dead_letters: DeadLetterQueue::new(),
+            results,
+            rate_limiter,
+            dedup,
+            key_locks: KeyedLocks::new(),
+            stats: TaskStats::new(),
+            events: QueueEvents::new(),
+            journal: None,
+            submitted: Arc::new(AtomicU64::new(0)),
+            pending: Arc::new(Mutex::new(VecDeque::new())),
+            pending_len: Arc::new(AtomicUsize::new(0)),
         }
     }
 
@@ -136,7 +256,7 @@ impl<T: StaticTaskItem> ProducerConsumer<T> {
             return false;
         }
 
-        *started = true;
+        *started = value;
         true
     }
 
@@ -152,6 +272,10 @@ impl<T: StaticTaskItem> ProducerConsumer<T> {
         self.cancelled.load(Ordering::SeqCst)
     }
 
+    pub fn token(&self) -> &CancellationToken {
+        &self.token
+    }
+
     pub fn is_finished(&self) -> bool {
         self.finished.load(Ordering::SeqCst)
     }
@@ -165,7 +289,7 @@ impl<T: StaticTaskItem> ProducerConsumer<T> {
     }
 
     pub fn len(&self) -> usize {
-        self.sender.len() + self.receiver.len()
+        self.sender.len() + self.receiver.len() + self.pending_len.load(Ordering::SeqCst)
     }
 
     pub fn consumers(&self) -> usize {
@@ -177,8 +301,9 @@ impl<T: StaticTaskItem> ProducerConsumer<T> {
     }
 
     fn dec_consumers(&self) -> bool {
-        self.consumers.fetch_sub(1, Ordering::SeqCst);
-        self.consumers() == 0 && (self.is_completed() || self.is_cancelled())
+        self.pool.exit(&self.consumers, || {
+            self.is_completed() || self.is_cancelled()
+        })
     }
 
     fn finish(&self) {
@@ -186,10 +311,15 @@ impl<T: StaticTaskItem> ProducerConsumer<T> {
             return;
         }
 
+        journal_finished(self.journal.as_ref(), self.is_cancelled());
         self.completed.store(true, Ordering::SeqCst);
+        self.finished.store(true, Ordering::SeqCst);
         self.set_started(false);
-        self.finished_cond.notify_all();
-        self.finished_noti.notify_waiters();
+        self.stats.finish();
+        self.results.close();
+        self.pool.clear_spawner();
+        self.events.emit(|| QueueEvent::Finished);
+        self.finished_event.set();
         thread::sleep(Duration::ZERO);
     }
 
@@ -197,15 +327,62 @@ impl<T: StaticTaskItem> ProducerConsumer<T> {
         self.running.load(Ordering::SeqCst)
     }
 
-    fn inc_running(&self) {
-        self.running.fetch_add(1, Ordering::SeqCst);
+    pub fn threads(&self) -> usize {
+        self.pool.threads()
     }
 
-    fn dec_running(&self) {
-        self.running.fetch_sub(1, Ordering::SeqCst);
+    pub fn set_threads(&self, threads: usize) {
+        self.pool.resize(threads, &self.consumers);
     }
 
-    pub fn start<H: TaskDelegation<ProducerConsumer<T>, T>>(&self, handler: &H) -> Result<()> {
+    pub fn dead_letters(&self) -> &DeadLetterQueue<T> {
+        &self.dead_letters
+    }
+
+    pub fn results(&self) -> &TaskResults<T, R> {
+        &self.results
+    }
+
+    pub fn rate_limiter(&self) -> &RateLimiter<T> {
+        &self.rate_limiter
+    }
+
+    pub fn deduplicator(&self) -> &Deduplicator<T> {
+        &self.dedup
+    }
+
+    pub fn key_locks(&self) -> &KeyedLocks<T> {
+        &self.key_locks
+    }
+
+    pub fn stats(&self) -> QueueStats {
+        self.stats.snapshot(self.len(), self.running())
+    }
+
+    pub fn events(&self) -> &QueueEvents<T, R> {
+        &self.events
+    }
+
+    pub fn subscribe(&self) -> QueueEventReceiver<T, R> {
+        self.events.subscribe()
+    }
+
+    pub fn set_progress(&self, pb: ProgressBar) {
+        self.stats.set_progress(pb);
+    }
+
+    pub fn clear_progress(&self) {
+        self.stats.clear_progress();
+    }
+
+    pub fn journal(&self) -> Option<&TaskJournal<T>> {
+        self.journal.as_ref()
+    }
+
+    pub fn start<H: TaskDelegation<ProducerConsumer<T, R>, T, R>>(
+        &self,
+        handler: &H,
+    ) -> Result<()> {
         if self.is_cancelled() {
             return Err(CanceledError.into());
         }
@@ -218,134 +395,337 @@ impl<T: StaticTaskItem> ProducerConsumer<T> {
             return Err(QueueStartedError.into());
         }
 
-        self.set_consumers(self.options.threads);
+        self.set_consumers(self.threads());
+        self.stats.start();
         handler.on_started(self);
+        self.events.emit(|| QueueEvent::Started);
+        self.feed_pending();
 
-        for _ in 0..self.options.threads {
-            let this = self.clone();
-            let handler = handler.clone();
-            thread::spawn(move || {
-                if this.options.threshold.is_zero() {
-                    loop {
-                        if this.is_cancelled() || (!this.is_busy() && this.is_completed()) {
-                            break;
-                        }
-
-                        if this.is_paused() {
-                            thread::sleep(this.options.pause_timeout);
-                            continue;
-                        }
-
-                        let Ok(item) = this.receiver.recv_timeout(this.options.peek_timeout) else {
-                            continue;
-                        };
-                        this.inc_running();
-                        match handler.process(&this, &item) {
-                            Ok(it) => {
-                                if !handler.on_completed(&this, &item, &it) {
-                                    this.dec_running();
-                                    break;
-                                }
-                            }
-                            Err(e) => {
-                                if !handler.on_completed(
-                                    &this,
-                                    &item,
-                                    &TaskResult::Error(e.get_message()),
-                                ) {
-                                    this.dec_running();
-                                    break;
-                                }
-                            }
-                        }
-                        this.dec_running();
-                    }
+        let this = self.clone();
+        let spawner = handler.clone();
+        self.pool.set_spawner(move || this.spawn_consumer(&spawner));
 
-                    if !this.dec_consumers() {
-                        return;
-                    }
+        for _ in 0..self.threads() {
+            self.spawn_consumer(handler);
+        }
 
-                    if this.is_cancelled() {
-                        handler.on_cancelled(&this);
-                    } else {
-                        handler.on_finished(&this);
-                    }
+        if let Some(scale) = self.options.autoscale {
+            autoscale(self, scale, &self.finished_event);
+        }
 
-                    this.finish();
+        Ok(())
+    }
+
+    fn spawn_consumer<H: TaskDelegation<ProducerConsumer<T, R>, T, R>>(&self, handler: &H) {
+        let this = self.clone();
+        let handler = handler.clone();
+        thread::spawn(move || {
+            loop {
+                if this.pool.retire(&this.consumers) {
                     return;
                 }
 
-                loop {
-                    if this.is_cancelled() || (this.is_busy() && this.is_completed()) {
-                        break;
-                    }
+                if this.is_cancelled() || (!this.is_busy() && this.is_completed()) {
+                    break;
+                }
 
-                    if this.is_paused() {
-                        thread::sleep(this.options.pause_timeout);
-                        continue;
-                    }
+                if this.is_paused() {
+                    thread::sleep(this.options.pause_timeout);
+                    continue;
+                }
 
-                    let Ok(item) = this.receiver.recv_timeout(this.options.peek_timeout) else {
-                        continue;
-                    };
-                    this.inc_running();
-                    match handler.process(&this, &item) {
-                        Ok(it) => {
-                            if !handler.on_completed(&this, &item, &it) {
-                                this.dec_running();
-                                break;
-                            }
-
-                            if !this.options.threshold.is_zero() {
-                                let time = Instant::now();
-
-                                if time.elapsed() < this.options.threshold {
-                                    let remaining = this.options.threshold - time.elapsed();
-                                    thread::sleep(remaining);
-                                }
-                            }
-                        }
-                        Err(e) => {
-                            if !handler.on_completed(
-                                &this,
-                                &item,
-                                &TaskResult::Error(e.get_message()),
-                            ) {
-                                this.dec_running();
-                                break;
-                            }
-                        }
-                    }
-                    this.dec_running();
+                let Ok(QueueItem { index, item, .. }) =
+                    this.receiver.recv_timeout(this.options.peek_timeout)
+                else {
+                    continue;
+                };
+
+                if !run_item(&this, &handler, index, item) {
+                    break;
                 }
+            }
+
+            if !this.dec_consumers() {
+                return;
+            }
+
+            if this.is_cancelled() {
+                handler.on_cancelled(&this);
+            } else {
+                handler.on_finished(&this);
+            }
+
+            this.finish();
+        });
+    }
+
+    pub fn start_async<H: AsyncTaskDelegation<ProducerConsumer<T, R>, T, R>>(
+        &self,
+        handler: &H,
+    ) -> Result<()> {
+        if self.is_cancelled() {
+            return Err(CanceledError.into());
+        }
+
+        if self.is_completed() && self.is_empty() {
+            return Err(QueueCompletedError.into());
+        }
+
+        let runtime = runtime_handle()?;
+
+        if !self.set_started(true) {
+            return Err(QueueStartedError.into());
+        }
 
-                if !this.dec_consumers() {
+        self.set_consumers(self.threads());
+        self.stats.start();
+        handler.on_started(self);
+        self.events.emit(|| QueueEvent::Started);
+        self.feed_pending();
+
+        let this = self.clone();
+        let spawner = handler.clone();
+        let handle = runtime.clone();
+        self.pool
+            .set_spawner(move || this.spawn_consumer_async(&spawner, &handle));
+
+        for _ in 0..self.threads() {
+            self.spawn_consumer_async(handler, &runtime);
+        }
+
+        if let Some(scale) = self.options.autoscale {
+            autoscale(self, scale, &self.finished_event);
+        }
+
+        Ok(())
+    }
+
+    fn spawn_consumer_async<H: AsyncTaskDelegation<ProducerConsumer<T, R>, T, R>>(
+        &self,
+        handler: &H,
+        runtime: &Handle,
+    ) {
+        let this = self.clone();
+        let handler = handler.clone();
+        runtime.spawn(async move {
+            loop {
+                if this.pool.retire(&this.consumers) {
                     return;
                 }
 
-                if this.is_cancelled() {
-                    handler.on_cancelled(&this);
-                } else {
-                    handler.on_finished(&this);
+                if this.is_cancelled() || (!this.is_busy() && this.is_completed()) {
+                    break;
+                }
+
+                if this.is_paused() {
+                    time::sleep(this.options.pause_timeout).await;
+                    continue;
                 }
 
-                this.finish();
-            });
+                let Ok(QueueItem { index, item, .. }) = this.receiver.try_recv() else {
+                    time::sleep(this.options.peek_timeout).await;
+                    continue;
+                };
+
+                if !run_item_async(&this, &handler, index, item).await {
+                    break;
+                }
+            }
+
+            if !this.dec_consumers() {
+                return;
+            }
+
+            if this.is_cancelled() {
+                handler.on_cancelled(&this);
+            } else {
+                handler.on_finished(&this);
+            }
+
+            this.finish();
+        });
+    }
+
+    pub fn start_batch<H: BatchTaskDelegation<ProducerConsumer<T, R>, T, R>>(
+        &self,
+        handler: &H,
+    ) -> Result<()> {
+        if self.is_cancelled() {
+            return Err(CanceledError.into());
+        }
+
+        if self.is_completed() && self.is_empty() {
+            return Err(QueueCompletedError.into());
+        }
+
+        if !self.set_started(true) {
+            return Err(QueueStartedError.into());
+        }
+
+        self.set_consumers(self.threads());
+        self.stats.start();
+        handler.on_started(self);
+        self.events.emit(|| QueueEvent::Started);
+        self.feed_pending();
+
+        let this = self.clone();
+        let spawner = handler.clone();
+        self.pool
+            .set_spawner(move || this.spawn_batch_consumer(&spawner));
+
+        for _ in 0..self.threads() {
+            self.spawn_batch_consumer(handler);
+        }
+
+        if let Some(scale) = self.options.autoscale {
+            autoscale(self, scale, &self.finished_event);
         }
 
         Ok(())
     }
 
-    pub fn enqueue(&self, item: T) -> Result<()> {
+    fn spawn_batch_consumer<H: BatchTaskDelegation<ProducerConsumer<T, R>, T, R>>(
+        &self,
+        handler: &H,
+    ) {
+        let this = self.clone();
+        let handler = handler.clone();
+        let batch = self.options.batch.unwrap_or_default();
+        thread::spawn(move || {
+            loop {
+                if this.pool.retire(&this.consumers) {
+                    return;
+                }
+
+                if this.is_cancelled() || (!this.is_busy() && this.is_completed()) {
+                    break;
+                }
+
+                if this.is_paused() {
+                    thread::sleep(this.options.pause_timeout);
+                    continue;
+                }
+
+                let Ok(first) = this.receiver.recv_timeout(this.options.peek_timeout) else {
+                    continue;
+                };
+                let mut items = vec![first];
+                fill_batch(&mut items, &batch, || this.receiver.try_recv().ok());
+
+                if !run_batch(&this, &handler, items) {
+                    break;
+                }
+            }
+
+            if !this.dec_consumers() {
+                return;
+            }
+
+            if this.is_cancelled() {
+                handler.on_cancelled(&this);
+            } else {
+                handler.on_finished(&this);
+            }
+
+            this.finish();
+        });
+    }
+
+    pub fn start_batch_async<H: AsyncBatchTaskDelegation<ProducerConsumer<T, R>, T, R>>(
+        &self,
+        handler: &H,
+    ) -> Result<()> {
         if self.is_cancelled() {
             return Err(CanceledError.into());
         }
 
-        if self.is_completed() {
+        if self.is_completed() && self.is_empty() {
             return Err(QueueCompletedError.into());
         }
 
-        self.sender.send(item)?;
+        let runtime = runtime_handle()?;
+
+        if !self.set_started(true) {
+            return Err(QueueStartedError.into());
+        }
+
+        self.set_consumers(self.threads());
+        self.stats.start();
+        handler.on_started(self);
+        self.events.emit(|| QueueEvent::Started);
+        self.feed_pending();
+
+        let this = self.clone();
+        let spawner = handler.clone();
+        let handle = runtime.clone();
+        self.pool
+            .set_spawner(move || this.spawn_batch_consumer_async(&spawner, &handle));
+
+        for _ in 0..self.threads() {
+            self.spawn_batch_consumer_async(handler, &runtime);
+        }
+
+        if let Some(scale) = self.options.autoscale {
+            autoscale(self, scale, &self.finished_event);
+        }
+
+        Ok(())
+    }
+
+    fn spawn_batch_consumer_async<H: AsyncBatchTaskDelegation<ProducerConsumer<T, R>, T, R>>(
+        &self,
+        handler: &H,
+        runtime: &Handle,
+    ) {
+        let this = self.clone();
+        let handler = handler.clone();
+        let batch = self.options.batch.unwrap_or_default();
+        runtime.spawn(async move {
+            loop {
+                if this.pool.retire(&this.consumers) {
+                    return;
+                }
+
+                if this.is_cancelled() || (!this.is_busy() && this.is_completed()) {
+                    break;
+                }
+
+                if this.is_paused() {
+                    time::sleep(this.options.pause_timeout).await;
+                    continue;
+                }
+
+                let Ok(first) = this.receiver.try_recv() else {
+                    time::sleep(this.options.peek_timeout).await;
+                    continue;
+                };
+                let mut items = vec![first];
+                fill_batch_async(&mut items, &batch, || this.receiver.try_recv().ok()).await;
+
+                if !run_batch_async(&this, &handler, items).await {
+                    break;
+                }
+            }
+
+            if !this.dec_consumers() {
+                return;
+            }
+
+            if this.is_cancelled() {
+                handler.on_cancelled(&this);
+            } else {
+                handler.on_finished(&this);
+            }
+
+            this.finish();
+        });
+    }
+
+    pub fn enqueue(&self, item: T) -> Result<()> {
+        let Some(item) = self.submit(item)? else {
+            return Ok(());
+        };
+        self.send(item)?;
 
         if !self.options.sleep_after_send.is_zero() {
             thread::sleep(self.options.sleep_after_send);
@@ -354,6 +734,124 @@ impl<T: StaticTaskItem> ProducerConsumer<T> {
         Ok(())
     }
 
+    pub async fn enqueue_async(&self, item: T) -> Result<()> {
+        let Some(item) = self.submit(item)? else {
+            return Ok(());
+        };
+
+        // A full channel is waited on off the runtime, which is what gives the backpressure.
+        if let Err(channel::TrySendError::Full(item)) = self.sender.try_send(item) {
+            let this = self.clone();
+            task::spawn_blocking(move || this.send(item))
+                .await
+                .map_err(|e| RmxError::InvalidOperation(e.to_string()))??;
+        }
+
+        if !self.options.sleep_after_send.is_zero() {
+            time::sleep(self.options.sleep_after_send).await;
+        }
+
+        Ok(())
+    }
+
+    pub async fn enqueue_all(&self, items: impl Stream<Item = T>) -> Result<usize> {
+        enqueue_stream(items, |item| self.enqueue_async(item)).await
+    }
+
+    pub fn sink(&self) -> QueueSink<T> {
+        let this = self.clone();
+        let completer = self.clone();
+        QueueSink::new(
+            move |item| {
+                let this = this.clone();
+                async move { this.enqueue_async(item).await }.boxed()
+            },
+            move || completer.complete(),
+        )
+    }
+
+    pub fn stream(&self) -> TaskOutputStream<T, R> {
+        self.results.subscribe()
+    }
+
+    // Returns None when the item is dropped as a duplicate.
+    fn submit(&self, item: T) -> Result<Option<QueueItem<T>>> {
+        if self.is_cancelled() {
+            return Err(CanceledError.into());
+        }
+
+        if self.is_completed() {
+            return Err(QueueCompletedError.into());
+        }
+
+        if self.dedup.is_duplicate(&item) {
+            return Ok(None);
+        }
+
+        let index = self.submitted.fetch_add(1, Ordering::SeqCst);
+        let item = QueueItem::new(index, item);
+        journal_enqueued(self.journal.as_ref(), &item)?;
+        self.stats.enqueued();
+        self.events.emit(|| QueueEvent::Enqueued {
+            index: item.index,
+            item: item.item.clone(),
+        });
+        Ok(Some(item))
+    }
+
+    // Moves the recovered items into the channel as the consumers make room for them.
+    fn feed_pending(&self) {
+        if self.pending_len.load(Ordering::SeqCst) == 0 {
+            return;
+        }
+
+        let this = self.clone();
+        thread::spawn(move || loop {
+            let Some(item) = this.pending.lock().unwrap().pop_front() else {
+                return;
+            };
+
+            if this.send(item).is_err() {
+                return;
+            }
+
+            this.pending_len.fetch_sub(1, Ordering::SeqCst);
+        });
+    }
+
+    fn send(&self, mut item: QueueItem<T>) -> Result<()> {
+        // A full channel must not block the producer forever once nothing will take the item out,
+        // because the queue is cancelled, finished or every consumer stopped. A paused queue still
+        // holds the producer back until it is resumed.
+        loop {
+            match self.sender.send_timeout(item, self.options.peek_timeout) {
+                Ok(_) => return Ok(()),
+                Err(channel::SendTimeoutError::Timeout(it)) => {
+                    if self.is_cancelled() {
+                        return Err(CanceledError.into());
+                    }
+
+                    if self.is_finished() {
+                        return Err(QueueCompletedError.into());
+                    }
+
+                    if self.is_started() && self.consumers() == 0 {
+                        return Err(RmxError::InvalidOperation(
+                            "The queue has no consumers left".to_string(),
+                        ));
+                    }
+
+                    item = it;
+                }
+                Err(channel::SendTimeoutError::Disconnected(_)) => {
+                    return Err(RmxError::InvalidOperation(
+                        "The queue channel is disconnected".to_string(),
+                    ))
+                }
+            }
+        }
+    }
+
     pub fn stop(&self, enforce: bool) {
         if enforce {
             self.cancel();
@@ -363,68 +861,157 @@ impl<T: StaticTaskItem> ProducerConsumer<T> {
     }
 
     pub fn complete(&self) {
-        self.completed.store(true, Ordering::SeqCst);
+        if !self.completed.swap(true, Ordering::SeqCst) {
+            self.events.emit(|| QueueEvent::Completed);
+        }
     }
 
     pub fn cancel(&self) {
-        self.cancelled.store(true, Ordering::SeqCst);
+        if !self.cancelled.swap(true, Ordering::SeqCst) {
+            self.events.emit(|| QueueEvent::Cancelled);
+        }
+        self.token.cancel();
+        self.finished_event.notify();
     }
 
     pub fn pause(&self) {
-        self.paused.store(true, Ordering::SeqCst);
+        if !self.paused.swap(true, Ordering::SeqCst) {
+            self.events.emit(|| QueueEvent::Paused);
+        }
     }
 
     pub fn resume(&self) {
-        self.paused.store(false, Ordering::SeqCst);
+        if self.paused.swap(false, Ordering::SeqCst) {
+            self.events.emit(|| QueueEvent::Resumed);
+        }
     }
 
     pub fn wait(&self) -> Result<()> {
-        wait(self, &self.finished_cond)
+        wait(self, &self.finished_event)
     }
 
     pub async fn wait_async(&self) -> Result<()> {
-        wait_async(self, &self.finished_noti).await
+        wait_async(self, &self.finished_event).await
     }
 
-    pub fn wait_until(&self, cond: impl Fn(&ProducerConsumer<T>) -> bool) -> Result<()> {
-        wait_until(self, &self.finished_cond, cond)
+    pub fn wait_until(&self, cond: impl Fn(&ProducerConsumer<T, R>) -> bool) -> Result<()> {
+        wait_until(self, &self.finished_event, cond)
     }
 
     pub async fn wait_until_async(
         &self,
-        cond: impl Fn(&ProducerConsumer<T>) -> Pin<Box<dyn Future<Output = bool> + Send>>,
+        cond: impl Fn(&ProducerConsumer<T, R>) -> Pin<Box<dyn Future<Output = bool> + Send>>,
     ) -> Result<()> {
-        wait_until_async(self, &self.finished_noti, cond).await
+        wait_until_async(self, &self.finished_event, cond).await
     }
 
     pub fn wait_for(&self, timeout: Duration) -> Result<()> {
-        wait_for(self, timeout, &self.finished_cond)
+        wait_for(self, timeout, &self.finished_event)
     }
 
     pub async fn wait_for_async(&self, timeout: Duration) -> Result<()> {
-        wait_for_async(self, timeout, &self.finished_noti).await
+        wait_for_async(self, timeout, &self.finished_event).await
     }
 
     pub fn wait_for_until(
         &self,
         timeout: Duration,
-        cond: impl Fn(&ProducerConsumer<T>) -> bool,
+        cond: impl Fn(&ProducerConsumer<T, R>) -> bool,
     ) -> Result<()> {
-        wait_for_until(self, timeout, &self.finished_cond, cond)
+        wait_for_until(self, timeout, &self.finished_event, cond)
     }
 
     pub async fn wait_for_until_async<
-        F: Fn(&ProducerConsumer<T>) -> Pin<Box<dyn Future<Output = bool> + Send>>,
+        F: Fn(&ProducerConsumer<T, R>) -> Pin<Box<dyn Future<Output = bool> + Send>>,
     >(
         &self,
         timeout: Duration,
         cond: F,
     ) -> Result<()> {
-        wait_for_until_async(self, timeout, &self.finished_noti, cond).await
+        wait_for_until_async(self, timeout, &self.finished_event, cond).await
+    }
+}
+
+impl<T: StaticTaskItem + Serialize + DeserializeOwned, R: StaticTaskItem> ProducerConsumer<T, R> {
+    // Starts a new journal, an existing file at the path is truncated. Only resume_from continues
+    // an old journal.
+    pub fn with_journal<P: AsRef<Path>>(options: ProducerConsumerOptions, path: P) -> Result<Self> {
+        let sync = options.journal_sync;
+        let mut this = Self::with_options(options);
+        this.journal = Some(TaskJournal::create(path)?.with_sync(sync));
+        Ok(this)
+    }
+
+    pub fn resume_from<P: AsRef<Path>>(path: P, options: ProducerConsumerOptions) -> Result<Self> {
+        let (items, next) = TaskJournal::<T>::compact(&path)?;
+        let sync = options.journal_sync;
+        let mut this = Self::with_options(options);
+        this.journal = Some(TaskJournal::open(path)?.with_sync(sync));
+        this.submitted.store(next, Ordering::SeqCst);
+
+        // Nothing consumes the channel yet, so the unfinished items wait aside and start() feeds
+        // them in without going over the capacity.
+        for item in &items {
+            this.dedup.remember(&item.item);
+            this.stats.enqueued();
+        }
+
+        this.pending_len.store(items.len(), Ordering::SeqCst);
+        this.pending.lock().unwrap().extend(items);
+        Ok(this)
+    }
+}
+
+impl<T: StaticTaskItem, R: StaticTaskItem> ScalableConsumer for ProducerConsumer<T, R> {
+    fn is_cancelled(&self) -> bool {
+        ProducerConsumer::is_cancelled(self)
+    }
+
+    fn is_finished(&self) -> bool {
+        ProducerConsumer::is_finished(self)
+    }
+
+    fn len(&self) -> usize {
+        ProducerConsumer::len(self)
+    }
+
+    fn running(&self) -> usize {
+        ProducerConsumer::running(self)
+    }
+
+    fn threads(&self) -> usize {
+        ProducerConsumer::threads(self)
+    }
+
+    fn set_threads(&self, threads: usize) {
+        ProducerConsumer::set_threads(self, threads)
+    }
+
+    fn stats(&self) -> QueueStats {
+        ProducerConsumer::stats(self)
+    }
+}
+
+impl<T: StaticTaskItem, R: StaticTaskItem> WorkerQueue<T, R> for ProducerConsumer<T, R> {
+    fn parts(&self) -> WorkerParts<'_, T, R> {
+        WorkerParts {
+            token: &self.token,
+            key_locks: &self.key_locks,
+            rate_limiter: &self.rate_limiter,
+            retry: self.options.retry.as_ref(),
+            dead_letters: &self.dead_letters,
+            item_timeout: self.options.item_timeout,
+            threshold: self.options.threshold,
+            results: &self.results,
+            stats: &self.stats,
+            journal: self.journal.as_ref(),
+            events: &self.events,
+            running: &self.running,
+        }
     }
 }
 
-impl<T: StaticTaskItem> AwaitableConsumer<T> for ProducerConsumer<T> {
+impl<T: StaticTaskItem, R: StaticTaskItem> AwaitableConsumer<T> for ProducerConsumer<T, R> {
     fn is_cancelled(&self) -> bool {
         ProducerConsumer::is_cancelled(self)
     }
@@ -0,0 +1,236 @@
+use indicatif::*;
+use std::{
+    borrow::Cow,
+    fmt,
+    io::{self, IsTerminal},
+    sync::{Arc, Mutex, Weak},
+    thread,
+    time::Duration,
+};
+
+use super::*;
+use crate::Result;
+
+const REPORT_INTERVAL_DEF: Duration = Duration::from_secs(5);
+
+#[derive(Debug)]
+struct ProgressEntry {
+    pb: ProgressBar,
+    // A finished bar is logged once and skipped afterwards.
+    reported: bool,
+}
+
+#[derive(Debug)]
+struct ProgressState {
+    multi: MultiProgress,
+    tty: bool,
+    interval: Duration,
+    entries: Mutex<Vec<ProgressEntry>>,
+    stop: Event,
+}
+
+impl Drop for ProgressState {
+    fn drop(&mut self) {
+        self.stop.set();
+    }
+}
+
+// Hosts several spinners and progress bars at once. When stdout is not a terminal the bars are
+// hidden and their state is logged as plain lines every interval instead.
+#[derive(Clone)]
+pub struct ProgressGroup {
+    state: Arc<ProgressState>,
+}
+
+impl fmt::Debug for ProgressGroup {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("ProgressGroup")
+            .field("tty", &self.state.tty)
+            .field("interval", &self.state.interval)
+            .field("bars", &self.len())
+            .finish()
+    }
+}
+
+impl Default for ProgressGroup {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl ProgressGroup {
+    pub fn new() -> Self {
+        Self::with_interval(REPORT_INTERVAL_DEF)
+    }
+
+    pub fn with_interval(interval: Duration) -> Self {
+        Self::with(io::stdout().is_terminal(), interval)
+    }
+
+    // Always logs plain lines, whatever stdout is.
+    pub fn plain(interval: Duration) -> Self {
+        Self::with(false, interval)
+    }
+
+    fn with(tty: bool, interval: Duration) -> Self {
+        let multi = if tty {
+            MultiProgress::new()
+        } else {
+            MultiProgress::with_draw_target(ProgressDrawTarget::hidden())
+        };
+        let group = ProgressGroup {
+            state: Arc::new(ProgressState {
+                multi,
+                tty,
+                interval,
+                entries: Mutex::new(Vec::new()),
+                stop: Event::manual_reset(),
+            }),
+        };
+
+        if !tty && !interval.is_zero() {
+            let state = Arc::downgrade(&group.state);
+            let stop = group.state.stop.clone();
+            thread::spawn(move || report_periodically(state, stop, interval));
+        }
+
+        group
+    }
+
+    pub fn is_tty(&self) -> bool {
+        self.state.tty
+    }
+
+    pub fn interval(&self) -> Duration {
+        self.state.interval
+    }
+
+    pub fn len(&self) -> usize {
+        self.state.entries.lock().unwrap().len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    pub fn add_spinner(&self, prefix: impl Into<String>) -> Spinner {
+        let mut options = SpinnerOptions::default();
+        options.prefix = Some(prefix.into());
+        self.add_spinner_with_options(options)
+    }
+
+    pub fn add_spinner_with_options(&self, options: SpinnerOptions) -> Spinner {
+        let pb = self.add(ProgressBar::new_spinner());
+        Spinner::with_bar(pb, options)
+    }
+
+    pub fn add_bar(&self, len: u64, prefix: impl Into<Cow<'static, str>>) -> ProgressBar {
+        let style = ProgressStyle::with_template(
+            "{prefix} [{bar:40.cyan/blue}] {pos}/{len} ({percent}%) {msg}",
+        )
+        .unwrap()
+        .progress_chars("=> ");
+        let pb = self.add_bar_with_style(len, style);
+        pb.set_prefix(prefix);
+        pb
+    }
+
+    pub fn add_bar_with_style(&self, len: u64, style: ProgressStyle) -> ProgressBar {
+        self.add(ProgressBar::new(len).with_style(style))
+    }
+
+    fn add(&self, pb: ProgressBar) -> ProgressBar {
+        let pb = self.state.multi.add(pb);
+        self.state.entries.lock().unwrap().push(ProgressEntry {
+            pb: pb.clone(),
+            reported: false,
+        });
+        pb
+    }
+
+    // Prints a line above the bars without breaking them.
+    pub fn println(&self, message: impl AsRef<str>) -> Result<()> {
+        if self.state.tty {
+            self.state.multi.println(message)?;
+        } else {
+            println!("{}", message.as_ref());
+        }
+
+        Ok(())
+    }
+
+    pub fn suspend<F: FnOnce() -> R, R>(&self, f: F) -> R {
+        self.state.multi.suspend(f)
+    }
+
+    // Logs the state of the bars. Does nothing on a terminal where the bars draw themselves.
+    pub fn report(&self) {
+        if !self.state.tty {
+            self.state.report();
+        }
+    }
+
+    // Stops the periodic log lines after logging the final state.
+    pub fn finish(&self) -> Result<()> {
+        if self.state.stop.is_set() {
+            return Ok(());
+        }
+
+        self.state.stop.set();
+
+        if self.state.tty {
+            return Ok(());
+        }
+
+        self.state.report();
+        Ok(())
+    }
+
+    pub fn clear(&self) -> Result<()> {
+        self.state.multi.clear()?;
+        Ok(())
+    }
+}
+
+impl ProgressState {
+    fn report(&self) {
+        let mut entries = self.entries.lock().unwrap();
+
+        for entry in entries.iter_mut().filter(|it| !it.reported) {
+            let pb = &entry.pb;
+            let mut line = pb.prefix();
+
+            if let Some(len) = pb.length() {
+                let percent = if len > 0 {
+                    pb.position().min(len) * 100 / len
+                } else {
+                    100
+                };
+                line = format!("{} {}/{} ({}%)", line, pb.position(), len, percent);
+            }
+
+            let message = pb.message();
+
+            if !message.is_empty() {
+                line = format!("{} {}", line, message);
+            }
+
+            if pb.is_finished() {
+                line = format!("{} [done]", line);
+                entry.reported = true;
+            }
+
+            println!("{}", line.trim_start());
+        }
+    }
+}
+
+fn report_periodically(state: Weak<ProgressState>, stop: Event, interval: Duration) {
+    while !stop.wait_timeout(interval) {
+        let Some(state) = state.upgrade() else {
+            return;
+        };
+
+        state.report();
+    }
+}
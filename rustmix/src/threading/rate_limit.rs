@@ -0,0 +1,196 @@
+use std::{
+    collections::HashMap,
+    fmt,
+    sync::{Arc, Mutex, RwLock},
+};
+use tokio::time::{self, Duration, Instant};
+
+use super::*;
+use crate::{error::*, Result};
+
+pub type RateLimitKey<T> = Arc<dyn Fn(&T) -> String + Send + Sync>;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct RateLimit {
+    pub permits: u32,
+    pub interval: Duration,
+    pub burst: u32,
+}
+
+impl RateLimit {
+    pub fn new(permits: u32, interval: Duration) -> Self {
+        let permits = permits.max(1);
+        RateLimit {
+            permits,
+            interval,
+            burst: permits,
+        }
+    }
+
+    pub fn per_second(permits: u32) -> Self {
+        Self::new(permits, Duration::from_secs(1))
+    }
+
+    pub fn per_minute(permits: u32) -> Self {
+        Self::new(permits, Duration::from_secs(60))
+    }
+
+    pub fn with_burst(&self, burst: u32) -> Self {
+        RateLimit {
+            burst: burst.max(1),
+            ..*self
+        }
+    }
+
+    fn rate(&self) -> f64 {
+        if self.interval.is_zero() {
+            return f64::INFINITY;
+        }
+
+        self.permits as f64 / self.interval.as_secs_f64()
+    }
+}
+
+#[derive(Debug)]
+struct TokenBucket {
+    tokens: f64,
+    updated: Instant,
+}
+
+impl TokenBucket {
+    fn refill(&mut self, rate: f64, capacity: f64, now: Instant) {
+        let elapsed = now.saturating_duration_since(self.updated).as_secs_f64();
+        self.tokens = (self.tokens + elapsed * rate).min(capacity);
+        self.updated = now;
+    }
+}
+
+#[derive(Debug)]
+struct Buckets {
+    keys: HashMap<String, TokenBucket>,
+    purged: Instant,
+}
+
+impl Buckets {
+    // A bucket that refilled completely is the same as a new one, so idle keys are dropped. It
+    // runs at most once per refill period to keep reserve cheap.
+    fn purge(&mut self, rate: f64, capacity: f64, now: Instant) {
+        let period = Duration::from_secs_f64(capacity / rate);
+
+        if now.saturating_duration_since(self.purged) < period {
+            return;
+        }
+
+        self.purged = now;
+        self.keys.retain(|_, bucket| {
+            bucket.refill(rate, capacity, now);
+            bucket.tokens < capacity
+        });
+    }
+}
+
+#[derive(Clone)]
+pub struct RateLimiter<T> {
+    limit: Option<RateLimit>,
+    key: Arc<RwLock<Option<RateLimitKey<T>>>>,
+    buckets: Arc<Mutex<Buckets>>,
+}
+
+impl<T> fmt::Debug for RateLimiter<T> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("RateLimiter")
+            .field("limit", &self.limit)
+            .field("keyed", &self.key.read().unwrap().is_some())
+            .finish()
+    }
+}
+
+impl<T> RateLimiter<T> {
+    pub fn new(limit: Option<RateLimit>) -> Self {
+        RateLimiter {
+            limit,
+            key: Arc::new(RwLock::new(None)),
+            buckets: Arc::new(Mutex::new(Buckets {
+                keys: HashMap::new(),
+                purged: Instant::now(),
+            })),
+        }
+    }
+
+    pub fn limit(&self) -> Option<RateLimit> {
+        self.limit
+    }
+
+    pub fn set_key(&self, key: impl Fn(&T) -> String + Send + Sync + 'static) {
+        let mut guard = self.key.write().unwrap();
+        *guard = Some(Arc::new(key));
+    }
+
+    pub fn clear_key(&self) {
+        let mut guard = self.key.write().unwrap();
+        *guard = None;
+    }
+
+    // Reserves a token for the item and returns how long the caller has to wait before using it.
+    pub fn reserve(&self, item: &T) -> Duration {
+        let Some(limit) = self.limit else {
+            return Duration::ZERO;
+        };
+        let rate = limit.rate();
+
+        if rate.is_infinite() {
+            return Duration::ZERO;
+        }
+
+        let key = match &*self.key.read().unwrap() {
+            Some(key) => key(item),
+            None => String::new(),
+        };
+        let capacity = limit.burst.max(1) as f64;
+        let now = Instant::now();
+        let mut buckets = self.buckets.lock().unwrap();
+        buckets.purge(rate, capacity, now);
+        let bucket = buckets.keys.entry(key).or_insert_with(|| TokenBucket {
+            tokens: capacity,
+            updated: now,
+        });
+        bucket.refill(rate, capacity, now);
+        bucket.tokens -= 1.0;
+
+        if bucket.tokens >= 0.0 {
+            return Duration::ZERO;
+        }
+
+        Duration::from_secs_f64(-bucket.tokens / rate)
+    }
+
+    // Waits for the reserved token, or fails once the token is cancelled.
+    pub fn acquire(&self, item: &T, token: &CancellationToken) -> Result<()> {
+        let delay = self.reserve(item);
+
+        if !delay.is_zero() && token.wait_timeout(delay) {
+            return Err(CanceledError.into());
+        }
+
+        Ok(())
+    }
+
+    pub async fn acquire_async(&self, item: &T, token: &CancellationToken) -> Result<()> {
+        let delay = self.reserve(item);
+
+        if !delay.is_zero() && time::timeout(delay, token.cancelled()).await.is_ok() {
+            return Err(CanceledError.into());
+        }
+
+        Ok(())
+    }
+
+    // The number of keys that have a bucket.
+    pub fn len(&self) -> usize {
+        self.buckets.lock().unwrap().keys.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+}
@@ -0,0 +1,125 @@
+use futures::channel::mpsc;
+use std::{
+    fmt,
+    sync::{Arc, Mutex},
+};
+
+use super::*;
+
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub enum ResultsOrder {
+    #[default]
+    Completion,
+    Submission,
+}
+
+impl fmt::Display for ResultsOrder {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            ResultsOrder::Completion => write!(f, "Completion"),
+            ResultsOrder::Submission => write!(f, "Submission"),
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct TaskOutput<T, R> {
+    pub index: u64,
+    pub item: T,
+    pub result: TaskResult<R>,
+}
+
+pub type TaskOutputStream<T, R> = mpsc::UnboundedReceiver<TaskOutput<T, R>>;
+
+type TaskOutputSenders<T, R> = Option<Vec<mpsc::UnboundedSender<TaskOutput<T, R>>>>;
+
+#[derive(Debug, Clone)]
+pub struct TaskResults<T: StaticTaskItem, R: StaticTaskItem> {
+    order: Option<ResultsOrder>,
+    items: Arc<Mutex<Vec<TaskOutput<T, R>>>>,
+    // None once the queue finished, so late subscribers get an ended stream.
+    subscribers: Arc<Mutex<TaskOutputSenders<T, R>>>,
+}
+
+impl<T: StaticTaskItem, R: StaticTaskItem> TaskResults<T, R> {
+    pub fn new(order: Option<ResultsOrder>) -> Self {
+        TaskResults {
+            order,
+            items: Arc::new(Mutex::new(Vec::new())),
+            subscribers: Arc::new(Mutex::new(Some(Vec::new()))),
+        }
+    }
+
+    pub fn order(&self) -> Option<ResultsOrder> {
+        self.order
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    pub fn len(&self) -> usize {
+        self.items.lock().unwrap().len()
+    }
+
+    // Streams every result completed from now on, whether or not the results are kept.
+    pub fn subscribe(&self) -> TaskOutputStream<T, R> {
+        let (sender, receiver) = mpsc::unbounded();
+
+        if let Some(subscribers) = self.subscribers.lock().unwrap().as_mut() {
+            subscribers.push(sender);
+        }
+
+        receiver
+    }
+
+    pub(super) fn push(&self, index: u64, item: &T, result: &TaskResult<R>) {
+        let output = TaskOutput {
+            index,
+            item: item.clone(),
+            result: result.clone(),
+        };
+
+        if let Some(subscribers) = self.subscribers.lock().unwrap().as_mut() {
+            subscribers.retain(|it| it.unbounded_send(output.clone()).is_ok());
+        }
+
+        if self.order.is_none() {
+            return;
+        }
+
+        self.items.lock().unwrap().push(output);
+    }
+
+    pub(super) fn close(&self) {
+        self.subscribers.lock().unwrap().take();
+    }
+
+    pub fn items(&self) -> Vec<TaskOutput<T, R>> {
+        let items = self.items.lock().unwrap().clone();
+        self.sort(items)
+    }
+
+    pub fn drain(&self) -> Vec<TaskOutput<T, R>> {
+        let items = self.items.lock().unwrap().drain(..).collect();
+        self.sort(items)
+    }
+
+    pub fn outputs(&self) -> Vec<R> {
+        self.items()
+            .into_iter()
+            .filter_map(|it| match it.result {
+                TaskResult::Success(output) => Some(output),
+                _ => None,
+            })
+            .collect()
+    }
+
+    fn sort(&self, mut items: Vec<TaskOutput<T, R>>) -> Vec<TaskOutput<T, R>> {
+        if self.order == Some(ResultsOrder::Submission) {
+            items.sort_by_key(|it| it.index);
+        }
+
+        items
+    }
+}
@@ -0,0 +1,433 @@
+use backoff::{
+    backoff::{Backoff, Constant},
+    ExponentialBackoffBuilder,
+};
+use futures::Future;
+use serde::{Deserialize, Serialize};
+use std::{
+    fmt,
+    path::Path,
+    sync::{Arc, Mutex},
+};
+use tokio::time::{self, Duration};
+
+use super::*;
+use crate::{error::*, io::file, Result};
+
+const RETRY_ATTEMPTS_DEF: usize = 3;
+const RETRY_INTERVAL_DEF: Duration = Duration::from_millis(500);
+
+pub type RetryPredicate = Arc<dyn Fn(&RmxError) -> bool + Send + Sync>;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum RetryBackoff {
+    Fixed(Duration),
+    Exponential { initial: Duration, max: Duration },
+}
+
+impl Default for RetryBackoff {
+    fn default() -> Self {
+        RetryBackoff::Fixed(RETRY_INTERVAL_DEF)
+    }
+}
+
+#[derive(Clone)]
+pub struct RetryPolicy {
+    pub max_attempts: usize,
+    pub backoff: RetryBackoff,
+    retryable: Option<RetryPredicate>,
+}
+
+impl Default for RetryPolicy {
+    fn default() -> Self {
+        RetryPolicy {
+            max_attempts: RETRY_ATTEMPTS_DEF,
+            backoff: Default::default(),
+            retryable: None,
+        }
+    }
+}
+
+impl fmt::Debug for RetryPolicy {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("RetryPolicy")
+            .field("max_attempts", &self.max_attempts)
+            .field("backoff", &self.backoff)
+            .field("retryable", &self.retryable.is_some())
+            .finish()
+    }
+}
+
+impl PartialEq for RetryPolicy {
+    fn eq(&self, other: &Self) -> bool {
+        self.max_attempts == other.max_attempts
+            && self.backoff == other.backoff
+            && match (&self.retryable, &other.retryable) {
+                (Some(a), Some(b)) => Arc::ptr_eq(a, b),
+                (None, None) => true,
+                _ => false,
+            }
+    }
+}
+
+impl Eq for RetryPolicy {}
+
+impl RetryPolicy {
+    pub fn new(max_attempts: usize) -> Self {
+        RetryPolicy {
+            max_attempts: max_attempts.max(1),
+            ..Default::default()
+        }
+    }
+
+    pub fn with_fixed(&self, interval: Duration) -> Self {
+        RetryPolicy {
+            backoff: RetryBackoff::Fixed(interval),
+            ..self.clone()
+        }
+    }
+
+    pub fn with_exponential(&self, initial: Duration, max: Duration) -> Self {
+        RetryPolicy {
+            backoff: RetryBackoff::Exponential { initial, max },
+            ..self.clone()
+        }
+    }
+
+    pub fn with_retryable(
+        &self,
+        retryable: impl Fn(&RmxError) -> bool + Send + Sync + 'static,
+    ) -> Self {
+        RetryPolicy {
+            retryable: Some(Arc::new(retryable)),
+            ..self.clone()
+        }
+    }
+
+    pub fn is_retryable(&self, error: &RmxError) -> bool {
+        match &self.retryable {
+            Some(retryable) => retryable(error),
+            None => true,
+        }
+    }
+
+    fn create_backoff(&self) -> Box<dyn Backoff + Send> {
+        match self.backoff {
+            RetryBackoff::Fixed(interval) => Box::new(Constant::new(interval)),
+            RetryBackoff::Exponential { initial, max } => Box::new(
+                ExponentialBackoffBuilder::new()
+                    .with_initial_interval(initial)
+                    .with_max_interval(max)
+                    .with_max_elapsed_time(None)
+                    .build(),
+            ),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct DeadLetter<T> {
+    pub item: T,
+    pub attempts: usize,
+    pub error: String,
+}
+
+#[derive(Debug, Clone)]
+pub struct DeadLetterQueue<T: StaticTaskItem> {
+    items: Arc<Mutex<Vec<DeadLetter<T>>>>,
+}
+
+impl<T: StaticTaskItem> DeadLetterQueue<T> {
+    pub fn new() -> Self {
+        DeadLetterQueue {
+            items: Arc::new(Mutex::new(Vec::new())),
+        }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    pub fn len(&self) -> usize {
+        self.items.lock().unwrap().len()
+    }
+
+    fn push(&self, item: DeadLetter<T>) {
+        self.items.lock().unwrap().push(item);
+    }
+
+    pub fn items(&self) -> Vec<DeadLetter<T>> {
+        self.items.lock().unwrap().clone()
+    }
+
+    pub fn drain(&self) -> Vec<DeadLetter<T>> {
+        self.items.lock().unwrap().drain(..).collect()
+    }
+
+    // One dead letter per line, the file is replaced atomically so a crash never leaves half of it.
+    pub fn save<P: AsRef<Path>>(&self, path: P) -> Result<()>
+    where
+        T: Serialize,
+    {
+        let items = self.items.lock().unwrap();
+        let mut lines = Vec::with_capacity(items.len());
+
+        for item in items.iter() {
+            lines.push(serde_json::to_string(item)?);
+        }
+
+        file::write_lines_atomic(path, lines.iter())
+    }
+}
+
+fn next_delay(
+    policy: &RetryPolicy,
+    backoff: &mut Box<dyn Backoff + Send>,
+    attempts: usize,
+    error: &RmxError,
+) -> Option<Duration> {
+    if attempts >= policy.max_attempts || !policy.is_retryable(error) {
+        return None;
+    }
+
+    backoff.next_backoff()
+}
+
+fn dead_letter<T: StaticTaskItem, R>(
+    dead_letters: &DeadLetterQueue<T>,
+    item: &T,
+    attempts: usize,
+    error: &RmxError,
+) -> TaskResult<R> {
+    dead_letters.push(DeadLetter {
+        item: item.clone(),
+        attempts,
+        error: error.get_message(),
+    });
+    error.into()
+}
+
+pub(super) fn process_with_retry<T: StaticTaskItem, R: StaticTaskItem>(
+    token: &CancellationToken,
+    item: &T,
+    policy: Option<&RetryPolicy>,
+    dead_letters: &DeadLetterQueue<T>,
+    process: impl Fn() -> Result<R>,
+) -> TaskResult<R> {
+    let Some(policy) = policy else {
+        return process().into();
+    };
+    let mut backoff = policy.create_backoff();
+    let mut attempts = 0;
+
+    loop {
+        attempts += 1;
+        let error = match process() {
+            Ok(it) => return TaskResult::Success(it),
+            Err(e) => e,
+        };
+
+        // An item cut short by cancel() did not run out of attempts.
+        if token.is_cancelled() {
+            return TaskResult::Cancelled;
+        }
+
+        let Some(delay) = next_delay(policy, &mut backoff, attempts, &error) else {
+            return dead_letter(dead_letters, item, attempts, &error);
+        };
+
+        if token.wait_timeout(delay) {
+            return TaskResult::Cancelled;
+        }
+    }
+}
+
+pub(super) async fn process_with_retry_async<
+    T: StaticTaskItem,
+    R: StaticTaskItem,
+    F: Future<Output = Result<R>>,
+>(
+    token: &CancellationToken,
+    item: &T,
+    policy: Option<&RetryPolicy>,
+    dead_letters: &DeadLetterQueue<T>,
+    process: impl Fn() -> F,
+) -> TaskResult<R> {
+    let Some(policy) = policy else {
+        return process().await.into();
+    };
+    let mut backoff = policy.create_backoff();
+    let mut attempts = 0;
+
+    loop {
+        attempts += 1;
+        let error = match process().await {
+            Ok(it) => return TaskResult::Success(it),
+            Err(e) => e,
+        };
+
+        if token.is_cancelled() {
+            return TaskResult::Cancelled;
+        }
+
+        let Some(delay) = next_delay(policy, &mut backoff, attempts, &error) else {
+            return dead_letter(dead_letters, item, attempts, &error);
+        };
+
+        if time::timeout(delay, token.cancelled()).await.is_ok() {
+            return TaskResult::Cancelled;
+        }
+    }
+}
+
+// Tracks the items of a batch across attempts. Each run only gets the items that are still
+// failing, the ones that succeeded keep their result.
+struct BatchRetry<'a, T, R> {
+    items: &'a [T],
+    policy: Option<&'a RetryPolicy>,
+    backoff: Option<Box<dyn Backoff + Send>>,
+    attempts: usize,
+    pending: Vec<usize>,
+    results: Vec<Option<TaskResult<R>>>,
+}
+
+impl<'a, T: StaticTaskItem, R: StaticTaskItem> BatchRetry<'a, T, R> {
+    fn new(items: &'a [T], policy: Option<&'a RetryPolicy>) -> Self {
+        BatchRetry {
+            items,
+            policy,
+            backoff: policy.map(|it| it.create_backoff()),
+            attempts: 0,
+            pending: (0..items.len()).collect(),
+            results: (0..items.len()).map(|_| None).collect(),
+        }
+    }
+
+    fn pending(&self) -> Vec<T> {
+        self.pending
+            .iter()
+            .map(|&index| self.items[index].clone())
+            .collect()
+    }
+
+    // Records a run and returns how long to wait before running the failed items again.
+    fn record(
+        &mut self,
+        token: &CancellationToken,
+        dead_letters: &DeadLetterQueue<T>,
+        results: Result<Vec<Result<R>>>,
+    ) -> Option<Duration> {
+        self.attempts += 1;
+        let pending = std::mem::take(&mut self.pending);
+        let results = batch_results(pending.len(), results);
+        let mut failed = Vec::new();
+
+        for (index, result) in pending.into_iter().zip(results) {
+            self.results[index] = Some(match result {
+                Ok(it) => TaskResult::Success(it),
+                Err(e) if self.policy.is_none() => e.as_ref().into(),
+                // An item cut short by cancel() did not run out of attempts.
+                Err(_) if token.is_cancelled() => TaskResult::Cancelled,
+                Err(e) => {
+                    failed.push((index, e));
+                    continue;
+                }
+            });
+        }
+
+        let policy = self.policy?;
+        let attempts = self.attempts;
+        let (retry, exhausted): (Vec<_>, Vec<_>) = failed
+            .into_iter()
+            .partition(|(_, e)| attempts < policy.max_attempts && policy.is_retryable(e));
+
+        for (index, error) in exhausted {
+            self.dead_letter(dead_letters, index, &error);
+        }
+
+        if retry.is_empty() {
+            return None;
+        }
+
+        let Some(delay) = self.backoff.as_mut().and_then(|it| it.next_backoff()) else {
+            for (index, error) in retry {
+                self.dead_letter(dead_letters, index, &error);
+            }
+
+            return None;
+        };
+
+        self.pending = retry.into_iter().map(|(index, _)| index).collect();
+        Some(delay)
+    }
+
+    fn dead_letter(&mut self, dead_letters: &DeadLetterQueue<T>, index: usize, error: &RmxError) {
+        self.results[index] = Some(dead_letter(
+            dead_letters,
+            &self.items[index],
+            self.attempts,
+            error,
+        ));
+    }
+
+    fn cancel(&mut self) {
+        for index in std::mem::take(&mut self.pending) {
+            self.results[index] = Some(TaskResult::Cancelled);
+        }
+    }
+
+    fn finish(self) -> Vec<TaskResult<R>> {
+        self.results
+            .into_iter()
+            .map(|it| it.unwrap_or(TaskResult::Cancelled))
+            .collect()
+    }
+}
+
+pub(super) fn process_batch_with_retry<T: StaticTaskItem, R: StaticTaskItem>(
+    token: &CancellationToken,
+    items: &[T],
+    policy: Option<&RetryPolicy>,
+    dead_letters: &DeadLetterQueue<T>,
+    process: impl Fn(&[T]) -> Result<Vec<Result<R>>>,
+) -> Vec<TaskResult<R>> {
+    let mut retry = BatchRetry::new(items, policy);
+
+    loop {
+        let results = process(&retry.pending());
+        let Some(delay) = retry.record(token, dead_letters, results) else {
+            return retry.finish();
+        };
+
+        if token.wait_timeout(delay) {
+            retry.cancel();
+            return retry.finish();
+        }
+    }
+}
+
+pub(super) async fn process_batch_with_retry_async<
+    T: StaticTaskItem,
+    R: StaticTaskItem,
+    F: Future<Output = Result<Vec<Result<R>>>>,
+>(
+    token: &CancellationToken,
+    items: &[T],
+    policy: Option<&RetryPolicy>,
+    dead_letters: &DeadLetterQueue<T>,
+    process: impl Fn(Vec<T>) -> F,
+) -> Vec<TaskResult<R>> {
+    let mut retry = BatchRetry::new(items, policy);
+
+    loop {
+        let results = process(retry.pending()).await;
+        let Some(delay) = retry.record(token, dead_letters, results) else {
+            return retry.finish();
+        };
+
+        if time::timeout(delay, token.cancelled()).await.is_ok() {
+            retry.cancel();
+            return retry.finish();
+        }
+    }
+}
@@ -0,0 +1,186 @@
+use std::{
+    fmt,
+    sync::{
+        atomic::{AtomicUsize, Ordering},
+        Arc, Mutex,
+    },
+    thread,
+};
+use tokio::time::Duration;
+
+use super::*;
+
+const AUTOSCALE_INTERVAL_DEF: Duration = Duration::from_secs(1);
+const AUTOSCALE_BACKLOG_DEF: usize = 2;
+
+type WorkerSpawner = Arc<dyn Fn() + Send + Sync>;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct AutoScale {
+    pub min: usize,
+    pub max: usize,
+    pub interval: Duration,
+    // Queued items per worker that trigger adding a worker.
+    pub backlog: usize,
+    // The p90 item latency above which workers are removed, as long as the backlog is not
+    // growing past its threshold. A backed-up queue always gets more workers first.
+    pub latency: Option<Duration>,
+}
+
+impl AutoScale {
+    pub fn new(min: usize, max: usize) -> Self {
+        let min = min.clamp(THREADS_MIN, THREADS_MAX);
+        AutoScale {
+            min,
+            max: max.clamp(min, THREADS_MAX),
+            interval: AUTOSCALE_INTERVAL_DEF,
+            backlog: AUTOSCALE_BACKLOG_DEF,
+            latency: None,
+        }
+    }
+
+    pub fn with_interval(&self, interval: Duration) -> Self {
+        AutoScale { interval, ..*self }
+    }
+
+    pub fn with_backlog(&self, backlog: usize) -> Self {
+        AutoScale {
+            backlog: backlog.max(1),
+            ..*self
+        }
+    }
+
+    pub fn with_latency(&self, latency: Duration) -> Self {
+        AutoScale {
+            latency: Some(latency),
+            ..*self
+        }
+    }
+
+    fn next_threads<Q: ScalableConsumer>(&self, pc: &Q) -> usize {
+        let threads = pc.threads();
+        let pending = pc.len();
+
+        if pending > threads * self.backlog {
+            return (threads + 1).min(self.max);
+        }
+
+        if let Some(latency) = self.latency {
+            if pc.stats().latency_p90 > latency {
+                return threads.saturating_sub(1).max(self.min);
+            }
+        }
+
+        if pending == 0 && pc.running() < threads {
+            return threads.saturating_sub(1).max(self.min);
+        }
+
+        threads.clamp(self.min, self.max)
+    }
+}
+
+pub(super) trait ScalableConsumer: Clone + Send + Sync + 'static {
+    fn is_cancelled(&self) -> bool;
+    fn is_finished(&self) -> bool;
+    fn len(&self) -> usize;
+    fn running(&self) -> usize;
+    fn threads(&self) -> usize;
+    fn set_threads(&self, threads: usize);
+    fn stats(&self) -> QueueStats;
+}
+
+pub(super) fn autoscale<Q: ScalableConsumer>(pc: &Q, scale: AutoScale, finished: &Event) {
+    let pc = pc.clone();
+    let finished = finished.clone();
+    thread::spawn(move || {
+        // The finished event wakes the thread right away, so it lets go of the queue with it.
+        while !finished.wait_timeout(scale.interval) {
+            if pc.is_finished() || pc.is_cancelled() {
+                break;
+            }
+
+            let threads = scale.next_threads(&pc);
+
+            if threads != pc.threads() {
+                pc.set_threads(threads);
+            }
+        }
+    });
+}
+
+#[derive(Clone)]
+pub(super) struct WorkerPool {
+    threads: Arc<AtomicUsize>,
+    spawner: Arc<Mutex<Option<WorkerSpawner>>>,
+}
+
+impl fmt::Debug for WorkerPool {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("WorkerPool")
+            .field("threads", &self.threads())
+            .finish()
+    }
+}
+
+impl WorkerPool {
+    pub fn new(threads: usize) -> Self {
+        WorkerPool {
+            threads: Arc::new(AtomicUsize::new(threads.clamp(THREADS_MIN, THREADS_MAX))),
+            spawner: Arc::new(Mutex::new(None)),
+        }
+    }
+
+    pub fn threads(&self) -> usize {
+        self.threads.load(Ordering::SeqCst)
+    }
+
+    pub fn set_spawner(&self, spawner: impl Fn() + Send + Sync + 'static) {
+        let mut guard = self.spawner.lock().unwrap();
+        *guard = Some(Arc::new(spawner));
+    }
+
+    // The spawner holds a clone of the queue, so it has to be dropped when the queue finishes.
+    pub fn clear_spawner(&self) {
+        let mut guard = self.spawner.lock().unwrap();
+        *guard = None;
+    }
+
+    pub fn resize(&self, threads: usize, workers: &AtomicUsize) {
+        let threads = threads.clamp(THREADS_MIN, THREADS_MAX);
+        let guard = self.spawner.lock().unwrap();
+        self.threads.store(threads, Ordering::SeqCst);
+
+        let Some(spawner) = guard.as_ref() else {
+            return;
+        };
+
+        // Extra workers retire themselves between items.
+        while workers.load(Ordering::SeqCst) < threads {
+            workers.fetch_add(1, Ordering::SeqCst);
+            spawner();
+        }
+    }
+
+    // Takes an exiting worker out and returns true if it is the last one and the queue is done, in
+    // which case the spawner is dropped too. Both happen under the spawner lock, so resize cannot
+    // add a worker after the last one decided to finish the queue.
+    pub fn exit(&self, workers: &AtomicUsize, done: impl FnOnce() -> bool) -> bool {
+        let mut guard = self.spawner.lock().unwrap();
+
+        if workers.fetch_sub(1, Ordering::SeqCst) != 1 || !done() {
+            return false;
+        }
+
+        *guard = None;
+        true
+    }
+
+    pub fn retire(&self, workers: &AtomicUsize) -> bool {
+        let threads = self.threads();
+        workers
+            .fetch_update(Ordering::SeqCst, Ordering::SeqCst, |n| {
+                (n > threads).then(|| n - 1)
+            })
+            .is_ok()
+    }
+}
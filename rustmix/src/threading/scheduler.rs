@@ -0,0 +1,422 @@
+use chrono::{DateTime, Utc};
+use std::{
+    fmt,
+    str::FromStr,
+    sync::{
+        atomic::{AtomicBool, AtomicU64, Ordering},
+        Arc, Mutex,
+    },
+    thread,
+};
+use tokio::time::Duration;
+
+use super::*;
+use crate::{error::*, Result};
+
+pub trait Clock: Send + Sync {
+    fn now(&self) -> DateTime<Utc>;
+}
+
+#[derive(Debug, Default, Clone, Copy)]
+pub struct SystemClock;
+
+impl Clock for SystemClock {
+    fn now(&self) -> DateTime<Utc> {
+        Utc::now()
+    }
+}
+
+// A clock that only moves when told to, so schedules can be verified without waiting.
+#[derive(Debug, Clone)]
+pub struct TestClock {
+    now: Arc<Mutex<DateTime<Utc>>>,
+}
+
+impl TestClock {
+    pub fn new(now: DateTime<Utc>) -> Self {
+        TestClock {
+            now: Arc::new(Mutex::new(now)),
+        }
+    }
+
+    pub fn set(&self, now: DateTime<Utc>) {
+        let mut guard = self.now.lock().unwrap();
+        *guard = now;
+    }
+
+    pub fn advance(&self, duration: Duration) {
+        let mut guard = self.now.lock().unwrap();
+        *guard = add_duration(*guard, duration);
+    }
+}
+
+impl Clock for TestClock {
+    fn now(&self) -> DateTime<Utc> {
+        *self.now.lock().unwrap()
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Schedule {
+    Delay(Duration),
+    At(DateTime<Utc>),
+    // Keeps the cadence of the planned times, the runs it misses are handled by MissedRuns.
+    FixedRate(Duration),
+    // Counts from the moment the previous run was dispatched.
+    FixedDelay(Duration),
+    Cron(cron::Schedule),
+}
+
+impl Schedule {
+    // Accepts the classic 5 fields as well as the 6 or 7 fields form with seconds and years.
+    pub fn cron(expression: &str) -> Result<Self> {
+        let expression = expression.trim();
+        let expression = if expression.split_whitespace().count() == 5 {
+            format!("0 {}", expression)
+        } else {
+            expression.to_string()
+        };
+        let schedule = cron::Schedule::from_str(&expression)
+            .map_err(|e| RmxError::Argument(format!("Invalid cron expression. {}", e)))?;
+        Ok(Schedule::Cron(schedule))
+    }
+
+    fn first(&self, now: DateTime<Utc>) -> Option<DateTime<Utc>> {
+        match self {
+            Schedule::Delay(delay) => Some(add_duration(now, *delay)),
+            Schedule::At(time) => Some(*time),
+            Schedule::FixedRate(interval) | Schedule::FixedDelay(interval) => {
+                Some(add_duration(now, *interval))
+            }
+            Schedule::Cron(schedule) => schedule.after(&now).next(),
+        }
+    }
+
+    // The first planned time after now, skipping the ones missed since due.
+    fn next(&self, due: DateTime<Utc>, now: DateTime<Utc>) -> Option<DateTime<Utc>> {
+        match self {
+            Schedule::Delay(_) | Schedule::At(_) => None,
+            Schedule::FixedRate(interval) => {
+                let runs = u32::try_from(self.missed(due, now)).unwrap_or(u32::MAX);
+                Some(add_duration(due, interval.saturating_mul(runs)))
+            }
+            Schedule::FixedDelay(interval) => Some(add_duration(now, *interval)),
+            Schedule::Cron(schedule) => schedule.after(&now).next(),
+        }
+    }
+
+    // How many planned times of a fixed rate fell between due and now, due included.
+    fn missed(&self, due: DateTime<Utc>, now: DateTime<Utc>) -> usize {
+        let Schedule::FixedRate(interval) = self else {
+            return 1;
+        };
+
+        if now < due {
+            return 0;
+        }
+
+        let elapsed = (now - due).to_std().unwrap_or_default();
+        usize::try_from(elapsed.as_nanos() / interval.as_nanos().max(1))
+            .unwrap_or(usize::MAX)
+            .saturating_add(1)
+    }
+}
+
+// What a fixed rate job does with the runs it missed because the ticks came late, the clock jumped
+// or the interval is shorter than the tick.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum MissedRuns {
+    // Dispatches a single item for all of them.
+    #[default]
+    Coalesce,
+    // Dispatches one item per missed run, but never more than the given number at once.
+    CatchUp(usize),
+}
+
+impl MissedRuns {
+    fn runs(&self, missed: usize) -> usize {
+        match self {
+            MissedRuns::Coalesce => missed.min(1),
+            MissedRuns::CatchUp(max) => missed.min((*max).max(1)),
+        }
+    }
+}
+
+pub trait ScheduleTarget<T>: Send + Sync {
+    fn dispatch(&self, item: T) -> Result<()>;
+}
+
+impl<T: StaticTaskItem, R: StaticTaskItem> ScheduleTarget<T> for Consumer<T, R> {
+    fn dispatch(&self, item: T) -> Result<()> {
+        self.enqueue(item)
+    }
+}
+
+impl<T: StaticTaskItem, R: StaticTaskItem> ScheduleTarget<T> for InjectorWorker<T, R> {
+    fn dispatch(&self, item: T) -> Result<()> {
+        self.enqueue(item)
+    }
+}
+
+#[derive(Debug, Clone)]
+struct ScheduledJob<T> {
+    id: u64,
+    item: T,
+    schedule: Schedule,
+    next: Option<DateTime<Utc>>,
+}
+
+#[derive(Clone)]
+pub struct Scheduler<T: StaticTaskItem> {
+    target: Arc<dyn ScheduleTarget<T>>,
+    clock: Arc<dyn Clock>,
+    missed_runs: MissedRuns,
+    jobs: Arc<Mutex<Vec<ScheduledJob<T>>>>,
+    ids: Arc<AtomicU64>,
+    started: Arc<AtomicBool>,
+    paused: Arc<AtomicBool>,
+    cancelled: Arc<AtomicBool>,
+}
+
+impl<T: StaticTaskItem> fmt::Debug for Scheduler<T> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("Scheduler")
+            .field("jobs", &self.len())
+            .field("missed_runs", &self.missed_runs)
+            .field("started", &self.is_started())
+            .field("paused", &self.is_paused())
+            .field("cancelled", &self.is_cancelled())
+            .finish()
+    }
+}
+
+impl<T: StaticTaskItem> Scheduler<T> {
+    pub fn new(target: impl ScheduleTarget<T> + 'static) -> Self {
+        Self::with_clock(target, SystemClock)
+    }
+
+    pub fn with_clock(
+        target: impl ScheduleTarget<T> + 'static,
+        clock: impl Clock + 'static,
+    ) -> Self {
+        Scheduler {
+            target: Arc::new(target),
+            clock: Arc::new(clock),
+            missed_runs: MissedRuns::default(),
+            jobs: Arc::new(Mutex::new(Vec::new())),
+            ids: Arc::new(AtomicU64::new(1)),
+            started: Arc::new(AtomicBool::new(false)),
+            paused: Arc::new(AtomicBool::new(false)),
+            cancelled: Arc::new(AtomicBool::new(false)),
+        }
+    }
+
+    pub fn with_missed_runs(self, missed_runs: MissedRuns) -> Self {
+        Scheduler {
+            missed_runs,
+            ..self
+        }
+    }
+
+    pub fn now(&self) -> DateTime<Utc> {
+        self.clock.now()
+    }
+
+    pub fn missed_runs(&self) -> MissedRuns {
+        self.missed_runs
+    }
+
+    pub fn is_started(&self) -> bool {
+        self.started.load(Ordering::SeqCst)
+    }
+
+    pub fn is_paused(&self) -> bool {
+        self.paused.load(Ordering::SeqCst)
+    }
+
+    pub fn is_cancelled(&self) -> bool {
+        self.cancelled.load(Ordering::SeqCst)
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    pub fn len(&self) -> usize {
+        self.jobs.lock().unwrap().len()
+    }
+
+    pub fn next_run(&self) -> Option<DateTime<Utc>> {
+        self.jobs
+            .lock()
+            .unwrap()
+            .iter()
+            .filter_map(|it| it.next)
+            .min()
+    }
+
+    pub fn schedule(&self, item: T, schedule: Schedule) -> Result<u64> {
+        if self.is_cancelled() {
+            return Err(CanceledError.into());
+        }
+
+        if let Schedule::FixedRate(interval) | Schedule::FixedDelay(interval) = &schedule {
+            if interval.is_zero() {
+                return Err(RmxError::Argument(
+                    "The interval of a recurring job cannot be zero".to_string(),
+                ));
+            }
+        }
+
+        let Some(next) = schedule.first(self.now()) else {
+            return Err(RmxError::Argument(
+                "The schedule has no upcoming runs".to_string(),
+            ));
+        };
+        let id = self.ids.fetch_add(1, Ordering::SeqCst);
+        self.jobs.lock().unwrap().push(ScheduledJob {
+            id,
+            item,
+            schedule,
+            next: Some(next),
+        });
+        Ok(id)
+    }
+
+    pub fn delay(&self, item: T, delay: Duration) -> Result<u64> {
+        self.schedule(item, Schedule::Delay(delay))
+    }
+
+    pub fn at(&self, item: T, time: DateTime<Utc>) -> Result<u64> {
+        self.schedule(item, Schedule::At(time))
+    }
+
+    pub fn fixed_rate(&self, item: T, interval: Duration) -> Result<u64> {
+        self.schedule(item, Schedule::FixedRate(interval))
+    }
+
+    pub fn fixed_delay(&self, item: T, interval: Duration) -> Result<u64> {
+        self.schedule(item, Schedule::FixedDelay(interval))
+    }
+
+    pub fn cron(&self, item: T, expression: &str) -> Result<u64> {
+        self.schedule(item, Schedule::cron(expression)?)
+    }
+
+    pub fn unschedule(&self, id: u64) -> bool {
+        let mut jobs = self.jobs.lock().unwrap();
+        let len = jobs.len();
+        jobs.retain(|it| it.id != id);
+        jobs.len() != len
+    }
+
+    pub fn clear(&self) {
+        self.jobs.lock().unwrap().clear();
+    }
+
+    // Dispatches every job that is due by the clock and returns how many items were sent.
+    pub fn tick(&self) -> Result<usize> {
+        if self.is_cancelled() {
+            return Err(CanceledError.into());
+        }
+
+        if self.is_paused() {
+            return Ok(0);
+        }
+
+        let now = self.now();
+        let mut due = Vec::new();
+
+        {
+            let mut jobs = self.jobs.lock().unwrap();
+
+            for job in jobs.iter_mut() {
+                let Some(next) = job.next.filter(|it| *it <= now) else {
+                    continue;
+                };
+
+                for _ in 0..self.missed_runs.runs(job.schedule.missed(next, now)) {
+                    due.push((job.id, job.item.clone()));
+                }
+
+                job.next = job.schedule.next(next, now);
+            }
+
+            jobs.retain(|it| it.next.is_some());
+        }
+
+        let mut dispatched = 0;
+
+        for (id, item) in due {
+            // A target that no longer accepts items ends the job.
+            if self.target.dispatch(item).is_err() {
+                self.unschedule(id);
+                continue;
+            }
+
+            dispatched += 1;
+        }
+
+        Ok(dispatched)
+    }
+
+    pub fn start(&self) -> Result<()> {
+        if self.is_cancelled() {
+            return Err(CanceledError.into());
+        }
+
+        if self.started.swap(true, Ordering::SeqCst) {
+            return Err(QueueStartedError.into());
+        }
+
+        let this = self.clone();
+        thread::spawn(move || {
+            while !this.is_cancelled() {
+                thread::sleep(Duration::from_millis(INTERVAL));
+                this.tick().ok();
+            }
+
+            this.started.store(false, Ordering::SeqCst);
+        });
+        Ok(())
+    }
+
+    pub fn pause(&self) {
+        self.paused.store(true, Ordering::SeqCst);
+    }
+
+    // The recurring runs that fell due during the pause are skipped and the jobs continue from
+    // their next planned time, the one-off jobs that fell due still run on the next tick.
+    pub fn resume(&self) {
+        let now = self.now();
+        let mut jobs = self.jobs.lock().unwrap();
+
+        if !self.paused.swap(false, Ordering::SeqCst) {
+            return;
+        }
+
+        for job in jobs.iter_mut() {
+            if matches!(job.schedule, Schedule::Delay(_) | Schedule::At(_)) {
+                continue;
+            }
+
+            if let Some(next) = job.next.filter(|it| *it <= now) {
+                job.next = job.schedule.next(next, now);
+            }
+        }
+
+        jobs.retain(|it| it.next.is_some());
+    }
+
+    pub fn cancel(&self) {
+        self.cancelled.store(true, Ordering::SeqCst);
+        self.clear();
+    }
+}
+
+fn add_duration(time: DateTime<Utc>, duration: Duration) -> DateTime<Utc> {
+    chrono::Duration::from_std(duration)
+        .ok()
+        .and_then(|it| time.checked_add_signed(it))
+        .unwrap_or(DateTime::<Utc>::MAX_UTC)
+}
@@ -0,0 +1,349 @@
+use std::{
+    fmt,
+    sync::{
+        atomic::{AtomicBool, AtomicUsize, Ordering},
+        Arc, Mutex,
+    },
+    thread,
+};
+use tokio::{
+    signal,
+    time::{Duration, Instant},
+};
+
+use super::*;
+use crate::{error::*, io, Result};
+
+const GRACE_DEF: Duration = Duration::from_secs(10);
+// The conventional exit code of a process stopped by SIGINT.
+const EXIT_INTERRUPTED: i32 = 130;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ShutdownOptions {
+    pub grace: Duration,
+    // Exits the process on a third signal, for the targets that ignore the cancel. Off by default,
+    // the host decides whether a library may end the process.
+    pub exit_on_third_signal: bool,
+}
+
+impl Default for ShutdownOptions {
+    fn default() -> Self {
+        ShutdownOptions {
+            grace: GRACE_DEF,
+            exit_on_third_signal: false,
+        }
+    }
+}
+
+impl ShutdownOptions {
+    pub fn new() -> Self {
+        Default::default()
+    }
+
+    pub fn with_grace(&self, grace: Duration) -> Self {
+        ShutdownOptions { grace, ..*self }
+    }
+
+    pub fn with_exit_on_third_signal(&self, exit_on_third_signal: bool) -> Self {
+        ShutdownOptions {
+            exit_on_third_signal,
+            ..*self
+        }
+    }
+}
+
+pub trait ShutdownTarget: Send + Sync {
+    fn complete(&self);
+    fn cancel(&self);
+    // True once the target ran and has nothing left running. A target that never started is
+    // not stopped, it may still start.
+    fn is_stopped(&self) -> bool;
+}
+
+impl<T: StaticTaskItem, R: StaticTaskItem> ShutdownTarget for Consumer<T, R> {
+    fn complete(&self) {
+        Consumer::complete(self)
+    }
+
+    fn cancel(&self) {
+        Consumer::cancel(self)
+    }
+
+    fn is_stopped(&self) -> bool {
+        self.is_finished()
+    }
+}
+
+impl<T: StaticTaskItem, R: StaticTaskItem> ShutdownTarget for ProducerConsumer<T, R> {
+    fn complete(&self) {
+        ProducerConsumer::complete(self)
+    }
+
+    fn cancel(&self) {
+        ProducerConsumer::cancel(self)
+    }
+
+    fn is_stopped(&self) -> bool {
+        self.is_finished()
+    }
+}
+
+impl<T: StaticTaskItem, R: StaticTaskItem> ShutdownTarget for InjectorWorker<T, R> {
+    fn complete(&self) {
+        InjectorWorker::complete(self)
+    }
+
+    fn cancel(&self) {
+        InjectorWorker::cancel(self)
+    }
+
+    fn is_stopped(&self) -> bool {
+        self.is_finished()
+    }
+}
+
+impl ShutdownTarget for Pipeline {
+    fn complete(&self) {
+        // A pipeline that was never started has nothing to drain.
+        Pipeline::complete(self).ok();
+    }
+
+    fn cancel(&self) {
+        Pipeline::cancel(self)
+    }
+
+    fn is_stopped(&self) -> bool {
+        self.is_finished()
+    }
+}
+
+// The first signal completes the registered queues and gives them the grace period to drain,
+// the second one cancels them right away. A third signal only exits the process when the options
+// ask for it.
+#[derive(Clone)]
+pub struct Shutdown {
+    pub options: ShutdownOptions,
+    targets: Arc<Mutex<Vec<Arc<dyn ShutdownTarget>>>>,
+    spinners: Arc<Mutex<Vec<Spinner>>>,
+    signals: Arc<AtomicUsize>,
+    listening: Arc<AtomicBool>,
+    done: Event,
+    token: CancellationToken,
+}
+
+impl fmt::Debug for Shutdown {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("Shutdown")
+            .field("options", &self.options)
+            .field("targets", &self.targets.lock().unwrap().len())
+            .field("spinners", &self.spinners.lock().unwrap().len())
+            .field("signals", &self.signals())
+            .field("done", &self.is_done())
+            .finish()
+    }
+}
+
+impl Default for Shutdown {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Shutdown {
+    pub fn new() -> Self {
+        Self::with_options(Default::default())
+    }
+
+    pub fn with_grace(grace: Duration) -> Self {
+        Self::with_options(ShutdownOptions::new().with_grace(grace))
+    }
+
+    pub fn with_options(options: ShutdownOptions) -> Self {
+        Shutdown {
+            options,
+            targets: Arc::new(Mutex::new(Vec::new())),
+            spinners: Arc::new(Mutex::new(Vec::new())),
+            signals: Arc::new(AtomicUsize::new(0)),
+            listening: Arc::new(AtomicBool::new(false)),
+            done: Event::manual_reset(),
+            token: CancellationToken::new(),
+        }
+    }
+
+    pub fn grace(&self) -> Duration {
+        self.options.grace
+    }
+
+    // Cancelled on the first signal, so long running code can stop on its own.
+    pub fn token(&self) -> &CancellationToken {
+        &self.token
+    }
+
+    pub fn signals(&self) -> usize {
+        self.signals.load(Ordering::SeqCst)
+    }
+
+    pub fn is_requested(&self) -> bool {
+        self.signals() > 0
+    }
+
+    pub fn is_done(&self) -> bool {
+        self.done.is_set()
+    }
+
+    pub fn register(&self, target: impl ShutdownTarget + 'static) {
+        self.targets.lock().unwrap().push(Arc::new(target));
+    }
+
+    pub fn register_spinner(&self, spinner: &Spinner) {
+        self.spinners.lock().unwrap().push(spinner.clone());
+    }
+
+    pub fn clear(&self) {
+        self.targets.lock().unwrap().clear();
+        self.spinners.lock().unwrap().clear();
+    }
+
+    // Listens for SIGINT and SIGTERM on the current tokio runtime, and for the Ctrl+C that
+    // io::get_char reads in raw mode.
+    pub fn listen(&self) -> Result<()> {
+        let runtime = runtime_handle()?;
+
+        if self.listening.swap(true, Ordering::SeqCst) {
+            return Err(RmxError::InvalidOperation(
+                "The shutdown coordinator is already listening".to_string(),
+            ));
+        }
+
+        let this = self.clone();
+        io::set_interrupt_handler(move || this.signal());
+
+        let this = self.clone();
+        runtime.spawn(async move {
+            while wait_for_signal().await.is_ok() {
+                this.signal();
+            }
+
+            this.listening.store(false, Ordering::SeqCst);
+        });
+        Ok(())
+    }
+
+    pub fn signal(&self) {
+        match self.signals.fetch_add(1, Ordering::SeqCst) {
+            0 => {
+                self.token.cancel();
+
+                for target in self.targets() {
+                    target.complete();
+                }
+
+                let this = self.clone();
+                thread::spawn(move || this.drain());
+            }
+            1 => self.abort(),
+            _ if self.options.exit_on_third_signal => {
+                io::restore_terminal().ok();
+                std::process::exit(EXIT_INTERRUPTED);
+            }
+            _ => {}
+        }
+    }
+
+    pub fn wait(&self) {
+        self.done.wait();
+    }
+
+    pub async fn wait_async(&self) {
+        self.done.wait_async().await;
+    }
+
+    fn targets(&self) -> Vec<Arc<dyn ShutdownTarget>> {
+        self.targets.lock().unwrap().clone()
+    }
+
+    fn is_stopped(&self) -> bool {
+        self.targets().iter().all(|it| it.is_stopped())
+    }
+
+    // Returns false if the targets are still running at the deadline.
+    fn wait_stopped(&self, deadline: Instant) -> bool {
+        while !self.is_stopped() {
+            if Instant::now() >= deadline {
+                return false;
+            }
+
+            thread::sleep(Duration::from_millis(INTERVAL));
+        }
+
+        true
+    }
+
+    fn drain(&self) {
+        let deadline = Instant::now() + self.grace();
+
+        while !self.is_stopped() && self.signals() < 2 {
+            if Instant::now() >= deadline {
+                self.abort();
+                return;
+            }
+
+            thread::sleep(Duration::from_millis(INTERVAL));
+        }
+
+        // The second signal aborts and reports done on its own.
+        if self.signals() > 1 {
+            return;
+        }
+
+        for spinner in self.spinners.lock().unwrap().iter() {
+            spinner.finish().ok();
+        }
+
+        self.set_done();
+    }
+
+    // Cancels the targets and reports done once they stopped, or after another grace period for
+    // the ones that ignore the cancel. The wait runs on its own thread, the signal listener has to
+    // stay free for the next signal.
+    fn abort(&self) {
+        for target in self.targets() {
+            target.cancel();
+        }
+
+        for spinner in self.spinners.lock().unwrap().iter() {
+            spinner.abandon().ok();
+        }
+
+        let this = self.clone();
+        thread::spawn(move || {
+            this.wait_stopped(Instant::now() + this.grace());
+            this.set_done();
+        });
+    }
+
+    fn set_done(&self) {
+        if self.is_done() {
+            return;
+        }
+
+        io::restore_terminal().ok();
+        self.done.set();
+    }
+}
+
+#[cfg(unix)]
+async fn wait_for_signal() -> Result<()> {
+    let mut terminate = signal::unix::signal(signal::unix::SignalKind::terminate())?;
+    tokio::select! {
+        result = signal::ctrl_c() => result?,
+        _ = terminate.recv() => {}
+    }
+    Ok(())
+}
+
+#[cfg(not(unix))]
+async fn wait_for_signal() -> Result<()> {
+    signal::ctrl_c().await?;
+    Ok(())
+}
@@ -100,6 +100,14 @@ impl Spinner {
         }
     }
 
+    // Wraps a bar that was already added to a MultiProgress.
+    pub(super) fn with_bar(pb: ProgressBar, options: SpinnerOptions) -> Self {
+        Self {
+            pb: Self::setup(pb, options),
+            is_finished: Arc::new(AtomicBool::new(false)),
+        }
+    }
+
     pub fn with(
         elapsed: Option<Duration>,
         finish: Option<ProgressFinish>,
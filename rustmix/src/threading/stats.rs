@@ -0,0 +1,177 @@
+use indicatif::{ProgressBar, ProgressStyle};
+use std::{
+    collections::VecDeque,
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Arc, Mutex, RwLock,
+    },
+};
+use tokio::time::{Duration, Instant};
+
+use super::*;
+
+const LATENCY_SAMPLES_MAX: usize = 4096;
+
+#[derive(Debug, Default, Clone, PartialEq)]
+pub struct QueueStats {
+    pub enqueued: u64,
+    pub processed: u64,
+    pub succeeded: u64,
+    pub failed: u64,
+    pub cancelled: u64,
+    pub pending: usize,
+    pub running: usize,
+    pub elapsed: Duration,
+    pub items_per_second: f64,
+    pub latency_p50: Duration,
+    pub latency_p90: Duration,
+    pub latency_p99: Duration,
+    pub latency_max: Duration,
+    pub eta: Option<Duration>,
+}
+
+#[derive(Debug, Clone)]
+pub struct TaskStats {
+    enqueued: Arc<AtomicU64>,
+    processed: Arc<AtomicU64>,
+    succeeded: Arc<AtomicU64>,
+    failed: Arc<AtomicU64>,
+    cancelled: Arc<AtomicU64>,
+    started: Arc<Mutex<Option<Instant>>>,
+    latencies: Arc<Mutex<VecDeque<Duration>>>,
+    progress: Arc<RwLock<Option<ProgressBar>>>,
+}
+
+impl TaskStats {
+    pub fn new() -> Self {
+        TaskStats {
+            enqueued: Arc::new(AtomicU64::new(0)),
+            processed: Arc::new(AtomicU64::new(0)),
+            succeeded: Arc::new(AtomicU64::new(0)),
+            failed: Arc::new(AtomicU64::new(0)),
+            cancelled: Arc::new(AtomicU64::new(0)),
+            started: Arc::new(Mutex::new(None)),
+            latencies: Arc::new(Mutex::new(VecDeque::new())),
+            progress: Arc::new(RwLock::new(None)),
+        }
+    }
+
+    pub fn progress_style() -> ProgressStyle {
+        ProgressStyle::with_template(
+            "{spinner:.green} [{elapsed_precise}] [{bar:40.cyan/blue}] {pos}/{len} ({per_sec}, ETA {eta}) {msg}",
+        )
+        .unwrap()
+        .progress_chars("=> ")
+    }
+
+    pub fn set_progress(&self, pb: ProgressBar) {
+        pb.set_length(self.enqueued.load(Ordering::SeqCst));
+        pb.set_position(self.processed.load(Ordering::SeqCst));
+        let mut progress = self.progress.write().unwrap();
+        *progress = Some(pb);
+    }
+
+    pub fn clear_progress(&self) {
+        let mut progress = self.progress.write().unwrap();
+        *progress = None;
+    }
+
+    pub(super) fn start(&self) {
+        let mut started = self.started.lock().unwrap();
+
+        if started.is_none() {
+            *started = Some(Instant::now());
+        }
+    }
+
+    pub(super) fn enqueued(&self) {
+        self.enqueued.fetch_add(1, Ordering::SeqCst);
+
+        if let Some(pb) = &*self.progress.read().unwrap() {
+            pb.inc_length(1);
+        }
+    }
+
+    pub(super) fn record<R>(&self, result: &TaskResult<R>, latency: Duration) {
+        self.processed.fetch_add(1, Ordering::SeqCst);
+        let counter = match result {
+            TaskResult::Success(_) => &self.succeeded,
+            TaskResult::Cancelled => &self.cancelled,
+            _ => &self.failed,
+        };
+        counter.fetch_add(1, Ordering::SeqCst);
+
+        {
+            let mut latencies = self.latencies.lock().unwrap();
+
+            if latencies.len() >= LATENCY_SAMPLES_MAX {
+                latencies.pop_front();
+            }
+
+            latencies.push_back(latency);
+        }
+
+        if let Some(pb) = &*self.progress.read().unwrap() {
+            pb.inc(1);
+        }
+    }
+
+    pub(super) fn finish(&self) {
+        if let Some(pb) = &*self.progress.read().unwrap() {
+            pb.finish();
+        }
+    }
+
+    pub fn snapshot(&self, pending: usize, running: usize) -> QueueStats {
+        let processed = self.processed.load(Ordering::SeqCst);
+        let elapsed = match *self.started.lock().unwrap() {
+            Some(started) => started.elapsed(),
+            None => Duration::ZERO,
+        };
+        let items_per_second = if elapsed.is_zero() {
+            0.0
+        } else {
+            processed as f64 / elapsed.as_secs_f64()
+        };
+        let eta = if items_per_second > 0.0 {
+            Some(Duration::from_secs_f64(
+                (pending + running) as f64 / items_per_second,
+            ))
+        } else {
+            None
+        };
+        let mut latencies = self
+            .latencies
+            .lock()
+            .unwrap()
+            .iter()
+            .copied()
+            .collect::<Vec<_>>();
+        latencies.sort();
+        QueueStats {
+            enqueued: self.enqueued.load(Ordering::SeqCst),
+            processed,
+            succeeded: self.succeeded.load(Ordering::SeqCst),
+            failed: self.failed.load(Ordering::SeqCst),
+            cancelled: self.cancelled.load(Ordering::SeqCst),
+            pending,
+            running,
+            elapsed,
+            items_per_second,
+            latency_p50: percentile(&latencies, 50),
+            latency_p90: percentile(&latencies, 90),
+            latency_p99: percentile(&latencies, 99),
+            latency_max: latencies.last().copied().unwrap_or_default(),
+            eta,
+        }
+    }
+}
+
+fn percentile(sorted: &[Duration], percent: usize) -> Duration {
+    if sorted.is_empty() {
+        return Duration::ZERO;
+    }
+
+    let index = (sorted.len() * percent).div_ceil(100).max(1) - 1;
+    sorted[index.min(sorted.len() - 1)]
+}
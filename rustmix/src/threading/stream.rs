@@ -0,0 +1,103 @@
+use futures::{future::BoxFuture, Future, FutureExt, Sink, Stream, StreamExt};
+use std::{
+    fmt,
+    pin::Pin,
+    sync::Arc,
+    task::{Context, Poll},
+};
+
+use crate::{error::*, Result};
+
+type SinkEnqueue<T> = Arc<dyn Fn(T) -> BoxFuture<'static, Result<()>> + Send + Sync>;
+type SinkComplete = Arc<dyn Fn() + Send + Sync>;
+
+// Closing the sink completes the queue, so the workers finish once it is drained.
+pub struct QueueSink<T> {
+    enqueue: SinkEnqueue<T>,
+    complete: SinkComplete,
+    pending: Option<BoxFuture<'static, Result<()>>>,
+}
+
+impl<T> fmt::Debug for QueueSink<T> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("QueueSink")
+            .field("pending", &self.pending.is_some())
+            .finish()
+    }
+}
+
+impl<T> QueueSink<T> {
+    pub(super) fn new<F, C>(enqueue: F, complete: C) -> Self
+    where
+        F: Fn(T) -> BoxFuture<'static, Result<()>> + Send + Sync + 'static,
+        C: Fn() + Send + Sync + 'static,
+    {
+        QueueSink {
+            enqueue: Arc::new(enqueue),
+            complete: Arc::new(complete),
+            pending: None,
+        }
+    }
+
+    fn poll_pending(&mut self, cx: &mut Context<'_>) -> Poll<Result<()>> {
+        let Some(pending) = self.pending.as_mut() else {
+            return Poll::Ready(Ok(()));
+        };
+
+        let result = futures::ready!(pending.poll_unpin(cx));
+        self.pending = None;
+        Poll::Ready(result)
+    }
+}
+
+impl<T> Unpin for QueueSink<T> {}
+
+impl<T> Sink<T> for QueueSink<T> {
+    type Error = RmxError;
+
+    fn poll_ready(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Result<()>> {
+        self.get_mut().poll_pending(cx)
+    }
+
+    fn start_send(self: Pin<&mut Self>, item: T) -> Result<()> {
+        let this = self.get_mut();
+
+        if this.pending.is_some() {
+            return Err(RmxError::InvalidOperation(
+                "The sink is not ready to accept another item".to_string(),
+            ));
+        }
+
+        this.pending = Some((this.enqueue)(item));
+        Ok(())
+    }
+
+    fn poll_flush(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Result<()>> {
+        self.get_mut().poll_pending(cx)
+    }
+
+    fn poll_close(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Result<()>> {
+        let this = self.get_mut();
+        futures::ready!(this.poll_pending(cx))?;
+        (this.complete)();
+        Poll::Ready(Ok(()))
+    }
+}
+
+pub(super) async fn enqueue_stream<T, S, F, Fut>(items: S, enqueue: F) -> Result<usize>
+where
+    S: Stream<Item = T>,
+    F: Fn(T) -> Fut,
+    Fut: Future<Output = Result<()>>,
+{
+    let mut items = Box::pin(items);
+    let mut count = 0;
+
+    // Each item waits for room in the queue before the next one is pulled from the source.
+    while let Some(item) = items.next().await {
+        enqueue(item).await?;
+        count += 1;
+    }
+
+    Ok(count)
+}
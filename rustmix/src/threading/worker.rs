@@ -0,0 +1,312 @@
+use std::{
+    slice,
+    sync::atomic::{AtomicUsize, Ordering},
+    thread,
+};
+use tokio::time::{self, Duration, Instant};
+
+use super::*;
+
+// What a worker needs from its queue to run items. The queues only differ in how they hand out
+// items, so running them is written once here.
+pub(super) struct WorkerParts<'a, T: StaticTaskItem, R: StaticTaskItem> {
+    pub token: &'a CancellationToken,
+    pub key_locks: &'a KeyedLocks<T>,
+    pub rate_limiter: &'a RateLimiter<T>,
+    pub retry: Option<&'a RetryPolicy>,
+    pub dead_letters: &'a DeadLetterQueue<T>,
+    pub item_timeout: Option<Duration>,
+    pub threshold: Duration,
+    pub results: &'a TaskResults<T, R>,
+    pub stats: &'a TaskStats,
+    pub journal: Option<&'a TaskJournal<T>>,
+    pub events: &'a QueueEvents<T, R>,
+    pub running: &'a AtomicUsize,
+}
+
+impl<T: StaticTaskItem, R: StaticTaskItem> WorkerParts<'_, T, R> {
+    fn started(&self, index: u64, item: &T) {
+        self.events.emit(|| QueueEvent::ItemStarted {
+            index,
+            item: item.clone(),
+        });
+    }
+
+    fn completed(&self, index: u64, item: &T, result: &TaskResult<R>, elapsed: Duration) {
+        self.results.push(index, item, result);
+        self.stats.record(result, elapsed);
+        journal_completed(self.journal, index, result);
+        self.events.emit(|| QueueEvent::ItemCompleted {
+            index,
+            item: item.clone(),
+            result: result.clone(),
+            elapsed,
+        });
+    }
+
+    // Nothing to wait for after an error, the worker moves on right away.
+    fn wait_threshold(&self, results: &[TaskResult<R>]) -> bool {
+        !self.threshold.is_zero() && !results.iter().any(|it| matches!(it, TaskResult::Error(_)))
+    }
+
+    // The next item deferred to the key, None once the key is released. A cancelled queue drops
+    // the deferred items like the ones still in the queue.
+    fn next_deferred(&self, guard: &mut Option<KeyGuard<T>>) -> Option<(u64, T)> {
+        if self.token.is_cancelled() {
+            let dropped = guard.take().map_or(0, KeyGuard::release);
+            self.running.fetch_sub(dropped, Ordering::SeqCst);
+            return None;
+        }
+
+        guard.as_mut()?.next()
+    }
+
+    fn next_deferred_batch(&self, guards: &mut Vec<KeyGuard<T>>) -> Vec<(u64, T)> {
+        if self.token.is_cancelled() {
+            let dropped = guards.drain(..).map(KeyGuard::release).sum();
+            self.running.fetch_sub(dropped, Ordering::SeqCst);
+            return Vec::new();
+        }
+
+        let deferred = guards
+            .iter_mut()
+            .flat_map(|it| it.take_deferred())
+            .collect();
+        guards.retain(|it| !it.is_released());
+        deferred
+    }
+}
+
+pub(super) trait WorkerQueue<T: StaticTaskItem, R: StaticTaskItem>:
+    AwaitableConsumer<T>
+{
+    fn parts(&self) -> WorkerParts<'_, T, R>;
+}
+
+// Returns false when the handler asks the worker to stop. An item whose key is busy is deferred to
+// the worker holding the key, and the items deferred to a key run right after it on the same
+// worker. They already left the queue, so they still run after the handler asked to stop.
+pub(super) fn run_item<Q, H, T, R>(this: &Q, handler: &H, index: u64, item: T) -> bool
+where
+    Q: WorkerQueue<T, R>,
+    H: TaskDelegation<Q, T, R>,
+    T: StaticTaskItem,
+    R: StaticTaskItem,
+{
+    let parts = this.parts();
+    // A deferred item counts as running until the holder of its key runs it.
+    parts.running.fetch_add(1, Ordering::SeqCst);
+    let mut guard = match parts.key_locks.lock_or_defer(index, &item) {
+        KeyLock::Deferred => return true,
+        KeyLock::Locked(guard) => Some(guard),
+        KeyLock::Free => None,
+    };
+    let mut next = Some((index, item));
+    let mut proceed = true;
+
+    while let Some((index, item)) = next.take() {
+        parts.started(index, &item);
+        let now = Instant::now();
+        // Every attempt takes its own token, so retries stay within the rate limit.
+        let result =
+            process_with_retry(parts.token, &item, parts.retry, parts.dead_letters, || {
+                parts.rate_limiter.acquire(&item, parts.token)?;
+                process_with_timeout(handler, this, &item, parts.token, parts.item_timeout)
+            });
+        parts.completed(index, &item, &result, now.elapsed());
+        next = parts.next_deferred(&mut guard);
+        let item_proceed = handler.on_completed(this, &item, &result);
+
+        if item_proceed && parts.wait_threshold(slice::from_ref(&result)) {
+            thread::sleep(parts.threshold);
+        }
+
+        parts.running.fetch_sub(1, Ordering::SeqCst);
+        proceed &= item_proceed;
+    }
+
+    proceed
+}
+
+pub(super) async fn run_item_async<Q, H, T, R>(this: &Q, handler: &H, index: u64, item: T) -> bool
+where
+    Q: WorkerQueue<T, R>,
+    H: AsyncTaskDelegation<Q, T, R>,
+    T: StaticTaskItem,
+    R: StaticTaskItem,
+{
+    let parts = this.parts();
+    parts.running.fetch_add(1, Ordering::SeqCst);
+    let mut guard = match parts.key_locks.lock_or_defer(index, &item) {
+        KeyLock::Deferred => return true,
+        KeyLock::Locked(guard) => Some(guard),
+        KeyLock::Free => None,
+    };
+    let mut next = Some((index, item));
+    let mut proceed = true;
+
+    while let Some((index, item)) = next.take() {
+        parts.started(index, &item);
+        let now = Instant::now();
+        let result = process_with_retry_async(
+            parts.token,
+            &item,
+            parts.retry,
+            parts.dead_letters,
+            || async {
+                parts.rate_limiter.acquire_async(&item, parts.token).await?;
+                process_with_timeout_async(handler, this, &item, parts.token, parts.item_timeout)
+                    .await
+            },
+        )
+        .await;
+        parts.completed(index, &item, &result, now.elapsed());
+        next = parts.next_deferred(&mut guard);
+        let item_proceed = handler.on_completed(this, &item, &result);
+
+        if item_proceed && parts.wait_threshold(slice::from_ref(&result)) {
+            time::sleep(parts.threshold).await;
+        }
+
+        parts.running.fetch_sub(1, Ordering::SeqCst);
+        proceed &= item_proceed;
+    }
+
+    proceed
+}
+
+// Every item of the batch reaches on_completed, even after one of them asked to stop. The items
+// deferred to the keys of the batch run as the next batch.
+pub(super) fn run_batch<Q, H, T, R>(this: &Q, handler: &H, items: Vec<QueueItem<T>>) -> bool
+where
+    Q: WorkerQueue<T, R>,
+    H: BatchTaskDelegation<Q, T, R>,
+    T: StaticTaskItem,
+    R: StaticTaskItem,
+{
+    let parts = this.parts();
+    parts.running.fetch_add(items.len(), Ordering::SeqCst);
+    let items = items.into_iter().map(|it| (it.index, it.item)).collect();
+    let (mut guards, mut items) = parts.key_locks.lock_or_defer_all(items);
+    let mut proceed = true;
+
+    while !items.is_empty() {
+        let batch_items = items.iter().map(|it| it.1.clone()).collect::<Vec<_>>();
+
+        for (index, item) in &items {
+            parts.started(*index, item);
+        }
+
+        let now = Instant::now();
+        let results = process_batch_with_retry(
+            parts.token,
+            &batch_items,
+            parts.retry,
+            parts.dead_letters,
+            |items| {
+                // Only the items that are tried again take another token.
+                for item in items {
+                    parts.rate_limiter.acquire(item, parts.token)?;
+                }
+
+                process_batch_with_timeout(handler, this, items, parts.token, parts.item_timeout)
+            },
+        );
+        let elapsed = now.elapsed();
+
+        for ((index, item), result) in items.iter().zip(results.iter()) {
+            parts.completed(*index, item, result, elapsed);
+        }
+
+        let next = parts.next_deferred_batch(&mut guards);
+        let mut batch_proceed = true;
+
+        for ((_, item), result) in items.iter().zip(results.iter()) {
+            batch_proceed &= handler.on_completed(this, item, result);
+        }
+
+        if batch_proceed && parts.wait_threshold(&results) {
+            thread::sleep(parts.threshold);
+        }
+
+        parts.running.fetch_sub(items.len(), Ordering::SeqCst);
+        proceed &= batch_proceed;
+        items = next;
+    }
+
+    proceed
+}
+
+pub(super) async fn run_batch_async<Q, H, T, R>(
+    this: &Q,
+    handler: &H,
+    items: Vec<QueueItem<T>>,
+) -> bool
+where
+    Q: WorkerQueue<T, R>,
+    H: AsyncBatchTaskDelegation<Q, T, R>,
+    T: StaticTaskItem,
+    R: StaticTaskItem,
+{
+    let parts = this.parts();
+    parts.running.fetch_add(items.len(), Ordering::SeqCst);
+    let items = items.into_iter().map(|it| (it.index, it.item)).collect();
+    let (mut guards, mut items) = parts.key_locks.lock_or_defer_all(items);
+    let mut proceed = true;
+
+    while !items.is_empty() {
+        let batch_items = items.iter().map(|it| it.1.clone()).collect::<Vec<_>>();
+
+        for (index, item) in &items {
+            parts.started(*index, item);
+        }
+
+        let now = Instant::now();
+        let results = process_batch_with_retry_async(
+            parts.token,
+            &batch_items,
+            parts.retry,
+            parts.dead_letters,
+            |items| {
+                let parts = &parts;
+                async move {
+                    for item in &items {
+                        parts.rate_limiter.acquire_async(item, parts.token).await?;
+                    }
+
+                    process_batch_with_timeout_async(
+                        handler,
+                        this,
+                        &items,
+                        parts.token,
+                        parts.item_timeout,
+                    )
+                    .await
+                }
+            },
+        )
+        .await;
+        let elapsed = now.elapsed();
+
+        for ((index, item), result) in items.iter().zip(results.iter()) {
+            parts.completed(*index, item, result, elapsed);
+        }
+
+        let next = parts.next_deferred_batch(&mut guards);
+        let mut batch_proceed = true;
+
+        for ((_, item), result) in items.iter().zip(results.iter()) {
+            batch_proceed &= handler.on_completed(this, item, result);
+        }
+
+        if batch_proceed && parts.wait_threshold(&results) {
+            time::sleep(parts.threshold).await;
+        }
+
+        parts.running.fetch_sub(items.len(), Ordering::SeqCst);
+        proceed &= batch_proceed;
+        items = next;
+    }
+
+    proceed
+}
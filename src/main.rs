@@ -27,6 +27,11 @@ async fn main() -> Result<()> {
     //tests::test_path()?;
     //tests::test_directory()?;
     //tests::test_file()?;
+    //tests::test_file_async().await?;
+    //tests::test_file_records()?;
+    //tests::test_file_jsonl()?;
+    //tests::test_file_atomic()?;
+    //tests::test_file_encodings()?;
 
     //tests::test_url()?;
     //tests::test_reqwest().await?;
@@ -40,8 +45,28 @@ async fn main() -> Result<()> {
 
     //tests::test_consumer(Duration::ZERO).await?;
     //tests::test_consumer(Duration::from_millis(150)).await?;
+    //tests::test_consumer_async(Duration::ZERO).await?;
+    //tests::test_consumer_retry().await?;
+    //tests::test_consumer_priority().await?;
+    //tests::test_consumer_rate_limit().await?;
+    //tests::test_consumer_stats().await?;
+    //tests::test_consumer_scaling().await?;
+    //tests::test_consumer_timeout().await?;
+    //tests::test_pipeline().await?;
+    //tests::test_scheduler().await?;
+    //tests::test_producer_consumer_stream().await?;
+    //tests::test_consumer_keyed().await?;
+    //tests::test_producer_consumer_batch().await?;
+    //tests::test_shutdown().await?;
+    //tests::test_event().await?;
+    //tests::test_progress_group().await?;
+    //tests::test_parallel().await?;
+    //tests::test_injector_worker_benchmark().await?;
+    //tests::test_queue_events().await?;
     //tests::test_producer_consumer(Duration::ZERO).await?;
     //tests::test_producer_consumer(Duration::from_millis(150)).await?;
+    //tests::test_producer_consumer_results().await?;
+    //tests::test_producer_consumer_resume().await?;
     //tests::test_injector_worker(Duration::ZERO).await?;
     //tests::test_injector_worker(Duration::from_millis(150)).await?;
 
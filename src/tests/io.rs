@@ -1,8 +1,9 @@
+use futures::{stream, StreamExt};
 use rand::{distributions::Alphanumeric, Rng};
 use rustmix::{
     io::{
         directory,
-        file::{self, FileEx},
+        file::{self, AsyncFileEx, DelimitedOptions, FileEx, TextEncoding, TextOptions},
         path::{self, IntoPath, PathEx},
     },
     Result,
@@ -10,6 +11,7 @@ use rustmix::{
 use std::{
     io::{stdin, LineWriter, Write},
     path::PathBuf,
+    time::Duration,
 };
 
 use super::*;
@@ -291,6 +293,370 @@ pub fn test_file() -> Result<()> {
     Ok(())
 }
 
+pub async fn test_file_async() -> Result<()> {
+    println!("\nTesting async file functions...");
+
+    let mut path = std::env::temp_dir().join("rustmix_async").join("lines.txt");
+    directory::ensure(path.parent().unwrap())?;
+    println!("I will create the file '{}'", &path.display());
+    let mut file =
+        tokio::fs::File::from_std(file::create_with(&path, file::FileOpenOptions::Truncate)?);
+    file.write_line(&"Hello, world!").await?;
+    file.write_line(&"The next line will be filtered out when the filter is applied.")
+        .await?;
+    file.write_line(&"!12345!").await?;
+    file.write_lines((1..=10).map(|i| format!("Line {}", i)))
+        .await?;
+    drop(file);
+
+    println!("\nI will read the file as a stream of lines.");
+    let mut file = tokio::fs::File::open(&path).await?;
+    let mut lines = file.read_lines();
+
+    while let Some(line) = lines.next().await {
+        println!("{}", line?);
+    }
+
+    println!("\nI will apply the filter now.");
+    let mut file = tokio::fs::File::open(&path).await?;
+    let lines = file
+        .read_lines_filtered(|e: &str| !e.contains("12345"))
+        .collect::<Vec<_>>()
+        .await
+        .into_iter()
+        .collect::<Result<Vec<_>>>()?;
+    println!("{} lines passed the filter", lines.len());
+
+    println!("\nI will read the file in batches of 5 lines and apply the filter.");
+    let mut file = tokio::fs::File::open(&path).await?;
+    let mut batches = file.read_batch_filtered(5, |e: &str| !e.contains("12345"));
+    let mut batch = 0;
+
+    while let Some(lines) = batches.next().await {
+        batch += 1;
+        print_batch(batch, lines?);
+    }
+
+    // A line with invalid UTF-8 is decoded lossily like the sync readers do, not skipped.
+    path.set_extension("broken");
+    tokio::fs::write(&path, b"first\nsecond \xff\nthird\n").await?;
+    let mut file = tokio::fs::File::open(&path).await?;
+    let lines = file
+        .read_lines()
+        .collect::<Vec<_>>()
+        .await
+        .into_iter()
+        .collect::<Result<Vec<_>>>()?;
+    assert_eq!(lines, file::open(&path)?.read()?.collect::<Vec<_>>());
+    println!("Broken lines: {:?}", lines);
+
+    let employees = get_employees(3);
+    println!("\nI will test writing some json.");
+    path.set_extension("json");
+    let mut file = tokio::fs::File::create(&path).await?;
+    file.write_json(&employees, Some(true)).await?;
+    drop(file);
+    let mut file = tokio::fs::File::open(&path).await?;
+    let read: Vec<Employee> = file.read_json().await?;
+    println!("Read {} employees back", read.len());
+
+    println!("\nI will test writing some csv.");
+    path.set_extension("csv");
+    let mut file = tokio::fs::File::create(&path).await?;
+    let written = file
+        .write_delimited(stream::iter(employees), None, Some(true))
+        .await?;
+    println!("Wrote {} records", written);
+    drop(file);
+
+    let mut file = tokio::fs::File::open(&path).await?;
+    let mut records = file.read_delimited(None, Some(true)).await?;
+
+    while let Some(record) = records.next().await {
+        println!("{:?}", record?);
+    }
+
+    path::del(path.parent().unwrap())?;
+    Ok(())
+}
+
+pub fn test_file_records() -> Result<()> {
+    println!("\nTesting typed delimited records...");
+
+    let dir = std::env::temp_dir().join("rustmix_records");
+    let path = dir.join("employees.tsv");
+    let employees = get_employees(5);
+    let options = DelimitedOptions::tsv();
+    let mut file = file::create_with(&path, file::FileOpenOptions::Truncate)?;
+    let written = file.write_records(&employees, &options)?;
+    println!("Wrote {} records to '{}'", written, path.display());
+    drop(file);
+
+    let mut file = file::open(&path)?;
+
+    for employee in file.read_records::<Employee>(&options)? {
+        let employee = employee?;
+        println!("{}: {}", employee.id, employee.name);
+    }
+
+    drop(file);
+
+    println!("\nI will read a csv with comments, padding and a bad value.");
+    let path = dir.join("broken.csv");
+    std::fs::write(
+        &path,
+        "# exported by hand\nid,employee_name,employee_age\n1, Employee 1 ,30\n2,Employee 2,abc\n",
+    )?;
+    let options = DelimitedOptions::csv()
+        .with_comment(b'#')
+        .with_trim(file::Trim::All)
+        .with_flexible(true);
+    let mut file = file::open(&path)?;
+
+    for employee in file.read_records::<Employee>(&options)? {
+        match employee {
+            Ok(employee) => println!("{}: '{}'", employee.id, employee.name),
+            Err(e) => println!("{}", e),
+        }
+    }
+
+    drop(file);
+    path::del(&dir)?;
+    Ok(())
+}
+
+pub fn test_file_jsonl() -> Result<()> {
+    println!("\nTesting JSON lines and streamed JSON arrays...");
+
+    let dir = std::env::temp_dir().join("rustmix_jsonl");
+    let path = dir.join("employees.jsonl");
+    let mut file = file::create_with(&path, file::FileOpenOptions::Truncate)?;
+    file.write_jsonl(get_employees(5).into_iter())?;
+
+    for employee in get_employees(2) {
+        file.append_jsonl(&employee)?;
+    }
+
+    drop(file);
+
+    let file = file::open(&path)?;
+
+    for employee in file.read_jsonl::<Employee>()? {
+        let employee = employee?;
+        println!("{}: {}", employee.id, employee.name);
+    }
+
+    drop(file);
+
+    let file = file::open(&path)?;
+    let batches = file.read_jsonl_batch(3, |batch, employees: Vec<Employee>| {
+        println!("Batch {}: {} employees", batch, employees.len());
+        true
+    })?;
+    println!("Read {} batches", batches);
+    drop(file);
+
+    println!("\nI will stream the elements of a JSON array.");
+    let path = dir.join("employees.json");
+    let mut file = file::create_with(&path, file::FileOpenOptions::Truncate)?;
+    file.write_json(&get_employees(5), Some(true))?;
+    drop(file);
+
+    let file = file::open(&path)?;
+
+    for employee in file.read_json_array::<Employee>()? {
+        let employee = employee?;
+        println!("{}: {}", employee.id, employee.name);
+    }
+
+    drop(file);
+
+    let file = file::open(&path)?;
+    let batches = file.read_json_array_batch(2, |batch, employees: Vec<Employee>| {
+        println!("Batch {}: {} employees", batch, employees.len());
+        batch < 2
+    })?;
+    println!("Stopped after {} batches", batches);
+    drop(file);
+
+    println!("\nI will read an array with a bad element.");
+    let path = dir.join("broken.json");
+    std::fs::write(
+        &path,
+        "[\n  {\"id\": 1, \"employee_name\": \"Employee 1\", \"employee_age\": 30},\n  {\"id\": \"two\"}\n]",
+    )?;
+    let file = file::open(&path)?;
+
+    for employee in file.read_json_array::<Employee>()? {
+        match employee {
+            Ok(employee) => println!("{}: {}", employee.id, employee.name),
+            Err(e) => println!("{}", e),
+        }
+    }
+
+    drop(file);
+    path::del(&dir)?;
+    Ok(())
+}
+
+pub fn test_file_atomic() -> Result<()> {
+    println!("\nTesting atomic writes and file locks...");
+
+    let dir = std::env::temp_dir().join("rustmix_atomic");
+    let path = dir.join("employees.json");
+    file::write_json_atomic(&path, &get_employees(3), Some(true))?;
+    file::write_json_atomic(&path, &get_employees(5), Some(true))?;
+    let employees: Vec<Employee> = file::open(&path)?.read_json()?;
+    println!(
+        "Read {} employees from '{}'",
+        employees.len(),
+        path.display()
+    );
+
+    let path = dir.join("state.txt");
+    file::write_lines_atomic(&path, ["started", "running", "stopped"].iter())?;
+    file::open(&path)?
+        .read()?
+        .for_each(|line| println!("{}", line));
+    println!(
+        "Files in the directory: {}",
+        std::fs::read_dir(&dir)?.count()
+    );
+
+    let first = file::open(&path)?;
+    let second = file::open(&path)?;
+    let lock = first.acquire_lock(file::LockMode::Exclusive)?;
+    println!("First handle locked the file: {:?}", lock.mode());
+    println!(
+        "Second handle got the lock: {}",
+        second.try_acquire_lock(file::LockMode::Shared)?.is_some()
+    );
+
+    match second.acquire_lock_timeout(file::LockMode::Exclusive, Duration::from_millis(200)) {
+        Ok(_) => println!("Second handle got the lock"),
+        Err(e) => println!("Second handle could not lock the file: {}", e),
+    }
+
+    lock.unlock()?;
+    let lock = second.acquire_lock_timeout(file::LockMode::Shared, Duration::from_millis(200))?;
+    let other = first.acquire_lock(file::LockMode::Shared)?;
+    println!(
+        "Both handles hold a lock: {:?} and {:?}",
+        lock.mode(),
+        other.mode()
+    );
+    drop(other);
+    drop(lock);
+    drop(first);
+    drop(second);
+
+    path::del(&dir)?;
+    Ok(())
+}
+
+pub fn test_file_encodings() -> Result<()> {
+    println!("\nTesting text encodings...");
+
+    let dir = std::env::temp_dir().join("rustmix_encodings");
+    let text =
+        "Name,City\r\nJos\u{e9},M\u{fc}nchen\r\nZo\u{eb},\u{20ac}uro \u{201c}city\u{201d}\r\n";
+    let mut utf16le = vec![0xFF, 0xFE];
+    utf16le.extend(text.encode_utf16().flat_map(u16::to_le_bytes));
+    let utf16be = text
+        .encode_utf16()
+        .flat_map(u16::to_be_bytes)
+        .collect::<Vec<_>>();
+    let windows_1252 = b"Name,City\nJos\xe9,M\xfcnchen\nZo\xeb,\x80uro \x93city\x94\n".to_vec();
+    let files = [
+        ("utf16le.csv", utf16le),
+        ("utf16be.csv", utf16be),
+        ("windows1252.csv", windows_1252),
+    ];
+    directory::ensure(&dir)?;
+
+    for (name, bytes) in files {
+        let path = dir.join(name);
+        std::fs::write(&path, bytes)?;
+        let file = file::open(&path)?;
+        let lines = file.read_text(&TextOptions::new())?;
+        println!("{} was detected as {}", name, lines.encoding().name());
+
+        for line in lines {
+            let line = line?;
+            println!("{}: {}", line.number, line.text);
+        }
+    }
+
+    println!("\nI will read a UTF-8 file with broken lines.");
+    let path = dir.join("broken.txt");
+    std::fs::write(
+        &path,
+        b"first line\nsecond \xff line\nthird line\nfourth \xc3 line\n",
+    )?;
+    let file = file::open(&path)?;
+
+    for line in file.read_text(&TextOptions::new().with_encoding(TextEncoding::Utf8))? {
+        match line {
+            Ok(line) => println!("{}: {}", line.number, line.text),
+            Err(e) => println!("{}", e),
+        }
+    }
+
+    drop(file);
+
+    let file = file::open(&path)?;
+    let mut lines = file.read_text(
+        &TextOptions::new()
+            .with_encoding(TextEncoding::Utf8)
+            .with_lossy(true),
+    )?;
+
+    for line in lines.by_ref() {
+        let line = line?;
+        println!("{}: {}", line.number, line.text);
+    }
+
+    println!("Lines with replacements: {:?}", lines.replaced_lines());
+    drop(lines);
+    drop(file);
+
+    // read replaces the invalid bytes instead of dropping the lines.
+    let file = file::open(&path)?;
+    assert_eq!(file.read()?.count(), 4);
+    drop(file);
+
+    println!("\nI will read a Windows-1252 file with a long ASCII header.");
+    let path = dir.join("windows1252_header.csv");
+    let mut bytes = format!("# {}\n", "header ".repeat(1000)).into_bytes();
+    bytes.extend_from_slice(b"Jos\xe9,M\xfcnchen\n");
+    std::fs::write(&path, bytes)?;
+    let file = file::open(&path)?;
+    let mut lines = file.read_text(&TextOptions::new())?;
+    let last = lines.by_ref().last().transpose()?.unwrap();
+    println!("{} was read as {}", last.text, lines.encoding().name());
+    assert_eq!(lines.encoding(), TextEncoding::Windows1252);
+    drop(lines);
+    drop(file);
+
+    println!("\nI will write and read UTF-16 records.");
+    let path = dir.join("employees.csv");
+    let options = DelimitedOptions::csv().with_encoding(TextEncoding::Utf16Le);
+    let mut file = file::create_with(&path, file::FileOpenOptions::Truncate)?;
+    file.write_records(&get_employees(3), &options)?;
+    drop(file);
+
+    let mut file = file::open(&path)?;
+
+    for employee in file.read_records::<Employee>(&options)? {
+        let employee = employee?;
+        println!("{}: {}", employee.id, employee.name);
+    }
+
+    drop(file);
+    path::del(&dir)?;
+    Ok(())
+}
+
 fn delete_dir(path: &PathBuf) -> Result<()> {
     print!("Do you want to delete the directory? (y/n): ");
     std::io::stdout().flush()?;
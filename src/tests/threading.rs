@@ -1,10 +1,14 @@
-use rustmix::{threading::*, Result};
+use chrono::{TimeZone, Utc};
+use futures::{stream, StreamExt};
+use indicatif::ProgressBar;
+use rustmix::{error::RmxError, set_debug, threading::*, Result};
 use std::{
+    collections::HashSet,
     future::Future,
     pin::Pin,
     sync::{
         atomic::{AtomicUsize, Ordering},
-        Arc,
+        Arc, Mutex,
     },
     thread,
     time::Instant,
@@ -42,19 +46,25 @@ impl TaskDelegation<Consumer<usize>, usize> for TaskHandler {
         println!("Consumer started");
     }
 
-    fn process(&self, _pc: &Consumer<usize>, item: &usize) -> Result<TaskResult> {
+    fn process(
+        &self,
+        _pc: &Consumer<usize>,
+        item: &usize,
+        _token: &CancellationToken,
+    ) -> Result<()> {
         self.tasks.fetch_add(1, Ordering::SeqCst);
         println!("Item: {}", item);
 
         if item % 5 == 0 {
-            return Ok(TaskResult::Error(
-                format!("Item {}. Multiples of 5 are not allowed", item).into(),
-            ));
+            return Err(RmxError::Argument(format!(
+                "Item {}. Multiples of 5 are not allowed",
+                item
+            )));
         } else if item % 3 == 0 {
-            return Ok(TaskResult::TimedOut);
+            return Err(RmxError::Timeout);
         }
 
-        Ok(TaskResult::Success)
+        Ok(())
     }
 
     fn on_completed(&self, _pc: &Consumer<usize>, item: &usize, result: &TaskResult) -> bool {
@@ -88,6 +98,8 @@ pub async fn test_consumer(cancel_after: Duration) -> Result<()> {
     let options = ConsumerOptions::new();
     let consumer = Consumer::<usize>::with_options(options);
     consumer.start(&handler.clone())?;
+    // A running consumer does not start a second set of workers.
+    assert!(consumer.start(&handler.clone()).is_err());
 
     for i in 1..=TEST_SIZE {
         if let Err(e) = consumer.enqueue(i) {
@@ -124,19 +136,25 @@ impl TaskDelegation<ProducerConsumer<usize>, usize> for TaskHandler {
         println!("Producer/Consumer started");
     }
 
-    fn process(&self, _pc: &ProducerConsumer<usize>, item: &usize) -> Result<TaskResult> {
+    fn process(
+        &self,
+        _pc: &ProducerConsumer<usize>,
+        item: &usize,
+        _token: &CancellationToken,
+    ) -> Result<()> {
         self.tasks.fetch_add(1, Ordering::SeqCst);
         println!("Item: {}", item);
 
         if item % 5 == 0 {
-            return Ok(TaskResult::Error(
-                format!("Item {}. Multiples of 5 are not allowed", item).into(),
-            ));
+            return Err(RmxError::Argument(format!(
+                "Item {}. Multiples of 5 are not allowed",
+                item
+            )));
         } else if item % 3 == 0 {
-            return Ok(TaskResult::TimedOut);
+            return Err(RmxError::Timeout);
         }
 
-        Ok(TaskResult::Success)
+        Ok(())
     }
 
     fn on_completed(
@@ -213,19 +231,25 @@ impl TaskDelegation<InjectorWorker<usize>, usize> for TaskHandler {
         println!("Injector/Worker started");
     }
 
-    fn process(&self, _pc: &InjectorWorker<usize>, item: &usize) -> Result<TaskResult> {
+    fn process(
+        &self,
+        _pc: &InjectorWorker<usize>,
+        item: &usize,
+        _token: &CancellationToken,
+    ) -> Result<()> {
         self.tasks.fetch_add(1, Ordering::SeqCst);
         println!("Item: {}", item);
 
         if item % 5 == 0 {
-            return Ok(TaskResult::Error(
-                format!("Item {}. Multiples of 5 are not allowed", item).into(),
-            ));
+            return Err(RmxError::Argument(format!(
+                "Item {}. Multiples of 5 are not allowed",
+                item
+            )));
         } else if item % 3 == 0 {
-            return Ok(TaskResult::TimedOut);
+            return Err(RmxError::Timeout);
         }
 
-        Ok(TaskResult::Success)
+        Ok(())
     }
 
     fn on_completed(&self, _pc: &InjectorWorker<usize>, item: &usize, result: &TaskResult) -> bool {
@@ -289,3 +313,1528 @@ pub async fn test_injector_worker(cancel_after: Duration) -> Result<()> {
     println!("Elapsed time: {:?}", now.elapsed());
     Ok(())
 }
+
+impl AsyncTaskDelegation<Consumer<usize>, usize> for TaskHandler {
+    fn on_started(&self, _pc: &Consumer<usize>) {
+        println!("Async Consumer started");
+    }
+
+    async fn process(
+        &self,
+        _pc: &Consumer<usize>,
+        item: &usize,
+        _token: &CancellationToken,
+    ) -> Result<()> {
+        self.tasks.fetch_add(1, Ordering::SeqCst);
+        tokio::time::sleep(Duration::from_millis(10)).await;
+        println!("Item: {}", item);
+
+        if item % 5 == 0 {
+            return Err(RmxError::Argument(format!(
+                "Item {}. Multiples of 5 are not allowed",
+                item
+            )));
+        } else if item % 3 == 0 {
+            return Err(RmxError::Timeout);
+        }
+
+        Ok(())
+    }
+
+    fn on_completed(&self, _pc: &Consumer<usize>, item: &usize, result: &TaskResult) -> bool {
+        self.done.fetch_add(1, Ordering::SeqCst);
+        println!("Result item: {}: {:?}", item, result);
+        true
+    }
+
+    fn on_cancelled(&self, _pc: &Consumer<usize>) {
+        println!(
+            "Cancelled. Got: {} tasks and finished {} tasks.",
+            self.tasks(),
+            self.done()
+        );
+    }
+
+    fn on_finished(&self, _pc: &Consumer<usize>) {
+        println!(
+            "Finished. Got: {} tasks and finished {} tasks.",
+            self.tasks(),
+            self.done()
+        );
+    }
+}
+
+pub async fn test_consumer_async(cancel_after: Duration) -> Result<()> {
+    println!("\nTesting async Consumer with {} tasks...", THREADS);
+
+    let now = Instant::now();
+    let handler = TaskHandler::new();
+    let options = ConsumerOptions::new().with_threads(THREADS);
+    let consumer = Consumer::<usize>::with_options(options);
+    consumer.start_async(&handler)?;
+
+    for i in 1..=TEST_SIZE {
+        if let Err(e) = consumer.enqueue(i) {
+            println!("Enqueue error: {:?}", e);
+            break;
+        }
+    }
+
+    consumer.complete();
+
+    if !cancel_after.is_zero() {
+        let ptr = consumer.clone();
+        tokio::spawn(async move {
+            tokio::time::sleep(cancel_after).await;
+
+            if ptr.is_finished() {
+                return;
+            }
+
+            ptr.cancel();
+        });
+    }
+
+    match consumer.wait_async().await {
+        Ok(_) => println!("Async Consumer finished"),
+        Err(e) => println!("Async Consumer error: {:?}", e),
+    }
+    println!("Elapsed time: {:?}", now.elapsed());
+    Ok(())
+}
+
+#[derive(Clone, Debug)]
+pub struct FlakyTaskHandler {
+    pub attempts: Arc<AtomicUsize>,
+}
+
+impl TaskDelegation<Consumer<usize>, usize> for FlakyTaskHandler {
+    fn on_started(&self, _pc: &Consumer<usize>) {
+        println!("Flaky Consumer started");
+    }
+
+    fn process(
+        &self,
+        _pc: &Consumer<usize>,
+        item: &usize,
+        _token: &CancellationToken,
+    ) -> Result<()> {
+        let attempt = self.attempts.fetch_add(1, Ordering::SeqCst);
+
+        if item % 7 == 0 {
+            return Err(RmxError::Network(format!("Item {} is unreachable", item)));
+        } else if item % 11 == 0 {
+            return Err(RmxError::NotSupported);
+        } else if item % 2 == 0 && attempt % 2 == 0 {
+            return Err(RmxError::Timeout);
+        }
+
+        Ok(())
+    }
+
+    fn on_completed(&self, _pc: &Consumer<usize>, item: &usize, result: &TaskResult) -> bool {
+        println!("Result item: {}: {:?}", item, result);
+        true
+    }
+
+    fn on_cancelled(&self, _pc: &Consumer<usize>) {
+        println!("Cancelled.");
+    }
+
+    fn on_finished(&self, pc: &Consumer<usize>) {
+        println!(
+            "Finished after {} attempts with {} dead letters.",
+            self.attempts.load(Ordering::SeqCst),
+            pc.dead_letters().len()
+        );
+    }
+}
+
+pub async fn test_consumer_retry() -> Result<()> {
+    println!("\nTesting Consumer retries with {} threads...", THREADS);
+
+    let handler = FlakyTaskHandler {
+        attempts: Arc::new(AtomicUsize::new(0)),
+    };
+    let retry = RetryPolicy::new(3)
+        .with_exponential(Duration::from_millis(10), Duration::from_millis(100))
+        .with_retryable(|e| !matches!(e, RmxError::NotSupported));
+    let options = ConsumerOptions::new()
+        .with_threads(THREADS)
+        .with_retry(retry);
+    let consumer = Consumer::<usize>::with_options(options);
+    consumer.start(&handler)?;
+
+    for i in 1..=100 {
+        consumer.enqueue(i)?;
+    }
+
+    consumer.complete();
+    consumer.wait_async().await?;
+
+    // Saved one dead letter per line.
+    let path = std::env::temp_dir().join("rustmix_dead_letters.jsonl");
+    consumer.dead_letters().save(&path)?;
+    let saved = std::fs::read_to_string(&path)?;
+    assert_eq!(saved.lines().count(), consumer.dead_letters().len());
+    std::fs::remove_file(&path)?;
+
+    for letter in consumer.dead_letters().drain() {
+        println!(
+            "Dead letter: {} after {} attempts. {}",
+            letter.item, letter.attempts, letter.error
+        );
+    }
+
+    Ok(())
+}
+
+#[derive(Clone, Debug)]
+pub struct SquareTaskHandler;
+
+impl TaskDelegation<ProducerConsumer<usize, usize>, usize, usize> for SquareTaskHandler {
+    fn on_started(&self, _pc: &ProducerConsumer<usize, usize>) {
+        println!("Typed Producer/Consumer started");
+    }
+
+    fn process(
+        &self,
+        _pc: &ProducerConsumer<usize, usize>,
+        item: &usize,
+        _token: &CancellationToken,
+    ) -> Result<usize> {
+        if item % 10 == 0 {
+            return Err(RmxError::Argument(format!("Item {} is ignored", item)));
+        }
+
+        Ok(item * item)
+    }
+
+    fn on_completed(
+        &self,
+        _pc: &ProducerConsumer<usize, usize>,
+        item: &usize,
+        result: &TaskResult<usize>,
+    ) -> bool {
+        println!("Result item: {}: {:?}", item, result);
+        true
+    }
+
+    fn on_cancelled(&self, _pc: &ProducerConsumer<usize, usize>) {
+        println!("Cancelled.");
+    }
+
+    fn on_finished(&self, pc: &ProducerConsumer<usize, usize>) {
+        println!("Finished with {} results.", pc.results().len());
+    }
+}
+
+pub async fn test_producer_consumer_results() -> Result<()> {
+    println!(
+        "\nTesting typed Producer/Consumer results with {} threads...",
+        THREADS
+    );
+
+    let options = ProducerConsumerOptions::new()
+        .with_threads(THREADS)
+        .with_results(ResultsOrder::Submission);
+    let prodcon = ProducerConsumer::<usize, usize>::with_options(options);
+    prodcon.start(&SquareTaskHandler)?;
+
+    for i in 1..=100 {
+        prodcon.enqueue(i)?;
+    }
+
+    prodcon.complete();
+    prodcon.wait_async().await?;
+
+    for output in prodcon.results().items() {
+        println!("#{} {}: {}", output.index, output.item, output.result);
+    }
+
+    println!("Outputs: {:?}", prodcon.results().outputs());
+    Ok(())
+}
+
+pub async fn test_producer_consumer_resume() -> Result<()> {
+    println!("\nTesting Producer/Consumer checkpoint and resume...");
+
+    let path = std::env::temp_dir().join("rustmix_resume.jsonl");
+    std::fs::remove_file(&path).ok();
+
+    let options = ProducerConsumerOptions::new()
+        .with_capacity(20)
+        .with_threads(2)
+        .with_threshold(Duration::from_millis(50))
+        .with_results(ResultsOrder::Submission)
+        .with_journal_sync(JournalSync::Interval(Duration::from_millis(20)));
+    let prodcon = ProducerConsumer::<usize, usize>::with_journal(options.clone(), &path)?;
+
+    prodcon.start(&SquareTaskHandler)?;
+
+    for i in 1..=20 {
+        prodcon.enqueue(i)?;
+    }
+
+    tokio::time::sleep(Duration::from_millis(200)).await;
+    // Simulate a crash half way through the job
+    prodcon.cancel();
+    prodcon.wait_async().await.ok();
+    println!(
+        "Processed {} items before stopping",
+        prodcon.results().len()
+    );
+
+    // A new journal over an old one starts from scratch instead of mixing up the indexes.
+    let copy = std::env::temp_dir().join("rustmix_resume_copy.jsonl");
+    std::fs::copy(&path, &copy)?;
+    let fresh = Consumer::<usize>::with_journal(ConsumerOptions::new(), &copy)?;
+
+    for i in 1..=3 {
+        fresh.enqueue(i)?;
+    }
+
+    let (items, next) = TaskJournal::<usize>::load(&copy)?;
+    assert_eq!(
+        items.iter().map(|it| it.item).collect::<Vec<_>>(),
+        [1, 2, 3]
+    );
+    assert_eq!(next, 3);
+    drop(fresh);
+    std::fs::remove_file(&copy)?;
+
+    // The unfinished items are fed in as the consumers make room, even through a smaller channel.
+    let options = options.with_capacity(4).with_dedup(Dedup::new(100));
+    let prodcon = ProducerConsumer::<usize, usize>::resume_from(&path, options)?;
+    println!("Resuming with {} unfinished items", prodcon.len());
+    // The journal is compacted down to the unfinished items, which the deduplicator now knows.
+    let lines = std::fs::read_to_string(&path)?.lines().count();
+    assert_eq!(lines, prodcon.len().max(1));
+    assert_eq!(prodcon.deduplicator().len(), prodcon.len());
+    prodcon.start(&SquareTaskHandler)?;
+    prodcon.complete();
+    prodcon.wait_async().await?;
+    // Nothing is left to resume after a clean finish.
+    assert_eq!(std::fs::metadata(&path)?.len(), 0);
+
+    for output in prodcon.results().items() {
+        println!("#{} {}: {}", output.index, output.item, output.result);
+    }
+
+    std::fs::remove_file(&path)?;
+    Ok(())
+}
+
+pub async fn test_consumer_priority() -> Result<()> {
+    println!("\nTesting Consumer with priority behavior...");
+
+    let handler = TaskHandler::new();
+    let options = ConsumerOptions::new()
+        .with_behavior(QueueBehavior::Priority)
+        .with_results(ResultsOrder::Completion);
+    let consumer = Consumer::<usize>::with_options(options);
+
+    for i in 1..=30 {
+        consumer.enqueue_with_priority(i, (i % 3) as i32)?;
+    }
+
+    consumer.enqueue_with_priority_async(31, 3).await?;
+    consumer.start(&handler)?;
+    consumer.complete();
+    consumer.wait_async().await?;
+
+    let order = consumer
+        .results()
+        .items()
+        .iter()
+        .map(|it| it.item)
+        .collect::<Vec<_>>();
+    println!("Completion order: {:?}", order);
+
+    // Other behaviors reject a priority rather than drop it.
+    let fifo = Consumer::<usize>::new();
+    assert!(fifo.enqueue_with_priority(1, 1).is_err());
+    assert!(fifo.enqueue_with_priority_async(1, 1).await.is_err());
+
+    // A pinned item keeps its place in the priority order.
+    let injwork = InjectorWorker::<usize>::with_options(
+        InjectorWorkerOptions::new()
+            .with_behavior(QueueBehavior::Priority)
+            .with_threads(1)
+            .with_results(ResultsOrder::Completion),
+    );
+
+    for i in 1..=5 {
+        injwork.enqueue_with_priority(i, i as i32)?;
+    }
+
+    injwork.enqueue_pinned(0, 0)?;
+    injwork.start(&CountTaskHandler::default())?;
+    injwork.complete();
+    injwork.wait_async().await?;
+
+    let order = injwork
+        .results()
+        .items()
+        .iter()
+        .map(|it| it.item)
+        .collect::<Vec<_>>();
+    println!("Injector completion order: {:?}", order);
+    assert_eq!(order, [5, 4, 3, 2, 1, 0]);
+    Ok(())
+}
+
+pub async fn test_consumer_rate_limit() -> Result<()> {
+    println!("\nTesting Consumer with a keyed rate limit of 5 items per second...");
+
+    let handler = TaskHandler::new();
+    let options = ConsumerOptions::new()
+        .with_threads(THREADS)
+        .with_rate_limit(RateLimit::per_second(5).with_burst(2));
+    let consumer = Consumer::<usize>::with_options(options);
+    consumer
+        .rate_limiter()
+        .set_key(|item| format!("host{}", item % 2));
+
+    for i in 1..=20 {
+        consumer.enqueue(i)?;
+    }
+
+    let now = Instant::now();
+    consumer.start(&handler)?;
+    consumer.complete();
+    consumer.wait_async().await?;
+    println!("Processed 20 items in {:?}", now.elapsed());
+    Ok(())
+}
+
+pub async fn test_consumer_stats() -> Result<()> {
+    println!("\nTesting Consumer with stats and a progress bar...");
+
+    let handler = SilentTaskHandler;
+    let options = ConsumerOptions::new().with_threads(THREADS);
+    let consumer = Consumer::<usize>::with_options(options);
+    let pb = ProgressBar::new(0).with_style(TaskStats::progress_style());
+    consumer.set_progress(pb);
+
+    for i in 1..=200 {
+        consumer.enqueue(i)?;
+    }
+
+    consumer.start(&handler)?;
+    consumer.complete();
+    consumer.wait_async().await?;
+
+    let stats = consumer.stats();
+    println!(
+        "Processed {} of {} items: {} succeeded, {} failed, {:.2} items/s, p50 {:?}, p99 {:?}",
+        stats.processed,
+        stats.enqueued,
+        stats.succeeded,
+        stats.failed,
+        stats.items_per_second,
+        stats.latency_p50,
+        stats.latency_p99
+    );
+    Ok(())
+}
+
+#[derive(Clone, Debug)]
+struct SilentTaskHandler;
+
+impl TaskDelegation<Consumer<usize>, usize> for SilentTaskHandler {
+    fn on_started(&self, _pc: &Consumer<usize>) {}
+
+    fn process(
+        &self,
+        _pc: &Consumer<usize>,
+        item: &usize,
+        _token: &CancellationToken,
+    ) -> Result<()> {
+        thread::sleep(Duration::from_millis(10 + (*item as u64 % 4) * 10));
+
+        if item % 7 == 0 {
+            return Err(RmxError::Argument(format!(
+                "Item {}. Multiples of 7 are not allowed",
+                item
+            )));
+        }
+
+        Ok(())
+    }
+
+    fn on_completed(&self, _pc: &Consumer<usize>, _item: &usize, _result: &TaskResult) -> bool {
+        true
+    }
+
+    fn on_cancelled(&self, _pc: &Consumer<usize>) {}
+
+    fn on_finished(&self, _pc: &Consumer<usize>) {}
+}
+
+pub async fn test_consumer_scaling() -> Result<()> {
+    println!("\nTesting Consumer with dynamic worker scaling...");
+
+    // The items take longer than the latency bound, but the backlog still has to win.
+    let options = ConsumerOptions::new().with_threads(1).with_autoscale(
+        AutoScale::new(1, 8)
+            .with_interval(Duration::from_millis(100))
+            .with_latency(Duration::from_millis(5)),
+    );
+    let consumer = Consumer::<usize>::with_options(options);
+
+    for i in 1..=300 {
+        consumer.enqueue(i)?;
+    }
+
+    consumer.start(&SilentTaskHandler)?;
+    consumer.complete();
+    let mut peak = consumer.threads();
+
+    while !consumer.is_finished() {
+        peak = peak.max(consumer.threads());
+        println!(
+            "Threads: {}, consumers: {}, pending: {}",
+            consumer.threads(),
+            consumer.consumers(),
+            consumer.len()
+        );
+        tokio::time::sleep(Duration::from_millis(250)).await;
+    }
+
+    let stats = consumer.stats();
+    println!(
+        "Processed {} of {} items in {:?} with up to {} threads",
+        stats.processed, stats.enqueued, stats.elapsed, peak
+    );
+    assert!(peak > 1);
+    Ok(())
+}
+
+#[derive(Clone, Debug)]
+struct SlowTaskHandler;
+
+impl TaskDelegation<Consumer<usize>, usize> for SlowTaskHandler {
+    fn on_started(&self, _pc: &Consumer<usize>) {}
+
+    fn process(
+        &self,
+        _pc: &Consumer<usize>,
+        item: &usize,
+        token: &CancellationToken,
+    ) -> Result<()> {
+        // Every fourth item takes far longer than the timeout.
+        let steps = if item % 4 == 0 { 50 } else { 2 };
+
+        for _ in 0..steps {
+            token.check()?;
+            thread::sleep(Duration::from_millis(10));
+        }
+
+        Ok(())
+    }
+
+    fn on_completed(&self, _pc: &Consumer<usize>, item: &usize, result: &TaskResult) -> bool {
+        println!("Result item: {}: {}", item, result);
+        true
+    }
+
+    fn on_cancelled(&self, _pc: &Consumer<usize>) {
+        println!("Cancelled.");
+    }
+
+    fn on_finished(&self, _pc: &Consumer<usize>) {
+        println!("Finished.");
+    }
+}
+
+#[derive(Clone, Debug)]
+struct StubbornTaskHandler;
+
+impl TaskDelegation<Consumer<usize>, usize> for StubbornTaskHandler {
+    fn on_started(&self, _pc: &Consumer<usize>) {}
+
+    fn process(
+        &self,
+        _pc: &Consumer<usize>,
+        _item: &usize,
+        _token: &CancellationToken,
+    ) -> Result<()> {
+        // Never looks at the token.
+        thread::sleep(Duration::from_millis(100));
+        Ok(())
+    }
+
+    fn on_completed(&self, _pc: &Consumer<usize>, _item: &usize, _result: &TaskResult) -> bool {
+        true
+    }
+
+    fn on_cancelled(&self, _pc: &Consumer<usize>) {}
+
+    fn on_finished(&self, _pc: &Consumer<usize>) {}
+}
+
+pub async fn test_consumer_timeout() -> Result<()> {
+    println!("\nTesting Consumer with per-item timeouts...");
+
+    let options = ConsumerOptions::new()
+        .with_threads(THREADS)
+        .with_item_timeout(Duration::from_millis(100));
+    let consumer = Consumer::<usize>::with_options(options);
+
+    for i in 1..=20 {
+        consumer.enqueue(i)?;
+    }
+
+    consumer.start(&SlowTaskHandler)?;
+    consumer.complete();
+    consumer.wait_async().await?;
+
+    let stats = consumer.stats();
+    println!(
+        "Processed {} items, {} failed or timed out",
+        stats.processed, stats.failed
+    );
+
+    // A handler that ignores its token and still returns Ok late is reported as timed out.
+    let options = ConsumerOptions::new()
+        .with_threads(1)
+        .with_item_timeout(Duration::from_millis(50))
+        .with_results(ResultsOrder::Completion);
+    let consumer = Consumer::<usize>::with_options(options);
+    consumer.enqueue(4)?;
+    consumer.start(&StubbornTaskHandler)?;
+    consumer.complete();
+    consumer.wait_async().await?;
+    let results = consumer.results().items();
+    println!("A late Ok was reported as {}", results[0].result);
+    assert!(matches!(results[0].result, TaskResult::TimedOut));
+    Ok(())
+}
+
+#[derive(Clone, Debug)]
+struct LabelTaskHandler(&'static str);
+
+impl TaskDelegation<ProducerConsumer<usize, String>, usize, String> for LabelTaskHandler {
+    fn on_started(&self, _pc: &ProducerConsumer<usize, String>) {}
+
+    fn process(
+        &self,
+        _pc: &ProducerConsumer<usize, String>,
+        item: &usize,
+        _token: &CancellationToken,
+    ) -> Result<String> {
+        Ok(format!("{}: {}", self.0, item))
+    }
+
+    fn on_completed(
+        &self,
+        _pc: &ProducerConsumer<usize, String>,
+        _item: &usize,
+        _result: &TaskResult<String>,
+    ) -> bool {
+        true
+    }
+
+    fn on_cancelled(&self, _pc: &ProducerConsumer<usize, String>) {}
+
+    fn on_finished(&self, _pc: &ProducerConsumer<usize, String>) {
+        println!("Stage {} finished", self.0);
+    }
+}
+
+#[derive(Clone, Debug, Default)]
+struct CollectTaskHandler {
+    items: Arc<Mutex<Vec<String>>>,
+}
+
+impl TaskDelegation<ProducerConsumer<String>, String> for CollectTaskHandler {
+    fn on_started(&self, _pc: &ProducerConsumer<String>) {}
+
+    fn process(
+        &self,
+        _pc: &ProducerConsumer<String>,
+        item: &String,
+        _token: &CancellationToken,
+    ) -> Result<()> {
+        self.items.lock().unwrap().push(item.clone());
+        Ok(())
+    }
+
+    fn on_completed(
+        &self,
+        _pc: &ProducerConsumer<String>,
+        _item: &String,
+        _result: &TaskResult,
+    ) -> bool {
+        true
+    }
+
+    fn on_cancelled(&self, _pc: &ProducerConsumer<String>) {}
+
+    fn on_finished(&self, _pc: &ProducerConsumer<String>) {
+        println!("Collect stage finished");
+    }
+}
+
+pub async fn test_pipeline() -> Result<()> {
+    println!("\nTesting a fan-out/fan-in Pipeline...");
+
+    let options = ProducerConsumerOptions::new()
+        .with_threads(2)
+        .with_capacity(8);
+    let pipeline = Pipeline::new();
+    assert!(pipeline.complete().is_err() && pipeline.wait_async().await.is_err());
+    let square = pipeline.stage(options.clone(), SquareTaskHandler);
+    let left = pipeline.stage(options.clone(), LabelTaskHandler("left"));
+    let right = pipeline.stage(options.clone(), LabelTaskHandler("right"));
+    let collector = CollectTaskHandler::default();
+    let collect = pipeline.stage(options, collector.clone());
+    square.connect(&left)?;
+    square.connect(&right)?;
+    left.connect(&collect)?;
+    right.connect(&collect)?;
+    pipeline.start()?;
+
+    for i in 1..=50 {
+        square.enqueue(i)?;
+    }
+
+    pipeline.complete()?;
+    pipeline.wait_async().await?;
+    println!(
+        "Collected {} items from {} stages, {} dropped",
+        collector.items.lock().unwrap().len(),
+        pipeline.len(),
+        pipeline.dropped()
+    );
+
+    // The only worker of the last stage stops after its first item, the first stage must not block
+    // on it forever, and waiting returns once the pipeline is cancelled.
+    let options = ProducerConsumerOptions::new()
+        .with_threads(1)
+        .with_capacity(1);
+    let pipeline = Pipeline::new();
+    let square = pipeline.stage(options.clone(), SquareTaskHandler);
+    let stop = pipeline.stage(options, StopTaskHandler);
+    square.connect(&stop)?;
+    pipeline.start()?;
+
+    for i in 1..=10 {
+        square.enqueue(i)?;
+    }
+
+    square.queue().complete();
+    square.queue().wait_async().await?;
+    println!("The first stage finished, {} dropped", pipeline.dropped());
+    pipeline.cancel();
+    assert!(pipeline.wait_async().await.is_err());
+    Ok(())
+}
+
+#[derive(Clone, Debug)]
+struct StopTaskHandler;
+
+impl TaskDelegation<ProducerConsumer<usize>, usize> for StopTaskHandler {
+    fn on_started(&self, _pc: &ProducerConsumer<usize>) {}
+
+    fn process(
+        &self,
+        _pc: &ProducerConsumer<usize>,
+        _item: &usize,
+        _token: &CancellationToken,
+    ) -> Result<()> {
+        Ok(())
+    }
+
+    fn on_completed(
+        &self,
+        _pc: &ProducerConsumer<usize>,
+        _item: &usize,
+        _result: &TaskResult,
+    ) -> bool {
+        false
+    }
+
+    fn on_cancelled(&self, _pc: &ProducerConsumer<usize>) {}
+
+    fn on_finished(&self, _pc: &ProducerConsumer<usize>) {}
+}
+
+pub async fn test_scheduler() -> Result<()> {
+    println!("\nTesting Scheduler with a test clock...");
+
+    let consumer = Consumer::<usize>::new();
+    consumer.start(&SilentTaskHandler)?;
+    let start = Utc.with_ymd_and_hms(2024, 1, 1, 0, 0, 0).unwrap();
+    let clock = TestClock::new(start);
+    let scheduler = Scheduler::with_clock(consumer.clone(), clock.clone());
+    scheduler.delay(1, Duration::from_secs(30))?;
+    scheduler.fixed_rate(2, Duration::from_secs(5 * 60))?;
+    scheduler.fixed_delay(3, Duration::from_secs(60))?;
+    scheduler.cron(4, "*/10 * * * *")?;
+
+    let mut dispatched = 0;
+
+    // Step through one hour a minute at a time.
+    for minute in 1..=60 {
+        clock.advance(Duration::from_secs(60));
+        let count = scheduler.tick()?;
+        dispatched += count;
+
+        if minute % 10 == 0 {
+            println!(
+                "{}: dispatched {} items so far, next run at {:?}",
+                clock.now().format("%H:%M"),
+                dispatched,
+                scheduler.next_run()
+            );
+        }
+    }
+
+    // 1 delayed + 12 fixed rate + 60 fixed delay + 6 cron runs
+    println!("Dispatched {} items, expected 79", dispatched);
+
+    // A clock jump over 6 runs dispatches a single item by default and at most 3 when catching up.
+    let coalesced = Scheduler::with_clock(consumer.clone(), clock.clone());
+    let capped = Scheduler::with_clock(consumer.clone(), clock.clone())
+        .with_missed_runs(MissedRuns::CatchUp(3));
+    coalesced.fixed_rate(5, Duration::from_secs(60))?;
+    capped.fixed_rate(6, Duration::from_secs(60))?;
+    clock.advance(Duration::from_secs(6 * 60));
+    assert_eq!(coalesced.tick()?, 1);
+    assert_eq!(capped.tick()?, 3);
+
+    // The runs missed during a pause are skipped on resume.
+    coalesced.pause();
+    clock.advance(Duration::from_secs(10 * 60));
+    coalesced.resume();
+    assert_eq!(coalesced.tick()?, 0);
+    clock.advance(Duration::from_secs(60));
+    assert_eq!(coalesced.tick()?, 1);
+    println!("Missed runs were coalesced, capped and skipped after a pause");
+
+    consumer.complete();
+    consumer.wait_async().await?;
+    Ok(())
+}
+
+pub async fn test_producer_consumer_stream() -> Result<()> {
+    println!("\nTesting Producer/Consumer stream and sink adapters...");
+
+    let options = ProducerConsumerOptions::new()
+        .with_capacity(4)
+        .with_threads(THREADS);
+    let prodcon = ProducerConsumer::<usize, usize>::with_options(options);
+    let outputs = prodcon.stream();
+    prodcon.start(&SquareTaskHandler)?;
+
+    // The bounded channel holds the source back until the consumers catch up.
+    let count = prodcon.enqueue_all(stream::iter(1..=50)).await?;
+    prodcon.complete();
+    let outputs = outputs.collect::<Vec<_>>().await;
+    let succeeded = outputs
+        .iter()
+        .filter(|it| matches!(it.result, TaskResult::Success(_)))
+        .count();
+    println!(
+        "Enqueued {} items, streamed {} results, {} succeeded",
+        count,
+        outputs.len(),
+        succeeded
+    );
+
+    let consumer = Consumer::<usize>::new();
+    consumer.start(&SilentTaskHandler)?;
+    // Forwarding closes the sink at the end, which completes the consumer.
+    stream::iter(1..=100)
+        .map(Ok)
+        .forward(consumer.sink())
+        .await?;
+    consumer.wait_async().await?;
+    println!("Sink processed {} items", consumer.stats().processed);
+    Ok(())
+}
+
+#[derive(Clone, Debug, Default)]
+struct KeyedTaskHandler {
+    active: Arc<Mutex<HashSet<usize>>>,
+    overlaps: Arc<AtomicUsize>,
+}
+
+impl TaskDelegation<Consumer<usize>, usize> for KeyedTaskHandler {
+    fn on_started(&self, _pc: &Consumer<usize>) {}
+
+    fn process(
+        &self,
+        _pc: &Consumer<usize>,
+        item: &usize,
+        _token: &CancellationToken,
+    ) -> Result<()> {
+        let account = item % 3;
+
+        if !self.active.lock().unwrap().insert(account) {
+            self.overlaps.fetch_add(1, Ordering::SeqCst);
+        }
+
+        thread::sleep(Duration::from_millis(5));
+        self.active.lock().unwrap().remove(&account);
+        Ok(())
+    }
+
+    fn on_completed(&self, _pc: &Consumer<usize>, _item: &usize, _result: &TaskResult) -> bool {
+        true
+    }
+
+    fn on_cancelled(&self, _pc: &Consumer<usize>) {}
+
+    fn on_finished(&self, _pc: &Consumer<usize>) {}
+}
+
+pub async fn test_consumer_keyed() -> Result<()> {
+    println!(
+        "\nTesting Consumer dedup and keyed serialization with {} threads...",
+        THREADS
+    );
+
+    let options = ConsumerOptions::new()
+        .with_threads(THREADS)
+        .with_dedup(Dedup::new(1000));
+    let consumer = Consumer::<usize>::with_options(options);
+    // Items of the same account must never run at the same time.
+    consumer.key_locks().set_key(|item| (item % 3).to_string());
+    let handler = KeyedTaskHandler::default();
+    consumer.start(&handler)?;
+
+    for _ in 0..2 {
+        for i in 0..100 {
+            consumer.enqueue(i)?;
+        }
+    }
+
+    consumer.complete();
+    consumer.wait_async().await?;
+    println!(
+        "Processed {} items, dropped {} duplicates, {} overlaps",
+        consumer.stats().processed,
+        consumer.deduplicator().duplicates(),
+        handler.overlaps.load(Ordering::SeqCst)
+    );
+    assert_eq!(handler.overlaps.load(Ordering::SeqCst), 0);
+
+    // Items of a hot key wait on the worker holding it instead of parking the other workers, and
+    // cancelling drops them.
+    let consumer = Consumer::<usize>::with_options(ConsumerOptions::new().with_threads(THREADS));
+    consumer.key_locks().set_key(|_| "hot".to_string());
+    consumer.start(&handler)?;
+
+    for i in 0..100 {
+        consumer.enqueue(i)?;
+    }
+
+    tokio::time::sleep(Duration::from_millis(50)).await;
+    println!(
+        "{} items are deferred to the hot key",
+        consumer.key_locks().deferred()
+    );
+    consumer.cancel();
+    consumer.wait_async().await?;
+    println!(
+        "Processed {} items of the hot key before cancelling",
+        consumer.stats().processed
+    );
+    assert_eq!(handler.overlaps.load(Ordering::SeqCst), 0);
+    Ok(())
+}
+
+#[derive(Clone, Debug, Default)]
+struct BatchTaskHandler {
+    batches: Arc<Mutex<Vec<usize>>>,
+    attempted: Arc<Mutex<HashSet<usize>>>,
+}
+
+impl BatchTaskDelegation<ProducerConsumer<usize, usize>, usize, usize> for BatchTaskHandler {
+    fn on_started(&self, _pc: &ProducerConsumer<usize, usize>) {
+        println!("Batch Producer/Consumer started");
+    }
+
+    fn process_batch(
+        &self,
+        _pc: &ProducerConsumer<usize, usize>,
+        items: &[usize],
+        _token: &CancellationToken,
+    ) -> Result<Vec<Result<usize>>> {
+        // One round trip for the whole batch, like a bulk insert.
+        thread::sleep(Duration::from_millis(20));
+        self.batches.lock().unwrap().push(items.len());
+        let mut attempted = self.attempted.lock().unwrap();
+        Ok(items
+            .iter()
+            .map(|item| {
+                if item % 10 == 0 {
+                    return Err(RmxError::Argument(format!("Item {} is ignored", item)));
+                }
+
+                // Every seventh row fails the first time and goes through when retried.
+                if item % 7 == 0 && attempted.insert(*item) {
+                    return Err(RmxError::Network(format!("Item {} timed out", item)));
+                }
+
+                Ok(item * item)
+            })
+            .collect())
+    }
+
+    fn on_completed(
+        &self,
+        _pc: &ProducerConsumer<usize, usize>,
+        _item: &usize,
+        _result: &TaskResult<usize>,
+    ) -> bool {
+        true
+    }
+
+    fn on_cancelled(&self, _pc: &ProducerConsumer<usize, usize>) {
+        println!("Cancelled.");
+    }
+
+    fn on_finished(&self, _pc: &ProducerConsumer<usize, usize>) {
+        println!("Finished.");
+    }
+}
+
+pub async fn test_producer_consumer_batch() -> Result<()> {
+    println!(
+        "\nTesting batch Producer/Consumer with {} threads...",
+        THREADS
+    );
+
+    let options = ProducerConsumerOptions::new()
+        .with_capacity(100)
+        .with_threads(THREADS)
+        .with_batch(Batch::new(10).with_linger(Duration::from_millis(50)))
+        .with_retry(
+            RetryPolicy::new(3)
+                .with_fixed(Duration::from_millis(10))
+                .with_retryable(|e| matches!(e, RmxError::Network(_))),
+        )
+        .with_results(ResultsOrder::Submission);
+    let prodcon = ProducerConsumer::<usize, usize>::with_options(options);
+    let handler = BatchTaskHandler::default();
+    prodcon.start_batch(&handler)?;
+
+    for i in 1..=200 {
+        prodcon.enqueue(i)?;
+    }
+
+    prodcon.complete();
+    prodcon.wait_async().await?;
+    let batches = handler.batches.lock().unwrap().clone();
+    let stats = prodcon.stats();
+    println!(
+        "Processed {} items in {} batches, {} succeeded, {} failed",
+        stats.processed,
+        batches.len(),
+        stats.succeeded,
+        stats.failed
+    );
+    println!("Batch sizes: {:?}", batches);
+    println!("Dead letters: {}", prodcon.dead_letters().len());
+    Ok(())
+}
+
+pub async fn test_shutdown() -> Result<()> {
+    println!("\nTesting graceful shutdown, press Ctrl+C to stop early...");
+
+    let consumer = Consumer::<usize>::with_options(ConsumerOptions::new().with_threads(THREADS));
+    let spinner = Spinner::new();
+    spinner.set_message("Processing items...");
+    let shutdown = Shutdown::with_grace(Duration::from_secs(5));
+    shutdown.register(consumer.clone());
+    shutdown.register_spinner(&spinner);
+    shutdown.listen()?;
+    consumer.start(&SilentTaskHandler)?;
+
+    for i in 1..=200 {
+        consumer.enqueue(i)?;
+    }
+
+    // Stands in for the first Ctrl+C when nobody presses it.
+    tokio::time::sleep(Duration::from_millis(200)).await;
+
+    if !shutdown.is_requested() {
+        shutdown.signal();
+    }
+
+    shutdown.wait_async().await;
+    println!(
+        "Shutdown after {} signal(s), processed {} items, cancelled: {}",
+        shutdown.signals(),
+        consumer.stats().processed,
+        consumer.is_cancelled()
+    );
+
+    // A queue that already finished must not hold the drain until the grace period runs out.
+    let squares = ProducerConsumer::<usize, usize>::with_options(
+        ProducerConsumerOptions::new().with_threads(THREADS),
+    );
+    squares.start(&SquareTaskHandler)?;
+
+    for i in 1..=5 {
+        squares.enqueue(i)?;
+    }
+
+    squares.complete();
+    squares.wait_async().await?;
+    let shutdown = Shutdown::with_grace(Duration::from_secs(5));
+    shutdown.register(squares.clone());
+    let now = Instant::now();
+    shutdown.signal();
+    shutdown.wait_async().await;
+    println!(
+        "Finished queue drained in {:?}, cancelled: {}",
+        now.elapsed(),
+        squares.is_cancelled()
+    );
+
+    // Aborting reports done only after the cancelled queue stopped its workers.
+    let slow = Consumer::<usize>::with_options(ConsumerOptions::new().with_threads(THREADS));
+    slow.start(&SlowTaskHandler)?;
+
+    for i in 1..=100 {
+        slow.enqueue(i)?;
+    }
+
+    let shutdown =
+        Shutdown::with_options(ShutdownOptions::new().with_grace(Duration::from_secs(5)));
+    shutdown.register(slow.clone());
+    shutdown.signal();
+    shutdown.signal();
+    shutdown.wait_async().await;
+    println!("Aborted, the queue is still started: {}", slow.is_started());
+    assert!(!slow.is_started() && slow.is_finished());
+    // The process only exits on a third signal when the options ask for it.
+    shutdown.signal();
+    assert_eq!(shutdown.signals(), 3);
+
+    // A queue that never started is not stopped yet, it could still start.
+    assert!(!ShutdownTarget::is_stopped(&Consumer::<usize>::new()));
+    Ok(())
+}
+
+pub async fn test_event() -> Result<()> {
+    println!("\nTesting events and waiting on finished queues...");
+
+    let event = Event::auto_reset();
+    let setter = event.clone();
+    thread::spawn(move || {
+        thread::sleep(Duration::from_millis(50));
+        setter.set();
+    });
+    event.wait_async().await;
+    // An auto-reset event releases a single waiter and resets itself.
+    assert!(!event.is_set());
+    assert!(!event.wait_timeout(Duration::from_millis(50)));
+    let waiters = (0..2)
+        .map(|_| {
+            let event = event.clone();
+            thread::spawn(move || event.wait_timeout(Duration::from_millis(300)))
+        })
+        .collect::<Vec<_>>();
+    thread::sleep(Duration::from_millis(50));
+    event.set();
+    let released = waiters
+        .into_iter()
+        .filter_map(|it| it.join().ok())
+        .filter(|released| *released)
+        .count();
+    assert_eq!(released, 1);
+    println!("Auto-reset event released one waiter and reset itself");
+
+    // A manual-reset event releases every waiter and stays set until reset.
+    let event = Event::manual_reset();
+    let waiters = (0..3)
+        .map(|_| {
+            let event = event.clone();
+            thread::spawn(move || event.wait_timeout(Duration::from_secs(5)))
+        })
+        .collect::<Vec<_>>();
+    thread::sleep(Duration::from_millis(50));
+    event.set();
+
+    for waiter in waiters {
+        assert!(waiter.join().unwrap());
+    }
+
+    assert!(event.is_set());
+    event.reset();
+    assert!(!event.wait_timeout(Duration::from_millis(50)));
+    // Notifying wakes the predicate waiters without setting the event.
+    event.notify();
+    assert!(!event.is_set());
+    println!("Manual-reset event released every waiter until reset");
+
+    // A wait registered after the event was set or the condition cleared returns right away.
+    let event = Event::manual_reset();
+    event.set();
+    assert!(event.wait_timeout_async(Duration::from_secs(1)).await);
+    assert!(
+        event
+            .wait_timeout_while_async(|| false, Duration::from_secs(1))
+            .await
+    );
+    println!("Late waiters did not miss the wake up");
+
+    let prodcon = ProducerConsumer::<usize, usize>::with_options(
+        ProducerConsumerOptions::new().with_threads(THREADS),
+    );
+    prodcon.start(&SquareTaskHandler)?;
+
+    for i in 1..=10 {
+        prodcon.enqueue(i)?;
+    }
+
+    prodcon.complete();
+    prodcon.wait_async().await?;
+    // Waiting again after the queue finished returns right away.
+    let again = tokio::time::timeout(Duration::from_secs(1), prodcon.wait_async()).await;
+    assert!(prodcon.is_finished() && again.is_ok());
+    println!(
+        "Finished: {}, second wait returned: {}",
+        prodcon.is_finished(),
+        again.is_ok()
+    );
+    Ok(())
+}
+
+pub async fn test_progress_group() -> Result<()> {
+    println!("\nTesting progress group...");
+
+    // Plain mode logs the bars as lines, which is what a CI log would show.
+    let group = ProgressGroup::plain(Duration::from_millis(100));
+    let spinner = group.add_spinner("Downloads ");
+    spinner.set_message("waiting for workers");
+
+    let handles = (1..=THREADS)
+        .map(|n| {
+            let pb = group.add_bar(20, format!("Worker {}", n));
+            let group = group.clone();
+            thread::spawn(move || {
+                for _ in 0..20 {
+                    thread::sleep(Duration::from_millis(10 * n as u64));
+                    pb.inc(1);
+                }
+
+                pb.finish_with_message("done");
+                group.println(format!("Worker {} finished", n)).ok();
+            })
+        })
+        .collect::<Vec<_>>();
+
+    for handle in handles {
+        handle.join().unwrap();
+    }
+
+    spinner.finish_with_message("all workers finished")?;
+    group.finish()?;
+    println!("Bars: {}", group.len());
+    Ok(())
+}
+
+pub async fn test_parallel() -> Result<()> {
+    println!("\nTesting parallel helpers...");
+
+    let dir = std::env::temp_dir().join("rustmix_parallel");
+    std::fs::create_dir_all(&dir)?;
+
+    for n in 1..=8 {
+        let lines = (1..=n * 100)
+            .map(|it| it.to_string())
+            .collect::<Vec<_>>()
+            .join("\n");
+        std::fs::write(dir.join(format!("numbers_{}.txt", n)), lines)?;
+    }
+
+    // Debug runs keep the pool on a single thread, set_debug only takes effect in debug builds.
+    if cfg!(debug_assertions) {
+        set_debug(true);
+        assert_eq!(ThreadPoolOptions::new().with_threads(4).threads(), 1);
+        set_debug(false);
+    }
+
+    // The default follows the cpus, an explicit count is kept even above it.
+    let cpus = ThreadPoolOptions::new().threads();
+    let options = ThreadPoolOptions::new().with_threads(cpus + 4);
+    assert_eq!(options.threads(), cpus + 4);
+    assert_eq!(options.build()?.current_num_threads(), cpus + 4);
+
+    let pool = ThreadPoolOptions::new().with_name("parallel").build()?;
+    println!("Pool threads: {}", pool.current_num_threads());
+
+    let pattern = dir.join("numbers_*.txt");
+    let files = par_process_files_with(&pool, pattern.to_string_lossy(), |file| {
+        let sum = par_map_lines(file, |line| line.parse::<u64>().unwrap_or_default())?
+            .into_iter()
+            .sum::<u64>();
+        Ok(sum)
+    })?;
+
+    for (file, sum) in &files {
+        println!("{}: {:?}", file.display(), sum);
+    }
+
+    // The invalid byte does not fail the file, its line is still mapped.
+    let file = dir.join("mixed.txt");
+    std::fs::write(&file, b"1\n2\n\xff3\n4\n5\n")?;
+    let mut total = 0;
+    let batches = par_map_lines_batch(
+        &file,
+        2,
+        |line| line.parse::<u64>().ok(),
+        |batch, mapped| {
+            println!("Batch {}: {:?}", batch, mapped);
+            total += mapped.into_iter().flatten().sum::<u64>();
+            true
+        },
+    )?;
+    println!("{} batches, total {}", batches, total);
+
+    std::fs::remove_dir_all(&dir)?;
+    Ok(())
+}
+
+#[derive(Clone, Debug, Default)]
+struct CountTaskHandler {
+    processed: Arc<AtomicUsize>,
+    threads: Arc<Mutex<HashSet<thread::ThreadId>>>,
+}
+
+impl CountTaskHandler {
+    fn processed(&self) -> usize {
+        self.processed.load(Ordering::SeqCst)
+    }
+
+    fn threads(&self) -> usize {
+        self.threads.lock().unwrap().len()
+    }
+
+    fn count(&self) {
+        self.processed.fetch_add(1, Ordering::SeqCst);
+        self.threads.lock().unwrap().insert(thread::current().id());
+    }
+}
+
+impl TaskDelegation<Consumer<usize>, usize> for CountTaskHandler {
+    fn on_started(&self, _pc: &Consumer<usize>) {}
+
+    fn process(
+        &self,
+        _pc: &Consumer<usize>,
+        _item: &usize,
+        _token: &CancellationToken,
+    ) -> Result<()> {
+        self.count();
+        Ok(())
+    }
+
+    fn on_completed(&self, _pc: &Consumer<usize>, _item: &usize, _result: &TaskResult) -> bool {
+        true
+    }
+
+    fn on_cancelled(&self, _pc: &Consumer<usize>) {}
+
+    fn on_finished(&self, _pc: &Consumer<usize>) {}
+}
+
+impl TaskDelegation<InjectorWorker<usize>, usize> for CountTaskHandler {
+    fn on_started(&self, _pc: &InjectorWorker<usize>) {}
+
+    fn process(
+        &self,
+        _pc: &InjectorWorker<usize>,
+        _item: &usize,
+        _token: &CancellationToken,
+    ) -> Result<()> {
+        self.count();
+        Ok(())
+    }
+
+    fn on_completed(
+        &self,
+        _pc: &InjectorWorker<usize>,
+        _item: &usize,
+        _result: &TaskResult,
+    ) -> bool {
+        true
+    }
+
+    fn on_cancelled(&self, _pc: &InjectorWorker<usize>) {}
+
+    fn on_finished(&self, _pc: &InjectorWorker<usize>) {}
+}
+
+fn print_throughput(name: &str, processed: usize, elapsed: Duration) -> f64 {
+    let throughput = processed as f64 / elapsed.as_secs_f64();
+    println!(
+        "{}: {} items in {:?} ({:.0} items/s)",
+        name, processed, elapsed, throughput
+    );
+    throughput
+}
+
+pub async fn test_injector_worker_benchmark() -> Result<()> {
+    const BENCH_THREADS: usize = 8;
+    const BENCH_SIZE: usize = 100000;
+
+    println!(
+        "\nComparing Consumer and Injector/Worker throughput with {} threads...",
+        BENCH_THREADS
+    );
+
+    let handler = CountTaskHandler::default();
+    let consumer =
+        Consumer::<usize>::with_options(ConsumerOptions::new().with_threads(BENCH_THREADS));
+    let now = Instant::now();
+    consumer.start(&handler)?;
+
+    for i in 0..BENCH_SIZE {
+        consumer.enqueue(i)?;
+    }
+
+    consumer.complete();
+    consumer.wait_async().await?;
+    assert_eq!(handler.processed(), BENCH_SIZE);
+    // The Consumer's shared SegQueue is the baseline the work stealing is measured against.
+    let baseline = print_throughput("Consumer", handler.processed(), now.elapsed());
+    let mut best = 0.0f64;
+
+    for steal_batch in [1, 10, 64] {
+        let handler = CountTaskHandler::default();
+        let options = InjectorWorkerOptions::new()
+            .with_threads(BENCH_THREADS)
+            .with_steal_batch(steal_batch);
+        let injwork = InjectorWorker::<usize>::with_options(options);
+        let now = Instant::now();
+        injwork.start(&handler)?;
+
+        for i in 0..BENCH_SIZE {
+            injwork.enqueue(i)?;
+        }
+
+        injwork.complete();
+        injwork.wait_async().await?;
+        assert_eq!(handler.processed(), BENCH_SIZE);
+        let throughput = print_throughput(
+            &format!("Injector/Worker, steal batch {}", steal_batch),
+            handler.processed(),
+            now.elapsed(),
+        );
+        println!("  {:.2}x the Consumer baseline", throughput / baseline);
+        best = best.max(throughput);
+    }
+
+    // Timings are noisy, so this only catches the stealing falling far behind the baseline.
+    assert!(
+        best >= baseline / 2.0,
+        "Injector/Worker at {:.0} items/s, Consumer at {:.0} items/s",
+        best,
+        baseline
+    );
+
+    // Items pinned to one worker stay on its thread.
+    let handler = CountTaskHandler::default();
+    let injwork = InjectorWorker::<usize>::with_options(
+        InjectorWorkerOptions::new().with_threads(BENCH_THREADS),
+    );
+    injwork.start(&handler)?;
+
+    for i in 0..1000 {
+        injwork.enqueue_pinned(i, 0)?;
+    }
+
+    injwork.complete();
+    injwork.wait_async().await?;
+    println!(
+        "Pinned {} items to worker 0, they ran on {} thread(s)",
+        handler.processed(),
+        handler.threads()
+    );
+    assert_eq!(handler.processed(), 1000);
+    Ok(())
+}
+
+pub async fn test_queue_events() -> Result<()> {
+    println!("\nTesting queue lifecycle events...");
+
+    let handler = CountTaskHandler::default();
+    let consumer = Consumer::<usize>::with_options(ConsumerOptions::new().with_threads(THREADS));
+
+    // Two independent subscribers, one counting the events and one logging the slow items.
+    let mut counter = consumer.subscribe();
+    let counts = tokio::spawn(async move {
+        let mut counts = std::collections::BTreeMap::new();
+
+        while let Ok(event) = counter.recv().await {
+            let name = match event {
+                QueueEvent::Started => "started",
+                QueueEvent::Enqueued { .. } => "enqueued",
+                QueueEvent::ItemStarted { .. } => "item started",
+                QueueEvent::ItemCompleted { .. } => "item completed",
+                QueueEvent::Paused => "paused",
+                QueueEvent::Resumed => "resumed",
+                QueueEvent::Completed => "completed",
+                QueueEvent::Cancelled => "cancelled",
+                QueueEvent::Finished => "finished",
+            };
+            *counts.entry(name).or_insert(0) += 1;
+
+            if event == QueueEvent::Finished {
+                break;
+            }
+        }
+
+        counts
+    });
+    let mut logger = consumer.subscribe();
+    let logged = tokio::spawn(async move {
+        let mut total = Duration::ZERO;
+
+        loop {
+            match logger.recv().await {
+                Ok(QueueEvent::ItemCompleted { elapsed, .. }) => total += elapsed,
+                Ok(QueueEvent::Finished) => break,
+                Ok(_) => {}
+                Err(e) => {
+                    println!("Logger stopped: {}", e);
+                    break;
+                }
+            }
+        }
+
+        total
+    });
+
+    consumer.start(&handler)?;
+    consumer.pause();
+
+    for i in 1..=100 {
+        consumer.enqueue(i)?;
+    }
+
+    consumer.resume();
+    consumer.complete();
+    consumer.wait_async().await?;
+    println!(
+        "Events: {:?}",
+        counts
+            .await
+            .map_err(|e| RmxError::InvalidOperation(e.to_string()))?
+    );
+    println!(
+        "Time spent in items: {:?}",
+        logged
+            .await
+            .map_err(|e| RmxError::InvalidOperation(e.to_string()))?
+    );
+    Ok(())
+}